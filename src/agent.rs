@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anoncreds::types::PresentationRequest;
+use axum::Router;
+use axum::extract::{Json, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::utils::*;
+
+// One issuer, holder and verifier sharing a workspace, as the CLI would run them
+struct Agent {
+    workspace: Workspace,
+    fixtures: BTreeMap<String, CredentialFixture>,
+}
+
+impl Agent {
+    fn fixture(&self, credential_type: &str) -> Result<&CredentialFixture, TestError> {
+        self.fixtures
+            .get(credential_type)
+            .ok_or_else(|| TestError(format!("No fixture for credential type {credential_type}")))
+    }
+}
+
+// Requests run one at a time, each loading and saving the workspace like a CLI command
+type AgentState = Arc<Mutex<Agent>>;
+
+struct AgentError(StatusCode, String);
+
+impl From<TestError> for AgentError {
+    fn from(e: TestError) -> Self {
+        AgentError(StatusCode::UNPROCESSABLE_ENTITY, e.0)
+    }
+}
+
+impl IntoResponse for AgentError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+// Wallet operations are blocking, and some of them panic on invalid input
+async fn run<T: Send + 'static>(
+    state: AgentState,
+    action: impl FnOnce(&Agent) -> Result<T, TestError> + Send + 'static,
+) -> Result<Json<T>, AgentError> {
+    tokio::task::spawn_blocking(move || {
+        let agent = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        action(&agent)
+    })
+    .await
+    .map_err(|e| AgentError(StatusCode::INTERNAL_SERVER_ERROR, format!("Agent task failed: {e}")))?
+    .map(Json)
+    .map_err(AgentError::from)
+}
+
+fn default_credential_format() -> CredentialFormat {
+    CredentialFormat::W3C
+}
+
+fn default_presentation_format() -> PresentationFormat {
+    PresentationFormat::W3C
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaBody {
+    #[serde(rename = "type")]
+    credential_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CredDefBody {
+    #[serde(rename = "type")]
+    credential_type: String,
+    #[serde(default)]
+    revocable: bool,
+    #[serde(default)]
+    midnight: bool,
+    time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OfferBody {
+    #[serde(rename = "type")]
+    credential_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestBody {
+    offer: OfferMessage,
+    link_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssueBody {
+    #[serde(rename = "type")]
+    credential_type: String,
+    request: RequestMessage,
+    values: Option<BTreeMap<String, String>>,
+    rev_idx: Option<u32>,
+    #[serde(default = "default_credential_format")]
+    format: CredentialFormat,
+    time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoreBody {
+    id: String,
+    credential: CredentialMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportLinkSecretsBody {
+    #[serde(default)]
+    ids: Vec<String>,
+    passphrase: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportLinkSecretsBody {
+    bundle: SealedVault,
+    passphrase: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeBody {
+    #[serde(rename = "type")]
+    credential_type: String,
+    indices: BTreeSet<u32>,
+    time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProofRequestBody {
+    spec: RequestSpec,
+    time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PresentBody {
+    request: PresentationRequest,
+    #[serde(default = "default_presentation_format")]
+    format: PresentationFormat,
+    #[serde(default)]
+    self_attested: HashMap<String, String>,
+    #[serde(default)]
+    unrevealed: BTreeSet<String>,
+    time: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerifyBody {
+    request: PresentationRequest,
+    presentation: PresentationBundle,
+    time: Option<u64>,
+}
+
+async fn create_schema(
+    State(state): State<AgentState>,
+    Json(body): Json<SchemaBody>,
+) -> Result<Json<Value>, AgentError> {
+    run(state, move |agent| {
+        let schema_id = agent.workspace.publish_schema(agent.fixture(&body.credential_type)?)?;
+        Ok(json!({ "schema_id": schema_id }))
+    })
+    .await
+}
+
+async fn create_cred_def(
+    State(state): State<AgentState>,
+    Json(body): Json<CredDefBody>,
+) -> Result<Json<Value>, AgentError> {
+    run(state, move |agent| {
+        let cred_def_id = agent.workspace.publish_cred_def(
+            agent.fixture(&body.credential_type)?,
+            body.revocable,
+            body.midnight,
+            body.time.unwrap_or_else(unix_now),
+        )?;
+        Ok(json!({ "cred_def_id": cred_def_id }))
+    })
+    .await
+}
+
+async fn offer(State(state): State<AgentState>, Json(body): Json<OfferBody>) -> Result<Json<OfferMessage>, AgentError> {
+    run(state, move |agent| {
+        agent.workspace.offer(agent.fixture(&body.credential_type)?)
+    })
+    .await
+}
+
+async fn issue(
+    State(state): State<AgentState>,
+    Json(body): Json<IssueBody>,
+) -> Result<Json<CredentialMessage>, AgentError> {
+    run(state, move |agent| {
+        let options = IssueOptions {
+            values: body.values,
+            rev_idx: body.rev_idx,
+            format: body.format,
+            time: body.time.unwrap_or_else(unix_now),
+        };
+        agent
+            .workspace
+            .issue(agent.fixture(&body.credential_type)?, body.request, &options)
+    })
+    .await
+}
+
+async fn revoke(State(state): State<AgentState>, Json(body): Json<RevokeBody>) -> Result<Json<Value>, AgentError> {
+    run(state, move |agent| {
+        agent.workspace.revoke(
+            agent.fixture(&body.credential_type)?,
+            &body.indices,
+            body.time.unwrap_or_else(unix_now),
+        )?;
+        Ok(json!({ "revoked": body.indices }))
+    })
+    .await
+}
+
+async fn request(
+    State(state): State<AgentState>,
+    Json(body): Json<RequestBody>,
+) -> Result<Json<RequestMessage>, AgentError> {
+    run(state, move |agent| {
+        agent.workspace.request(body.offer, body.link_secret.as_deref())
+    })
+    .await
+}
+
+async fn store(State(state): State<AgentState>, Json(body): Json<StoreBody>) -> Result<Json<Value>, AgentError> {
+    run(state, move |agent| {
+        agent.workspace.store(body.credential, &body.id)?;
+        Ok(json!({ "id": body.id }))
+    })
+    .await
+}
+
+async fn export_link_secrets(
+    State(state): State<AgentState>,
+    Json(body): Json<ExportLinkSecretsBody>,
+) -> Result<Json<SealedVault>, AgentError> {
+    run(state, move |agent| {
+        let ids: Vec<&str> = body.ids.iter().map(String::as_str).collect();
+        agent.workspace.export_link_secrets(&body.passphrase, &ids)
+    })
+    .await
+}
+
+async fn import_link_secrets(
+    State(state): State<AgentState>,
+    Json(body): Json<ImportLinkSecretsBody>,
+) -> Result<Json<Value>, AgentError> {
+    run(state, move |agent| {
+        let imported = agent.workspace.import_link_secrets(&body.passphrase, &body.bundle)?;
+        Ok(json!({ "imported": imported }))
+    })
+    .await
+}
+
+async fn present(
+    State(state): State<AgentState>,
+    Json(body): Json<PresentBody>,
+) -> Result<Json<PresentationBundle>, AgentError> {
+    run(state, move |agent| {
+        let choices = HolderChoices {
+            self_attested: body.self_attested,
+            unrevealed: body.unrevealed,
+        };
+        agent.workspace.present(
+            &body.request,
+            &body.format,
+            &choices,
+            body.time.unwrap_or_else(unix_now),
+        )
+    })
+    .await
+}
+
+async fn proof_request(
+    State(state): State<AgentState>,
+    Json(body): Json<ProofRequestBody>,
+) -> Result<Json<PresentationRequest>, AgentError> {
+    run(state, move |agent| {
+        agent
+            .workspace
+            .proof_request(&body.spec, body.time.unwrap_or_else(unix_now))
+    })
+    .await
+}
+
+// Answers 200 with the report whether or not the presentation was accepted
+async fn verify(
+    State(state): State<AgentState>,
+    Json(body): Json<VerifyBody>,
+) -> Result<Json<VerifyOutcome>, AgentError> {
+    run(state, move |agent| {
+        agent
+            .workspace
+            .verify(&body.request, &body.presentation, body.time.unwrap_or_else(unix_now))
+    })
+    .await
+}
+
+fn router(state: AgentState) -> Router {
+    Router::new()
+        .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+        .route("/issuer/schemas", post(create_schema))
+        .route("/issuer/cred-defs", post(create_cred_def))
+        .route("/issuer/offers", post(offer))
+        .route("/issuer/credentials", post(issue))
+        .route("/issuer/revocations", post(revoke))
+        .route("/holder/requests", post(request))
+        .route("/holder/credentials", post(store))
+        .route("/holder/link-secrets/export", post(export_link_secrets))
+        .route("/holder/link-secrets/import", post(import_link_secrets))
+        .route("/holder/presentations", post(present))
+        .route("/verifier/presentation-requests", post(proof_request))
+        .route("/verifier/verifications", post(verify))
+        .with_state(state)
+}
+
+// Only ever binds the loopback interface
+pub fn serve(workspace: Workspace, fixtures_dir: &Path, port: u16) -> anyhow::Result<()> {
+    let fixtures = load_fixtures(fixtures_dir).map_err(|e| anyhow::anyhow!(e.0))?;
+    let state = Arc::new(Mutex::new(Agent { workspace, fixtures }));
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+
+    tokio::runtime::Runtime::new()?.block_on(async move {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!(%addr, "agent listening");
+        axum::serve(listener, router(state)).await?;
+        Ok(())
+    })
+}
@@ -0,0 +1,487 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anoncreds::types::PresentationRequest;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::utils::*;
+
+/// Issue, hold and verify anoncreds credentials, keeping every party's state in files
+#[derive(Debug, Parser)]
+pub struct Cli {
+    /// Wallets and issuance sessions of the party running the command
+    #[arg(long, global = true, default_value = "wallet")]
+    wallet_dir: PathBuf,
+    /// Ledger shared by all parties
+    #[arg(long, global = true, default_value = "ledger")]
+    ledger_dir: PathBuf,
+    /// Encrypts the issuer and holder wallets, required by their commands
+    #[arg(long, global = true, env = "WALLET_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    /// Credential type definitions
+    #[arg(long, global = true, default_value = FIXTURES_DIR)]
+    fixtures: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run scenario files, or every scenario in `scenarios/`
+    Scenario { files: Vec<PathBuf> },
+    /// Publish the schema of a credential type
+    Schema {
+        #[command(subcommand)]
+        command: SchemaCommand,
+    },
+    /// Publish the cred def of a credential type, and its revocation registry
+    Creddef {
+        #[command(subcommand)]
+        command: CredDefCommand,
+    },
+    /// Offer a credential to a holder (issuer)
+    Offer {
+        #[arg(long = "type")]
+        credential_type: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Answer an offer with a credential request (holder)
+    Request {
+        #[arg(long)]
+        offer: PathBuf,
+        /// The wallet's default link secret when absent
+        #[arg(long)]
+        link_secret: Option<String>,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Issue the credential for a request (issuer)
+    Issue {
+        #[arg(long = "type")]
+        credential_type: String,
+        #[arg(long)]
+        request: PathBuf,
+        /// JSON object of attribute values, the fixture's sample values when absent
+        #[arg(long)]
+        values: Option<PathBuf>,
+        /// Revocation registry index, required for revocable credential types
+        #[arg(long)]
+        rev_idx: Option<u32>,
+        #[arg(long, value_enum, default_value_t = Format::W3c)]
+        format: Format,
+        #[arg(long)]
+        time: Option<u64>,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Validate and store an issued credential (holder)
+    Store {
+        #[arg(long)]
+        credential: PathBuf,
+        #[arg(long)]
+        id: String,
+    },
+    /// Back up or restore the holder's link secrets (holder)
+    LinkSecret {
+        #[command(subcommand)]
+        command: LinkSecretCommand,
+    },
+    /// Revoke credentials by revocation registry index (issuer)
+    Revoke {
+        #[arg(long = "type")]
+        credential_type: String,
+        #[arg(long = "index", required = true)]
+        indices: Vec<u32>,
+        #[arg(long)]
+        time: Option<u64>,
+    },
+    /// Build a presentation request from a request spec (verifier)
+    ProofRequest {
+        #[arg(long)]
+        spec: PathBuf,
+        #[arg(long)]
+        time: Option<u64>,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Answer a presentation request with a presentation bundle (holder)
+    Present {
+        #[arg(long)]
+        request: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::W3c)]
+        format: Format,
+        /// JSON object of referent: self-attested value
+        #[arg(long)]
+        self_attested: Option<PathBuf>,
+        /// Referent to prove without revealing its value, can be repeated
+        #[arg(long)]
+        unrevealed: Vec<String>,
+        #[arg(long)]
+        time: Option<u64>,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Serve the issuer, holder and verifier endpoints over HTTP on localhost
+    #[cfg(feature = "agent")]
+    Agent {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+    },
+    /// Verify a presentation and print the report, fails unless it is accepted (verifier)
+    Verify {
+        #[arg(long)]
+        request: PathBuf,
+        #[arg(long)]
+        presentation: PathBuf,
+        /// Replaces the verifier's trust policy
+        #[arg(long)]
+        policy: Option<PathBuf>,
+        #[arg(long)]
+        time: Option<u64>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SchemaCommand {
+    Create {
+        #[arg(long = "type")]
+        credential_type: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CredDefCommand {
+    Create {
+        #[arg(long = "type")]
+        credential_type: String,
+        #[arg(long)]
+        revocable: bool,
+        /// Also deploy a Midnight registry for the revocation registry
+        #[arg(long, requires = "revocable")]
+        midnight: bool,
+        #[arg(long)]
+        time: Option<u64>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum LinkSecretCommand {
+    /// Write link secrets to an encrypted bundle
+    Export {
+        /// Link secret to export, can be repeated, all of them when absent
+        #[arg(long = "id")]
+        ids: Vec<String>,
+        /// Encrypts the bundle
+        #[arg(long, env = "LINK_SECRET_PASSPHRASE", hide_env_values = true)]
+        bundle_passphrase: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Add the link secrets of an encrypted bundle to the wallet
+    Import {
+        #[arg(long)]
+        bundle: PathBuf,
+        #[arg(long, env = "LINK_SECRET_PASSPHRASE", hide_env_values = true)]
+        bundle_passphrase: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Legacy,
+    W3c,
+}
+
+impl From<Format> for CredentialFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Legacy => CredentialFormat::Legacy,
+            Format::W3c => CredentialFormat::W3C,
+        }
+    }
+}
+
+impl From<Format> for PresentationFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Legacy => PresentationFormat::Legacy,
+            Format::W3c => PresentationFormat::W3C,
+        }
+    }
+}
+
+fn fail(e: TestError) -> anyhow::Error {
+    anyhow::anyhow!(e.0)
+}
+
+impl Cli {
+    pub fn run(self) -> anyhow::Result<()> {
+        match &self.command {
+            Command::Scenario { files } => run_scenarios(files),
+            Command::Schema {
+                command: SchemaCommand::Create { credential_type },
+            } => self.create_schema(credential_type),
+            Command::Creddef {
+                command:
+                    CredDefCommand::Create {
+                        credential_type,
+                        revocable,
+                        midnight,
+                        time,
+                    },
+            } => self.create_cred_def(credential_type, *revocable, *midnight, time.unwrap_or_else(unix_now)),
+            Command::Offer { credential_type, out } => self.offer(credential_type, out),
+            Command::Request {
+                offer,
+                link_secret,
+                out,
+            } => self.request(offer, link_secret.as_deref(), out),
+            Command::Issue {
+                credential_type,
+                request,
+                values,
+                rev_idx,
+                format,
+                time,
+                out,
+            } => self.issue(
+                credential_type,
+                request,
+                values.as_deref(),
+                *rev_idx,
+                *format,
+                time.unwrap_or_else(unix_now),
+                out,
+            ),
+            Command::Store { credential, id } => self.store(credential, id),
+            Command::LinkSecret {
+                command:
+                    LinkSecretCommand::Export {
+                        ids,
+                        bundle_passphrase,
+                        out,
+                    },
+            } => self.export_link_secrets(ids, bundle_passphrase, out),
+            Command::LinkSecret {
+                command:
+                    LinkSecretCommand::Import {
+                        bundle,
+                        bundle_passphrase,
+                    },
+            } => self.import_link_secrets(bundle, bundle_passphrase),
+            Command::Revoke {
+                credential_type,
+                indices,
+                time,
+            } => self.revoke(credential_type, indices, time.unwrap_or_else(unix_now)),
+            Command::ProofRequest { spec, time, out } => self.proof_request(spec, time.unwrap_or_else(unix_now), out),
+            Command::Present {
+                request,
+                format,
+                self_attested,
+                unrevealed,
+                time,
+                out,
+            } => self.present(
+                request,
+                *format,
+                self_attested.as_deref(),
+                unrevealed,
+                time.unwrap_or_else(unix_now),
+                out,
+            ),
+            #[cfg(feature = "agent")]
+            Command::Agent { port } => {
+                // The agent runs the issuer and the holder, so it does not start without their passphrase
+                let workspace = self.workspace()?;
+                workspace.passphrase().map_err(fail)?;
+                crate::agent::serve(workspace, &self.fixtures, *port)
+            }
+            Command::Verify {
+                request,
+                presentation,
+                policy,
+                time,
+            } => self.verify(request, presentation, policy.as_deref(), time.unwrap_or_else(unix_now)),
+        }
+    }
+
+    fn workspace(&self) -> anyhow::Result<Workspace> {
+        Workspace::new(&self.wallet_dir, &self.ledger_dir, self.passphrase.as_deref()).map_err(fail)
+    }
+
+    fn fixture(&self, credential_type: &str) -> anyhow::Result<CredentialFixture> {
+        load_fixtures(&self.fixtures)
+            .map_err(fail)?
+            .remove(credential_type)
+            .ok_or_else(|| anyhow::anyhow!("No fixture for credential type {credential_type}"))
+    }
+
+    fn create_schema(&self, credential_type: &str) -> anyhow::Result<()> {
+        let schema_id = self
+            .workspace()?
+            .publish_schema(&self.fixture(credential_type)?)
+            .map_err(fail)?;
+        println!("{schema_id}");
+        Ok(())
+    }
+
+    fn create_cred_def(&self, credential_type: &str, revocable: bool, midnight: bool, time: u64) -> anyhow::Result<()> {
+        let cred_def_id = self
+            .workspace()?
+            .publish_cred_def(&self.fixture(credential_type)?, revocable, midnight, time)
+            .map_err(fail)?;
+        println!("{cred_def_id}");
+        Ok(())
+    }
+
+    fn offer(&self, credential_type: &str, out: &Path) -> anyhow::Result<()> {
+        let message = self.workspace()?.offer(&self.fixture(credential_type)?).map_err(fail)?;
+        write_json(out, &message).map_err(fail)
+    }
+
+    fn request(&self, offer: &Path, link_secret: Option<&str>, out: &Path) -> anyhow::Result<()> {
+        let offer = read_json(offer).map_err(fail)?;
+        let message = self.workspace()?.request(offer, link_secret).map_err(fail)?;
+        write_json(out, &message).map_err(fail)
+    }
+
+    fn issue(
+        &self,
+        credential_type: &str,
+        request: &Path,
+        values: Option<&Path>,
+        rev_idx: Option<u32>,
+        format: Format,
+        time: u64,
+        out: &Path,
+    ) -> anyhow::Result<()> {
+        let request = read_json(request).map_err(fail)?;
+        let options = IssueOptions {
+            values: values.map(read_json).transpose().map_err(fail)?,
+            rev_idx,
+            format: format.into(),
+            time,
+        };
+        let message = self
+            .workspace()?
+            .issue(&self.fixture(credential_type)?, request, &options)
+            .map_err(fail)?;
+        write_json(out, &message).map_err(fail)
+    }
+
+    fn store(&self, credential: &Path, credential_id: &str) -> anyhow::Result<()> {
+        let credential = read_json(credential).map_err(fail)?;
+        self.workspace()?.store(credential, credential_id).map_err(fail)
+    }
+
+    fn export_link_secrets(&self, ids: &[String], bundle_passphrase: &str, out: &Path) -> anyhow::Result<()> {
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let bundle = self
+            .workspace()?
+            .export_link_secrets(bundle_passphrase, &ids)
+            .map_err(fail)?;
+        write_json(out, &bundle).map_err(fail)
+    }
+
+    fn import_link_secrets(&self, bundle: &Path, bundle_passphrase: &str) -> anyhow::Result<()> {
+        let bundle: SealedVault = read_json(bundle).map_err(fail)?;
+        let imported = self
+            .workspace()?
+            .import_link_secrets(bundle_passphrase, &bundle)
+            .map_err(fail)?;
+        for id in imported {
+            println!("{id}");
+        }
+        Ok(())
+    }
+
+    fn revoke(&self, credential_type: &str, indices: &[u32], time: u64) -> anyhow::Result<()> {
+        let indices: BTreeSet<u32> = indices.iter().copied().collect();
+        self.workspace()?
+            .revoke(&self.fixture(credential_type)?, &indices, time)
+            .map_err(fail)
+    }
+
+    fn proof_request(&self, spec: &Path, time: u64, out: &Path) -> anyhow::Result<()> {
+        let spec: RequestSpec = read_json(spec).map_err(fail)?;
+        let pres_request = self.workspace()?.proof_request(&spec, time).map_err(fail)?;
+        write_json(out, &pres_request).map_err(fail)
+    }
+
+    fn present(
+        &self,
+        request: &Path,
+        format: Format,
+        self_attested: Option<&Path>,
+        unrevealed: &[String],
+        time: u64,
+        out: &Path,
+    ) -> anyhow::Result<()> {
+        let pres_request: PresentationRequest = read_json(request).map_err(fail)?;
+        let choices = HolderChoices {
+            self_attested: match self_attested {
+                Some(path) => read_json(path).map_err(fail)?,
+                None => HashMap::new(),
+            },
+            unrevealed: unrevealed.iter().cloned().collect(),
+        };
+        let bundle = self
+            .workspace()?
+            .present(&pres_request, &format.into(), &choices, time)
+            .map_err(fail)?;
+        write_json(out, &bundle).map_err(fail)
+    }
+
+    fn verify(&self, request: &Path, presentation: &Path, policy: Option<&Path>, time: u64) -> anyhow::Result<()> {
+        let workspace = self.workspace()?;
+        if let Some(policy) = policy {
+            workspace
+                .set_trust_policy(TrustPolicy::load(policy).map_err(fail)?)
+                .map_err(fail)?;
+        }
+        let pres_request: PresentationRequest = read_json(request).map_err(fail)?;
+        let bundle: PresentationBundle = read_json(presentation).map_err(fail)?;
+
+        let outcome = workspace.verify(&pres_request, &bundle, time).map_err(fail)?;
+        println!("{}", serde_json::to_string_pretty(&outcome)?);
+        anyhow::ensure!(outcome.report.accepted(), "presentation was not accepted");
+        Ok(())
+    }
+}
+
+fn run_scenarios(files: &[PathBuf]) -> anyhow::Result<()> {
+    let scenarios = if files.is_empty() {
+        load_scenarios(Path::new(SCENARIOS_DIR))
+    } else {
+        files.iter().map(|path| Scenario::load(path)).collect()
+    }
+    .map_err(fail)?;
+
+    let mut failed = vec![];
+    for scenario in scenarios.iter() {
+        match scenario.run() {
+            Ok(report) if report.passed() => tracing::info!(scenario = %scenario.name, "scenario passed"),
+            Ok(report) => {
+                for step in report.steps.iter().filter(|step| !step.mismatches.is_empty()) {
+                    tracing::error!(
+                        scenario = %scenario.name,
+                        step = step.index,
+                        action = step.action,
+                        mismatches = ?step.mismatches,
+                        "unexpected outcome"
+                    );
+                }
+                failed.push(scenario.name.clone());
+            }
+            Err(e) => {
+                tracing::error!(scenario = %scenario.name, error = %e.0, "scenario could not run");
+                failed.push(scenario.name.clone());
+            }
+        }
+    }
+
+    anyhow::ensure!(failed.is_empty(), "failed scenarios: {}", failed.join(", "));
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anoncreds::data_types::schema::SchemaId;
+use anoncreds::types::PresentationRequest;
+use serde::Serialize;
+
+use super::disclosure::Disclosure;
+use super::evidence::PresentationBundle;
+use super::fixtures::CredentialFixture;
+use super::mock::{CredentialFormat, PresentationFormat, TestError};
+use super::policy::TrustPolicy;
+use super::report::VerificationReport;
+use super::request::RequestSpec;
+use super::selection::HolderChoices;
+use super::vault::SealedVault;
+use super::workspace::{CredentialMessage, OfferMessage, RequestMessage, Workspace};
+
+#[derive(Debug, Clone)]
+pub struct IssueOptions {
+    // The fixture's sample values when absent
+    pub values: Option<BTreeMap<String, String>>,
+    // Required for revocable credential types
+    pub rev_idx: Option<u32>,
+    pub format: CredentialFormat,
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyOutcome {
+    pub report: VerificationReport,
+    pub disclosure: Disclosure,
+}
+
+// Each step of the flow as one party runs it: load its state, act, save what changed
+impl Workspace {
+    pub fn publish_schema(&self, fixture: &CredentialFixture) -> Result<String, TestError> {
+        let mut ledger = self.load_ledger()?;
+        let issuer = self.load_issuer()?;
+
+        let (_, schema_id) = issuer.create_schema(&mut ledger, fixture)?;
+        self.save_ledger(&ledger)?;
+        Ok(schema_id)
+    }
+
+    // Also creates the revocation registry when revocable, and deploys its Midnight registry if asked to
+    pub fn publish_cred_def(
+        &self,
+        fixture: &CredentialFixture,
+        revocable: bool,
+        midnight: bool,
+        time: u64,
+    ) -> Result<String, TestError> {
+        if midnight && !revocable {
+            return Err(TestError(format!(
+                "{} needs to be revocable to use Midnight",
+                fixture.name
+            )));
+        }
+        let mut ledger = self.load_ledger()?;
+        let mut issuer = self.load_issuer()?;
+
+        let schema = ledger
+            .schemas
+            .get(&SchemaId::new_unchecked(fixture.schema.id.as_str()))
+            .cloned()
+            .ok_or_else(|| TestError(format!("Schema {} is not published", fixture.schema.id)))?;
+        let (cred_def, cred_def_id) = issuer.create_cred_def(&mut ledger, fixture, &schema, revocable)?;
+        if revocable {
+            let (rev_reg_id, _, _) =
+                issuer.create_revocation_registry(&mut ledger, fixture, &cred_def, Some(time), true)?;
+            if midnight {
+                issuer.deploy_midnight_registry(&mut ledger, &rev_reg_id)?;
+            }
+        }
+
+        self.save_ledger(&ledger)?;
+        self.save_issuer(&issuer)?;
+        Ok(cred_def_id)
+    }
+
+    pub fn offer(&self, fixture: &CredentialFixture) -> Result<OfferMessage, TestError> {
+        let mut issuer = self.load_issuer()?;
+        if !issuer.cred_defs.contains_key(&fixture.cred_def.id) {
+            return Err(TestError(format!("This issuer has no cred def for {}", fixture.name)));
+        }
+
+        let (thread_id, offer) =
+            issuer.offer_credential(&self.sessions()?, &fixture.schema.id, &fixture.cred_def.id)?;
+        self.save_issuer(&issuer)?;
+        Ok(OfferMessage { thread_id, offer })
+    }
+
+    pub fn request(&self, message: OfferMessage, link_secret: Option<&str>) -> Result<RequestMessage, TestError> {
+        let sessions = self.sessions()?;
+        let ledger = self.load_ledger()?;
+        let mut holder = self.load_holder()?;
+
+        holder.receive_offer(&sessions, &ledger, &message.thread_id, message.offer)?;
+        let request = holder.request_credential(&sessions, &ledger, &message.thread_id, link_secret)?;
+        let (_, commitment) = holder.midnight_commitment(&message.thread_id)?;
+        self.save_holder(&holder)?;
+        Ok(RequestMessage {
+            thread_id: message.thread_id,
+            request,
+            commitment,
+        })
+    }
+
+    pub fn issue(
+        &self,
+        fixture: &CredentialFixture,
+        message: RequestMessage,
+        options: &IssueOptions,
+    ) -> Result<CredentialMessage, TestError> {
+        let mut ledger = self.load_ledger()?;
+        let mut issuer = self.load_issuer()?;
+
+        let values = options.values.as_ref().unwrap_or(&fixture.values);
+        let cred_values = fixture.credential_values_for(values)?;
+
+        // Revocable only if this issuer created the registry
+        let rev_reg_id = fixture
+            .revocation
+            .as_ref()
+            .map(|rev_reg| rev_reg.id.clone())
+            .filter(|rev_reg_id| issuer.rev_defs.contains_key(rev_reg_id));
+        let revocation = match rev_reg_id {
+            Some(rev_reg_id) => {
+                let rev_idx = options
+                    .rev_idx
+                    .ok_or_else(|| TestError(format!("{} is revocable, a revocation index is needed", fixture.name)))?;
+                let (_, status_list) = ledger
+                    .latest_rev_status_list(&rev_reg_id)
+                    .ok_or_else(|| TestError(format!("No status list for {rev_reg_id}")))?;
+                Some((rev_reg_id, rev_idx, status_list.clone()))
+            }
+            None => None,
+        };
+
+        let sessions = self.sessions()?;
+        issuer.receive_request(&sessions, &message.thread_id, &message.request)?;
+        let credential = issuer.issue_requested_credential(
+            &sessions,
+            &message.thread_id,
+            &options.format,
+            cred_values.into(),
+            revocation.as_ref().map(|(rev_reg_id, _, _)| rev_reg_id.as_str()),
+            revocation.as_ref().map(|(_, _, status_list)| status_list),
+            revocation.as_ref().map(|(_, rev_idx, _)| *rev_idx),
+            None,
+        )?;
+
+        if let Some((rev_reg_id, rev_idx, status_list)) = &revocation {
+            let cred_def = &issuer
+                .cred_defs
+                .get(&fixture.cred_def.id)
+                .ok_or_else(|| TestError(format!("Issuer has no cred def {}", fixture.cred_def.id)))?
+                .public;
+            let (_, batch) = issuer.issue_credentials_batch(
+                &mut ledger,
+                cred_def,
+                rev_reg_id,
+                status_list,
+                &BTreeMap::from([(*rev_idx, message.commitment)]),
+                Some(options.time),
+            )?;
+            tracing::info!(?batch, "registered credential");
+            self.save_ledger(&ledger)?;
+        }
+
+        self.save_issuer(&issuer)?;
+        Ok(CredentialMessage {
+            thread_id: message.thread_id,
+            credential,
+        })
+    }
+
+    pub fn store(&self, mut message: CredentialMessage, credential_id: &str) -> Result<(), TestError> {
+        let ledger = self.load_ledger()?;
+        let mut holder = self.load_holder()?;
+
+        holder.receive_credential(
+            &self.sessions()?,
+            &ledger,
+            &message.thread_id,
+            credential_id,
+            &mut message.credential,
+        )?;
+        self.save_holder(&holder)
+    }
+
+    // The holder's link secrets sealed with their own passphrase, every one of them when `ids` is empty
+    pub fn export_link_secrets(&self, passphrase: &str, ids: &[&str]) -> Result<SealedVault, TestError> {
+        self.load_holder()?.export_link_secrets(passphrase, ids)
+    }
+
+    pub fn import_link_secrets(&self, passphrase: &str, bundle: &SealedVault) -> Result<Vec<String>, TestError> {
+        let mut holder = self.load_holder()?;
+        let imported = holder.import_link_secrets(passphrase, bundle)?;
+        self.save_holder(&holder)?;
+        Ok(imported)
+    }
+
+    pub fn revoke(&self, fixture: &CredentialFixture, indices: &BTreeSet<u32>, time: u64) -> Result<(), TestError> {
+        if indices.is_empty() {
+            return Err(TestError("Nothing to revoke".to_string()));
+        }
+        let mut ledger = self.load_ledger()?;
+        let issuer = self.load_issuer()?;
+
+        let rev_reg_id = &fixture
+            .revocation
+            .as_ref()
+            .ok_or_else(|| TestError(format!("{} is not revocable", fixture.name)))?
+            .id;
+        if !issuer.rev_defs.contains_key(rev_reg_id) {
+            return Err(TestError(format!("Issuer has no revocation registry {rev_reg_id}")));
+        }
+        let cred_def = &issuer
+            .cred_defs
+            .get(&fixture.cred_def.id)
+            .ok_or_else(|| TestError(format!("Issuer has no cred def {}", fixture.cred_def.id)))?
+            .public;
+        let (_, status_list) = ledger
+            .latest_rev_status_list(rev_reg_id)
+            .ok_or_else(|| TestError(format!("No status list for {rev_reg_id}")))?;
+        let status_list = status_list.clone();
+
+        let (_, batch) =
+            issuer.revoke_credentials_batch(&mut ledger, cred_def, rev_reg_id, &status_list, indices, Some(time))?;
+        tracing::info!(?batch, "revoked credentials");
+        self.save_ledger(&ledger)
+    }
+
+    pub fn proof_request(&self, spec: &RequestSpec, time: u64) -> Result<PresentationRequest, TestError> {
+        let mut verifier = self.load_verifier()?;
+        let pres_request = spec.build(&mut verifier, time)?;
+        self.save_verifier(&verifier)?;
+        Ok(pres_request)
+    }
+
+    // The presentation together with Midnight evidence for the credentials that need it
+    pub fn present(
+        &self,
+        pres_request: &PresentationRequest,
+        format: &PresentationFormat,
+        choices: &HolderChoices,
+        time: u64,
+    ) -> Result<PresentationBundle, TestError> {
+        let ledger = self.load_ledger()?;
+        let mut holder = self.load_holder()?;
+
+        let refreshed = holder.refresh_revocation_states(&ledger)?;
+        tracing::info!(?refreshed, "refreshed revocation states");
+        let bundle = holder.present_with_evidence(&ledger, format, pres_request, choices, time)?;
+        self.save_holder(&holder)?;
+        Ok(bundle)
+    }
+
+    pub fn set_trust_policy(&self, policy: TrustPolicy) -> Result<(), TestError> {
+        let mut verifier = self.load_verifier()?;
+        verifier.policy = policy;
+        self.save_verifier(&verifier)
+    }
+
+    // The nonce is spent whatever the outcome, so the verifier is saved either way
+    pub fn verify(
+        &self,
+        pres_request: &PresentationRequest,
+        bundle: &PresentationBundle,
+        time: u64,
+    ) -> Result<VerifyOutcome, TestError> {
+        let ledger = self.load_ledger()?;
+        let mut verifier = self.load_verifier()?;
+
+        let mut report = verifier.verify_bundle_at(&ledger, bundle, pres_request, time);
+        verifier.apply_policy(&mut report, &ledger, time);
+        self.save_verifier(&verifier)?;
+        let disclosure = report.disclosure();
+        Ok(VerifyOutcome { report, disclosure })
+    }
+}
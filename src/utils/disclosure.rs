@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use anoncreds::types::PresentationRequest;
+use serde::Serialize;
+
+use super::mock::{Presentations, TestError};
+use super::report::{PresentedCredential, ReferentKind, ReferentReport, VerificationReport, collect_referents};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DisclosedAttribute {
+    pub name: String,
+    pub value: String,
+    pub credential: PresentedCredential,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DisclosedGroup {
+    // attribute name: raw value
+    pub values: BTreeMap<String, String>,
+    pub credential: PresentedCredential,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DisclosedPredicate {
+    // e.g. `age >= 18`
+    pub predicate: String,
+    // None until the presentation verified
+    pub satisfied: Option<bool>,
+    pub credential: PresentedCredential,
+}
+
+// What a presentation discloses, per referent of the request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Disclosure {
+    pub attributes: BTreeMap<String, DisclosedAttribute>,
+    pub groups: BTreeMap<String, DisclosedGroup>,
+    pub predicates: BTreeMap<String, DisclosedPredicate>,
+    // Proven without revealing the value
+    pub unrevealed: BTreeMap<String, PresentedCredential>,
+    // referent: (attribute name, value)
+    pub self_attested: BTreeMap<String, (String, String)>,
+}
+
+impl Disclosure {
+    fn from_referents(referents: &[ReferentReport]) -> Self {
+        let mut disclosure = Disclosure::default();
+        for entry in referents {
+            let referent = entry.referent.clone();
+            match (entry.kind, entry.credential.clone()) {
+                (ReferentKind::RevealedAttribute, Some(credential)) => {
+                    if let Some((name, value)) = entry.revealed.iter().next() {
+                        disclosure.attributes.insert(
+                            referent,
+                            DisclosedAttribute {
+                                name: name.clone(),
+                                value: value.clone(),
+                                credential,
+                            },
+                        );
+                    }
+                }
+                (ReferentKind::AttributeGroup, Some(credential)) => {
+                    disclosure.groups.insert(
+                        referent,
+                        DisclosedGroup {
+                            values: entry.revealed.clone(),
+                            credential,
+                        },
+                    );
+                }
+                (ReferentKind::Predicate, Some(credential)) => {
+                    disclosure.predicates.insert(
+                        referent,
+                        DisclosedPredicate {
+                            predicate: entry.predicate.clone().unwrap_or_default(),
+                            satisfied: entry.predicate_satisfied,
+                            credential,
+                        },
+                    );
+                }
+                (ReferentKind::UnrevealedAttribute, Some(credential)) => {
+                    disclosure.unrevealed.insert(referent, credential);
+                }
+                (ReferentKind::SelfAttestedAttribute, _) => {
+                    if let Some((name, value)) = entry.revealed.iter().next() {
+                        disclosure.self_attested.insert(referent, (name.clone(), value.clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        disclosure
+    }
+
+    // A revealed value, from a single attribute or a group
+    pub fn value(&self, referent: &str, name: &str) -> Option<&str> {
+        if let Some(attribute) = self.attributes.get(referent) {
+            return (attribute.name == name).then_some(attribute.value.as_str());
+        }
+        self.groups
+            .get(referent)
+            .and_then(|group| group.values.get(name))
+            .map(String::as_str)
+    }
+}
+
+// Reads the disclosed data without verifying the presentation, predicates stay undecided
+pub fn extract_disclosure(
+    presentation: &Presentations,
+    pres_req: &PresentationRequest,
+) -> Result<Disclosure, TestError> {
+    collect_referents(presentation, pres_req).map(|referents| Disclosure::from_referents(&referents))
+}
+
+impl VerificationReport {
+    // Empty unless the presentation verified
+    pub fn disclosure(&self) -> Disclosure {
+        if !self.verified {
+            return Disclosure::default();
+        }
+        Disclosure::from_referents(&self.referents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::testing::{fixture, issued};
+    use crate::utils::{CredentialFormat, PresentationFormat};
+
+    // Both credentials have a `name`, so each referent has to be traced to the one that answered it
+    fn disclosed(credential_format: CredentialFormat, format: PresentationFormat) -> Disclosure {
+        let mut issued = issued(credential_format.clone());
+        issued.issue(&fixture("EMP"), credential_format, false);
+        let spec = json!({
+            "name": "proof",
+            "attributes": {
+                "citizen": { "name": "name", "restrictions": { "cred_def_id": "creddef:government" } },
+                "employee": { "name": "name", "restrictions": { "cred_def_id": "creddef:employee" } },
+                "role": { "name": "role" },
+                "profile": { "names": ["age", "sex"] },
+            },
+            "predicates": {
+                "adult": { "name": "age", "p_type": ">=", "p_value": 18 },
+            },
+        });
+        let (pres_request, presentation) = issued.present(spec, &format);
+        extract_disclosure(&presentation, &pres_request).unwrap()
+    }
+
+    fn check(disclosure: &Disclosure) {
+        let attribute = |referent: &str| {
+            let attribute = &disclosure.attributes[referent];
+            (attribute.value.as_str(), attribute.credential.cred_def_id.as_str())
+        };
+        assert_eq!(attribute("citizen"), ("Alex", "creddef:government"));
+        assert_eq!(attribute("employee"), ("John", "creddef:employee"));
+        assert_eq!(attribute("role"), ("Developer", "creddef:employee"));
+
+        let profile = &disclosure.groups["profile"];
+        assert_eq!(profile.credential.cred_def_id, "creddef:government");
+        assert_eq!(profile.values["age"], "28");
+        assert_eq!(profile.values["sex"], "male");
+        assert_eq!(disclosure.value("profile", "age"), Some("28"));
+        assert_eq!(disclosure.value("citizen", "name"), Some("Alex"));
+        assert_eq!(disclosure.value("citizen", "role"), None);
+
+        let adult = &disclosure.predicates["adult"];
+        assert_eq!(adult.predicate, "age >= 18");
+        assert_eq!(adult.satisfied, None);
+        assert_eq!(adult.credential.cred_def_id, "creddef:government");
+        assert!(disclosure.unrevealed.is_empty());
+        assert!(disclosure.self_attested.is_empty());
+    }
+
+    #[test]
+    fn legacy_referents_are_mapped_to_their_credentials() {
+        check(&disclosed(CredentialFormat::Legacy, PresentationFormat::Legacy));
+    }
+
+    #[test]
+    fn w3c_referents_are_mapped_to_their_credentials() {
+        check(&disclosed(CredentialFormat::W3C, PresentationFormat::W3C));
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anoncreds::types::{CredentialValues, MakeCredentialValues};
+use chrono::{DateTime, Months, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::mock::TestError;
+use super::query::attr_common_view;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeType {
+    #[default]
+    String,
+    // Fits an i32, the range anoncreds predicates work on
+    Integer,
+    // `YYYY-MM-DD`, issued as its day number so it can be used in predicates
+    Date,
+    // Issued as 1 or 0
+    Boolean,
+}
+
+impl fmt::Display for AttributeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AttributeType::String => "string",
+            AttributeType::Integer => "integer",
+            AttributeType::Date => "date",
+            AttributeType::Boolean => "boolean",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeDefinition {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub attr_type: AttributeType,
+}
+
+// Day numbers count from this date
+pub fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).expect("valid epoch")
+}
+
+pub fn date_to_day_number(date: NaiveDate) -> i32 {
+    (date - epoch()).num_days() as i32
+}
+
+pub fn day_number_to_date(day_number: i32) -> NaiveDate {
+    epoch() + chrono::Duration::days(day_number as i64)
+}
+
+// The calendar date in `tz` at `instant`, which is what "today" means for a verifier there
+pub fn date_in<Tz: TimeZone>(tz: &Tz, instant: DateTime<Utc>) -> NaiveDate {
+    instant.with_timezone(tz).date_naive()
+}
+
+// Latest birthdate of someone at least `years` old on `today`. Subtracting whole months keeps the day of month,
+// except that Feb 29 becomes Feb 28 in a common year, so someone born on Feb 29 comes of age on Mar 1
+pub fn latest_birthdate(years: u32, today: NaiveDate) -> Result<NaiveDate, TestError> {
+    years
+        .checked_mul(12)
+        .and_then(|months| today.checked_sub_months(Months::new(months)))
+        .ok_or_else(|| TestError(format!("no birthdate is {years} years before {today}")))
+}
+
+// The raw value to issue for `value`. anoncreds encodes raw values that parse as an i32 as that integer
+// and hashes everything else, so integers have to be written in a single canonical form
+pub fn canonical_value(attr_type: AttributeType, value: &str) -> Result<String, TestError> {
+    let trimmed = value.trim();
+    match attr_type {
+        AttributeType::String => Ok(value.to_string()),
+        AttributeType::Integer => trimmed
+            .parse::<i32>()
+            .map(|number| number.to_string())
+            .map_err(|e| TestError(format!("`{value}` is not an integer in the i32 range: {e}"))),
+        AttributeType::Date => NaiveDate::parse_from_str(trimmed, "%Y-%m-%d")
+            .map(|date| date_to_day_number(date).to_string())
+            .map_err(|e| TestError(format!("`{value}` is not a YYYY-MM-DD date: {e}"))),
+        AttributeType::Boolean => match trimmed.to_lowercase().as_str() {
+            "true" | "1" => Ok("1".to_string()),
+            "false" | "0" => Ok("0".to_string()),
+            _ => Err(TestError(format!("`{value}` is not a boolean"))),
+        },
+    }
+}
+
+// The value anoncreds signs and proves for a raw value: the integer itself when it parses as an i32, the SHA-256
+// of it read as a big-endian number otherwise
+pub fn encoded_value(raw: &str) -> Result<String, TestError> {
+    let mut values = MakeCredentialValues::default();
+    values
+        .add_raw("value", raw)
+        .map_err(|e| TestError(format!("Error encoding `{raw}`: {e}")))?;
+    let values: CredentialValues = values.into();
+    Ok(values.0["value"].encoded.clone())
+}
+
+// Canonical raw values for exactly the attributes in `definitions`, keyed by schema attribute name
+pub fn encode_attributes(
+    definitions: &[AttributeDefinition],
+    values: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, TestError> {
+    let mut given: BTreeMap<String, (&String, &String)> = BTreeMap::new();
+    for (name, value) in values.iter() {
+        if given.insert(attr_common_view(name), (name, value)).is_some() {
+            return Err(TestError(format!("Attribute {name} is given more than once")));
+        }
+    }
+
+    let mut encoded = BTreeMap::new();
+    for definition in definitions {
+        let (_, value) = given
+            .remove(&attr_common_view(&definition.name))
+            .ok_or_else(|| TestError(format!("Attribute {} has no value", definition.name)))?;
+        let value = canonical_value(definition.attr_type, value).map_err(|e| {
+            TestError(format!(
+                "Attribute {} ({}): {}",
+                definition.name, definition.attr_type, e.0
+            ))
+        })?;
+        encoded.insert(definition.name.clone(), value);
+    }
+    if !given.is_empty() {
+        let unexpected: Vec<&str> = given.values().map(|(name, _)| name.as_str()).collect();
+        return Err(TestError(format!(
+            "Attributes not in the schema: {}",
+            unexpected.join(", ")
+        )));
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn definitions() -> Vec<AttributeDefinition> {
+        [
+            ("Name", AttributeType::String),
+            ("Age", AttributeType::Integer),
+            ("Birth Date", AttributeType::Date),
+            ("Member", AttributeType::Boolean),
+        ]
+        .into_iter()
+        .map(|(name, attr_type)| AttributeDefinition {
+            name: name.to_string(),
+            attr_type,
+        })
+        .collect()
+    }
+
+    fn values(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn integers_have_one_canonical_form() {
+        for value in ["28", " 28 ", "028", "+28"] {
+            assert_eq!(canonical_value(AttributeType::Integer, value).unwrap(), "28");
+        }
+        assert_eq!(canonical_value(AttributeType::Integer, "-0").unwrap(), "0");
+        assert_eq!(
+            canonical_value(AttributeType::Integer, "-2147483648").unwrap(),
+            "-2147483648"
+        );
+        assert!(canonical_value(AttributeType::Integer, "2147483648").is_err());
+        assert!(canonical_value(AttributeType::Integer, "28.0").is_err());
+        assert!(canonical_value(AttributeType::Integer, "").is_err());
+    }
+
+    #[test]
+    fn dates_and_booleans_are_issued_as_integers() {
+        assert_eq!(canonical_value(AttributeType::Date, "1970-01-01").unwrap(), "0");
+        assert_eq!(canonical_value(AttributeType::Date, "1997-04-12").unwrap(), "9963");
+        assert_eq!(canonical_value(AttributeType::Date, "1969-12-31").unwrap(), "-1");
+        assert!(canonical_value(AttributeType::Date, "12/04/1997").is_err());
+        assert_eq!(canonical_value(AttributeType::Boolean, " TRUE ").unwrap(), "1");
+        assert_eq!(canonical_value(AttributeType::Boolean, "0").unwrap(), "0");
+        assert!(canonical_value(AttributeType::Boolean, "yes").is_err());
+    }
+
+    #[test]
+    fn strings_are_kept_as_given() {
+        assert_eq!(canonical_value(AttributeType::String, " Alex ").unwrap(), " Alex ");
+        assert_eq!(canonical_value(AttributeType::String, "028").unwrap(), "028");
+    }
+
+    #[test]
+    fn integers_are_encoded_as_themselves() {
+        assert_eq!(encoded_value("28").unwrap(), "28");
+        assert_eq!(encoded_value("-1").unwrap(), "-1");
+        assert_eq!(encoded_value("2147483647").unwrap(), "2147483647");
+    }
+
+    #[test]
+    fn other_values_are_encoded_as_their_sha256() {
+        assert_eq!(
+            encoded_value("Alex").unwrap(),
+            "99262857098057710338306967609588410025648622308394250666849665532448612202874"
+        );
+        // Out of the i32 range, so hashed like any string
+        assert_eq!(
+            encoded_value("2147483648").unwrap(),
+            "26221484005389514539852548961319751347124425277437769688639924217837557266135"
+        );
+    }
+
+    #[test]
+    fn equal_integers_encode_the_same_once_canonical() {
+        let encode = |value: &str| encoded_value(&canonical_value(AttributeType::Integer, value).unwrap()).unwrap();
+        assert_eq!(encode("28"), encode(" 028"));
+        assert_eq!(encoded_value("28").unwrap(), encoded_value("28").unwrap());
+        assert_eq!(encoded_value("Alex").unwrap(), encoded_value("Alex").unwrap());
+        assert_ne!(encoded_value("Alex").unwrap(), encoded_value("alex").unwrap());
+    }
+
+    #[test]
+    fn attributes_are_encoded_by_schema_name() {
+        let encoded = encode_attributes(
+            &definitions(),
+            &values(&[
+                ("name", "Alex"),
+                ("age", " 28"),
+                ("birthdate", "1997-04-12"),
+                ("MEMBER", "true"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            encoded,
+            values(&[("Name", "Alex"), ("Age", "28"), ("Birth Date", "9963"), ("Member", "1")])
+        );
+    }
+
+    #[test]
+    fn attributes_have_to_match_the_schema() {
+        let complete = [
+            ("Name", "Alex"),
+            ("Age", "28"),
+            ("Birth Date", "1997-04-12"),
+            ("Member", "1"),
+        ];
+        let error =
+            |values_given: &[(&str, &str)]| encode_attributes(&definitions(), &values(values_given)).unwrap_err().0;
+
+        assert_eq!(error(&complete[1..]), "Attribute Name has no value");
+        let mut extra = complete.to_vec();
+        extra.push(("Nickname", "Al"));
+        assert_eq!(error(&extra), "Attributes not in the schema: Nickname");
+        let mut twice = complete.to_vec();
+        twice.push(("age", "28"));
+        assert_eq!(error(&twice), "Attribute age is given more than once");
+        let mut invalid = complete.to_vec();
+        invalid[1] = ("Age", "old");
+        assert!(error(&invalid).starts_with("Attribute Age (integer): "));
+    }
+
+    #[test]
+    fn feb_29_birthdays_come_of_age_on_mar_1_in_common_years() {
+        assert_eq!(latest_birthdate(18, date("2026-02-28")).unwrap(), date("2008-02-28"));
+        assert_eq!(latest_birthdate(18, date("2026-03-01")).unwrap(), date("2008-03-01"));
+        // Today is Feb 29, and the birth year is a common or a leap year
+        assert_eq!(latest_birthdate(18, date("2028-02-29")).unwrap(), date("2010-02-28"));
+        assert_eq!(latest_birthdate(20, date("2028-02-29")).unwrap(), date("2008-02-29"));
+    }
+
+    #[test]
+    fn year_boundary() {
+        assert_eq!(latest_birthdate(18, date("2025-12-31")).unwrap(), date("2007-12-31"));
+        assert_eq!(latest_birthdate(18, date("2026-01-01")).unwrap(), date("2008-01-01"));
+    }
+
+    #[test]
+    fn out_of_range_ages_are_errors() {
+        assert!(latest_birthdate(u32::MAX, date("2026-01-01")).is_err());
+        assert!(latest_birthdate(300_000, date("2026-01-01")).is_err());
+    }
+
+    #[test]
+    fn today_depends_on_the_verifiers_time_zone() {
+        let instant = DateTime::parse_from_rfc3339("2025-12-31T11:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(date_in(&Utc, instant), date("2025-12-31"));
+        assert_eq!(
+            date_in(&FixedOffset::east_opt(14 * 3600).unwrap(), instant),
+            date("2026-01-01")
+        );
+        assert_eq!(
+            date_in(&FixedOffset::west_opt(12 * 3600).unwrap(), instant),
+            date("2025-12-30")
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use super::mock::{CredentialFormat, Credentials, PresentationFormat, Presentations, TestError};
+
+// Bumped whenever the envelope layout changes
+pub const ENVELOPE_VERSION: u32 = 1;
+
+const W3C_V1_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+const W3C_V2_CONTEXT: &str = "https://www.w3.org/ns/credentials/v2";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvelopeType {
+    Credential,
+    Presentation,
+    PresentationBundle,
+}
+
+// W3C Verifiable Credentials Data Model version of a W3C payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum W3CSpecVersion {
+    #[serde(rename = "1.1")]
+    V1_1,
+    #[serde(rename = "2.0")]
+    V2_0,
+}
+
+impl W3CSpecVersion {
+    // Read from the `@context` of a W3C credential or presentation
+    fn of(payload: &Value) -> Result<Self, TestError> {
+        let contexts = payload["@context"]
+            .as_array()
+            .ok_or_else(|| TestError("W3C payload has no @context".to_string()))?;
+        let has = |context: &str| contexts.iter().any(|entry| entry.as_str() == Some(context));
+        match (has(W3C_V1_CONTEXT), has(W3C_V2_CONTEXT)) {
+            (true, false) => Ok(W3CSpecVersion::V1_1),
+            (false, true) => Ok(W3CSpecVersion::V2_0),
+            _ => Err(TestError("W3C payload has no single data model @context".to_string())),
+        }
+    }
+}
+
+// `{"type", "version", "format", "spec_version", "payload"}`, the wire form of credentials and presentations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Envelope<F> {
+    #[serde(rename = "type")]
+    pub envelope_type: EnvelopeType,
+    pub version: u32,
+    pub format: F,
+    // W3C payloads only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_version: Option<W3CSpecVersion>,
+    pub payload: Value,
+}
+
+impl<F> Envelope<F> {
+    fn new(envelope_type: EnvelopeType, format: F, w3c: bool, payload: Value) -> Result<Self, TestError> {
+        let spec_version = if w3c { Some(W3CSpecVersion::of(&payload)?) } else { None };
+        Ok(Self {
+            envelope_type,
+            version: ENVELOPE_VERSION,
+            format,
+            spec_version,
+            payload,
+        })
+    }
+
+    // Checks the header against the payload before it is parsed
+    fn open<T: DeserializeOwned>(self, envelope_type: EnvelopeType, w3c: bool) -> Result<T, TestError> {
+        if self.envelope_type != envelope_type {
+            return Err(TestError(format!(
+                "Expected a {envelope_type:?} envelope, got a {:?} envelope",
+                self.envelope_type
+            )));
+        }
+        if self.version != ENVELOPE_VERSION {
+            return Err(TestError(format!(
+                "Unsupported envelope version {}, expected {ENVELOPE_VERSION}",
+                self.version
+            )));
+        }
+        match (w3c, self.spec_version) {
+            (true, Some(spec_version)) => {
+                let payload_version = W3CSpecVersion::of(&self.payload)?;
+                if payload_version != spec_version {
+                    return Err(TestError(format!(
+                        "Envelope declares W3C spec version {spec_version:?}, the payload is {payload_version:?}"
+                    )));
+                }
+            }
+            (true, None) => return Err(TestError("W3C envelope has no spec_version".to_string())),
+            (false, Some(_)) => return Err(TestError("Legacy envelope has a spec_version".to_string())),
+            (false, None) => {}
+        }
+        serde_json::from_value(self.payload).map_err(|e| TestError(format!("Invalid {envelope_type:?} payload: {e}")))
+    }
+}
+
+impl Credentials {
+    pub fn to_envelope(&self) -> Result<Envelope<CredentialFormat>, TestError> {
+        let (format, payload) = match self {
+            Credentials::Legacy(credential) => (CredentialFormat::Legacy, serde_json::to_value(credential)),
+            Credentials::W3C(credential) => (CredentialFormat::W3C, serde_json::to_value(credential)),
+        };
+        let payload = payload.map_err(|e| TestError(e.to_string()))?;
+        let w3c = format == CredentialFormat::W3C;
+        Envelope::new(EnvelopeType::Credential, format, w3c, payload)
+    }
+
+    pub fn from_envelope(envelope: Envelope<CredentialFormat>) -> Result<Self, TestError> {
+        match envelope.format {
+            CredentialFormat::Legacy => envelope.open(EnvelopeType::Credential, false).map(Credentials::Legacy),
+            CredentialFormat::W3C => envelope.open(EnvelopeType::Credential, true).map(Credentials::W3C),
+        }
+    }
+}
+
+impl Presentations {
+    pub fn to_envelope(&self) -> Result<Envelope<PresentationFormat>, TestError> {
+        let (format, payload) = match self {
+            Presentations::Legacy(presentation) => (PresentationFormat::Legacy, serde_json::to_value(presentation)),
+            Presentations::W3C(presentation) => (PresentationFormat::W3C, serde_json::to_value(presentation)),
+        };
+        let payload = payload.map_err(|e| TestError(e.to_string()))?;
+        let w3c = format == PresentationFormat::W3C;
+        Envelope::new(EnvelopeType::Presentation, format, w3c, payload)
+    }
+
+    pub fn from_envelope(envelope: Envelope<PresentationFormat>) -> Result<Self, TestError> {
+        match envelope.format {
+            PresentationFormat::Legacy => envelope
+                .open(EnvelopeType::Presentation, false)
+                .map(Presentations::Legacy),
+            PresentationFormat::W3C => envelope.open(EnvelopeType::Presentation, true).map(Presentations::W3C),
+        }
+    }
+}
+
+impl Serialize for Credentials {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_envelope()
+            .map_err(|e| serde::ser::Error::custom(e.0))?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Credentials {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Credentials::from_envelope(Envelope::deserialize(deserializer)?).map_err(|e| D::Error::custom(e.0))
+    }
+}
+
+impl Serialize for Presentations {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_envelope()
+            .map_err(|e| serde::ser::Error::custom(e.0))?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Presentations {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Presentations::from_envelope(Envelope::deserialize(deserializer)?).map_err(|e| D::Error::custom(e.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::testing::issued;
+
+    // Serializes, checks the header and parses it back, which has to give the same JSON
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T, format: &str, spec_version: Option<&str>) {
+        let json = serde_json::to_value(value).unwrap();
+        assert_eq!(json["version"], json!(ENVELOPE_VERSION));
+        assert_eq!(json["format"], json!(format));
+        assert_eq!(json.get("spec_version").and_then(Value::as_str), spec_version);
+        let parsed: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+    }
+
+    fn presentation_spec() -> Value {
+        json!({ "name": "proof", "attributes": { "name": { "name": "name" } } })
+    }
+
+    #[test]
+    fn legacy_credentials_and_presentations_round_trip() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let (_, presentation) = issued.present(presentation_spec(), &PresentationFormat::Legacy);
+        let credential = Credentials::Legacy(issued.holder.credentials.remove("GVT").unwrap());
+        round_trip(&credential, "Legacy", None);
+        round_trip(&presentation, "Legacy", None);
+        assert_eq!(serde_json::to_value(&presentation).unwrap()["type"], "presentation");
+    }
+
+    #[test]
+    fn w3c_credentials_and_presentations_round_trip() {
+        let mut issued = issued(CredentialFormat::W3C);
+        let (_, presentation) = issued.present(presentation_spec(), &PresentationFormat::W3C);
+        let credential = Credentials::W3C(issued.holder.w3c_credentials.remove("GVT").unwrap());
+        round_trip(&credential, "W3C", Some("1.1"));
+        round_trip(&presentation, "W3C", Some("1.1"));
+        assert_eq!(serde_json::to_value(&credential).unwrap()["type"], "credential");
+    }
+
+    // An envelope whose header is checked before its payload is parsed
+    fn envelope(envelope_type: &str, format: &str, spec_version: Option<&str>, contexts: &[&str]) -> Value {
+        let mut envelope = json!({
+            "type": envelope_type,
+            "version": ENVELOPE_VERSION,
+            "format": format,
+            "payload": { "@context": contexts },
+        });
+        if let Some(spec_version) = spec_version {
+            envelope["spec_version"] = json!(spec_version);
+        }
+        envelope
+    }
+
+    fn credential_error(envelope: Value) -> String {
+        serde_json::from_value::<Credentials>(envelope).unwrap_err().to_string()
+    }
+
+    fn presentation_error(envelope: Value) -> String {
+        serde_json::from_value::<Presentations>(envelope)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn envelopes_of_another_type_are_rejected() {
+        let error = credential_error(envelope("presentation", "W3C", Some("1.1"), &[W3C_V1_CONTEXT]));
+        assert_eq!(error, "Expected a Credential envelope, got a Presentation envelope");
+        let error = presentation_error(envelope("credential", "Legacy", None, &[]));
+        assert_eq!(error, "Expected a Presentation envelope, got a Credential envelope");
+        assert!(credential_error(envelope("receipt", "Legacy", None, &[])).contains("unknown variant"));
+    }
+
+    #[test]
+    fn other_envelope_versions_are_rejected() {
+        let mut future = envelope("credential", "Legacy", None, &[]);
+        future["version"] = json!(ENVELOPE_VERSION + 1);
+        assert_eq!(
+            credential_error(future),
+            format!(
+                "Unsupported envelope version {}, expected {ENVELOPE_VERSION}",
+                ENVELOPE_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn spec_version_has_to_match_the_payload_context() {
+        let error = credential_error(envelope("credential", "W3C", Some("2.0"), &[W3C_V1_CONTEXT]));
+        assert_eq!(error, "Envelope declares W3C spec version V2_0, the payload is V1_1");
+        let error = presentation_error(envelope("presentation", "W3C", Some("1.1"), &[W3C_V2_CONTEXT]));
+        assert_eq!(error, "Envelope declares W3C spec version V1_1, the payload is V2_0");
+        let error = credential_error(envelope(
+            "credential",
+            "W3C",
+            Some("1.1"),
+            &[W3C_V1_CONTEXT, W3C_V2_CONTEXT],
+        ));
+        assert_eq!(error, "W3C payload has no single data model @context");
+        let error = credential_error(envelope("credential", "W3C", None, &[W3C_V1_CONTEXT]));
+        assert_eq!(error, "W3C envelope has no spec_version");
+    }
+
+    #[test]
+    fn legacy_payloads_have_no_spec_version() {
+        let error = credential_error(envelope("credential", "Legacy", Some("1.1"), &[W3C_V1_CONTEXT]));
+        assert_eq!(error, "Legacy envelope has a spec_version");
+        let error = presentation_error(envelope("presentation", "Legacy", Some("2.0"), &[]));
+        assert_eq!(error, "Legacy envelope has a spec_version");
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let mut extra = envelope("credential", "Legacy", None, &[]);
+        extra["signature"] = json!("...");
+        assert!(credential_error(extra).contains("unknown field `signature`"));
+    }
+}
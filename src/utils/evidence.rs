@@ -0,0 +1,542 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anoncreds::data_types::nonce::Nonce;
+use anoncreds::data_types::rev_reg_def::RevocationRegistryDefinitionId;
+use anoncreds::types::PresentationRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::envelope::{ENVELOPE_VERSION, EnvelopeType};
+use super::midnight::{
+    Bytes32, MerkleTreePath, REV_REG_TREE_DEPTH, REVOKED_LEAF, holder_public_key, holder_sign, merkle_tree_path_root,
+    persistent_hash, verify_holder_signature,
+};
+use super::mock::{PresentationFormat, Presentations, RevocableCredential, TestError};
+use super::report::{ReasonCode, VerificationReport, collect_referents};
+use super::resolve::presented_credentials;
+use super::selection::HolderChoices;
+use super::storage::{Ledger, ProverWallet, VerifierWallet};
+use super::vault::{from_hex, to_hex};
+
+// Non-revocation evidence for one presented credential: its leaf is in the tree whose root the contract held at
+// `timestamp`, and the leaf commits to `holder_key`, which signed the bundle's binding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MidnightEvidence {
+    pub rev_reg_id: String,
+    pub contract_address: String,
+    #[serde(with = "hex32")]
+    pub root: Bytes32,
+    pub timestamp: u64,
+    #[serde(with = "hex32")]
+    pub leaf: Bytes32,
+    pub index: u32,
+    // From the leaf level up to the root
+    #[serde(with = "hex32_vec")]
+    pub siblings: Vec<Bytes32>,
+    #[serde(with = "hex32")]
+    pub holder_key: Bytes32,
+    // Over the binding, left out of the binding itself
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl MidnightEvidence {
+    pub fn path(&self) -> MerkleTreePath {
+        MerkleTreePath {
+            leaf: self.leaf,
+            index: self.index,
+            siblings: self.siblings.clone(),
+        }
+    }
+}
+
+// A presentation and the Midnight evidence for its credentials, sent as one artifact. `binding` is a digest
+// over both and the request nonce, and every evidence entry carries a signature over it by the key its leaf
+// commits to. Only the holder of that key can move the evidence into another presentation or exchange
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresentationBundle {
+    #[serde(rename = "type")]
+    pub envelope_type: EnvelopeType,
+    pub version: u32,
+    pub presentation: Presentations,
+    // One entry per Midnight registry the presented credentials belong to
+    #[serde(default)]
+    pub evidence: Vec<MidnightEvidence>,
+    pub binding: String,
+}
+
+impl PresentationBundle {
+    // `secrets` holds the commitment secret of each evidence entry, in the same order
+    pub fn new(
+        presentation: Presentations,
+        mut evidence: Vec<MidnightEvidence>,
+        secrets: &[Bytes32],
+        pres_request: &PresentationRequest,
+    ) -> Result<Self, TestError> {
+        let binding = binding_digest(&presentation, &evidence, &pres_request.value().nonce)?;
+        for (evidence, secret) in evidence.iter_mut().zip(secrets) {
+            evidence.signature = to_hex(&holder_sign(secret, binding.as_bytes()));
+        }
+        Ok(Self {
+            envelope_type: EnvelopeType::PresentationBundle,
+            version: ENVELOPE_VERSION,
+            presentation,
+            evidence,
+            binding,
+        })
+    }
+
+    pub fn check_header(&self) -> Result<(), TestError> {
+        if self.envelope_type != EnvelopeType::PresentationBundle {
+            return Err(TestError(format!(
+                "Expected a presentation bundle, got a {:?} envelope",
+                self.envelope_type
+            )));
+        }
+        if self.version != ENVELOPE_VERSION {
+            return Err(TestError(format!(
+                "Unsupported bundle version {}, expected {ENVELOPE_VERSION}",
+                self.version
+            )));
+        }
+        Ok(())
+    }
+}
+
+// serde_json maps are sorted, so the serialized form is canonical. Signatures are over the digest, so they are
+// left out of it
+fn binding_digest(
+    presentation: &Presentations,
+    evidence: &[MidnightEvidence],
+    nonce: &Nonce,
+) -> Result<String, TestError> {
+    let unsigned: Vec<MidnightEvidence> = evidence
+        .iter()
+        .map(|evidence| MidnightEvidence {
+            signature: String::new(),
+            ..evidence.clone()
+        })
+        .collect();
+    let bound = json!({ "nonce": nonce, "presentation": presentation, "evidence": unsigned });
+    let bytes = serde_json::to_vec(&bound).map_err(|e| TestError(e.to_string()))?;
+    Ok(to_hex(&Sha256::digest(bytes)))
+}
+
+// How many presented credentials belong to each registry with a Midnight contract on the ledger
+fn midnight_registries(ledger: &Ledger, presentation: &Presentations) -> Result<BTreeMap<String, usize>, TestError> {
+    let mut registries = BTreeMap::new();
+    for rev_reg_id in presented_credentials(presentation)?
+        .into_iter()
+        .filter_map(|credential| credential.rev_reg_id)
+    {
+        if ledger
+            .midnight_rev_regs
+            .contains_key(&RevocationRegistryDefinitionId::new_unchecked(rev_reg_id.as_str()))
+        {
+            *registries.entry(rev_reg_id).or_default() += 1;
+        }
+    }
+    Ok(registries)
+}
+
+impl ProverWallet {
+    // Presents as `present` does, with the Midnight path of every presented credential's own leaf, signed with
+    // that credential's commitment secret. A credential whose leaf is revoked has no evidence, the verifier
+    // reports it missing
+    pub fn present_with_evidence(
+        &self,
+        ledger: &Ledger,
+        format: &PresentationFormat,
+        pres_request: &PresentationRequest,
+        choices: &HolderChoices,
+        now: u64,
+    ) -> Result<PresentationBundle, TestError> {
+        let (presentation, selected) = self.present_selected(ledger, format, pres_request, choices)?;
+
+        let mut evidence = vec![];
+        let mut secrets = vec![];
+        for credential in selected.iter() {
+            let Some((rev_reg_id, index)) = self.held_rev_reg(format, &credential.id) else {
+                continue;
+            };
+            let Some(registry) = ledger.midnight_rev_regs.get(&rev_reg_id) else {
+                continue;
+            };
+            let commitment_secret = self
+                .midnight_secrets
+                .get(&credential.id)
+                .ok_or_else(|| TestError(format!("No Midnight commitment for credential {}", credential.id)))?;
+            let path = registry
+                .path_at(index)
+                .ok_or_else(|| TestError(format!("Index {index} is not in the Midnight registry")))?;
+            if let Err(e) = registry.proof_non_revoked(&path, commitment_secret) {
+                tracing::warn!(credential = %credential.id, %rev_reg_id, error = %e.0, "no Midnight evidence");
+                continue;
+            }
+            evidence.push(MidnightEvidence {
+                rev_reg_id: rev_reg_id.0,
+                contract_address: registry.contract_address(),
+                root: registry.root(),
+                timestamp: now,
+                leaf: path.leaf,
+                index: path.index,
+                siblings: path.siblings,
+                holder_key: holder_public_key(commitment_secret),
+                signature: String::new(),
+            });
+            secrets.push(*commitment_secret);
+        }
+        PresentationBundle::new(presentation, evidence, &secrets, pres_request)
+    }
+
+    fn held_rev_reg(&self, format: &PresentationFormat, id: &str) -> Option<(RevocationRegistryDefinitionId, u32)> {
+        match format {
+            PresentationFormat::Legacy => self
+                .credentials
+                .get(id)
+                .and_then(|credential| Some((credential.rev_reg_id()?, credential.rev_reg_index()?))),
+            PresentationFormat::W3C => self
+                .w3c_credentials
+                .get(id)
+                .and_then(|credential| Some((credential.rev_reg_id()?, credential.rev_reg_index()?))),
+        }
+    }
+}
+
+impl VerifierWallet {
+    // Rejects an unbound bundle before anything else. Otherwise verifies the presentation and requires valid evidence
+    // for every presented credential under Midnight revocation. The nonce is consumed only when both pass
+    pub fn verify_bundle_at(
+        &mut self,
+        ledger: &Ledger,
+        bundle: &PresentationBundle,
+        pres_req: &PresentationRequest,
+        now: u64,
+    ) -> VerificationReport {
+        let bound = bundle.check_header().and_then(|_| {
+            let expected = binding_digest(&bundle.presentation, &bundle.evidence, &pres_req.value().nonce)?;
+            if expected != bundle.binding {
+                return Err(TestError(
+                    "binding does not match the presentation, evidence and nonce".to_string(),
+                ));
+            }
+            Ok(())
+        });
+        if let Err(e) = bound {
+            let mut report = VerificationReport {
+                verified: false,
+                referents: collect_referents(&bundle.presentation, pres_req).unwrap_or_default(),
+                failures: vec![],
+                midnight_checked: BTreeSet::new(),
+                policy_violations: vec![],
+            };
+            report.fail(ReasonCode::UnboundRevocationEvidence, None, e.0);
+            return report;
+        }
+
+        let nonce = &pres_req.value().nonce;
+        let mut report = self.check_presentation_from_ledger_at(ledger, &bundle.presentation, pres_req, now);
+        // An unknown nonce already fails the presentation
+        let issued_at = self.nonces.get(nonce).map_or(0, |issued| issued.issued_at);
+        let (problems, checked) = check_evidence(ledger, bundle, issued_at, self.policy.max_revocation_status_age, now);
+        for (reason, detail) in problems {
+            report.verified = false;
+            report.fail(reason, None, detail);
+        }
+        report.midnight_checked = checked;
+        if report.verified {
+            self.nonces.consume(nonce, now);
+        }
+        report
+    }
+}
+
+// The problems found, and the registries whose presented credentials are all covered by valid evidence
+fn check_evidence(
+    ledger: &Ledger,
+    bundle: &PresentationBundle,
+    issued_at: u64,
+    max_age: Option<u64>,
+    now: u64,
+) -> (Vec<(ReasonCode, String)>, BTreeSet<String>) {
+    let required = match midnight_registries(ledger, &bundle.presentation) {
+        Ok(required) => required,
+        // Reported as a malformed presentation by the verification
+        Err(_) => return (vec![], BTreeSet::new()),
+    };
+
+    // Every presented credential has its own leaf, so a registry needs one entry per credential and distinct leaves
+    let mut problems = vec![];
+    let mut given: BTreeMap<&str, BTreeSet<u32>> = BTreeMap::new();
+    let mut invalid: BTreeSet<&str> = BTreeSet::new();
+    for evidence in bundle.evidence.iter() {
+        if !given
+            .entry(evidence.rev_reg_id.as_str())
+            .or_default()
+            .insert(evidence.index)
+        {
+            problems.push((
+                ReasonCode::InvalidRevocationEvidence,
+                format!(
+                    "evidence for {} index {} is given twice",
+                    evidence.rev_reg_id, evidence.index
+                ),
+            ));
+            continue;
+        }
+        if !required.contains_key(&evidence.rev_reg_id) {
+            problems.push((
+                ReasonCode::InvalidRevocationEvidence,
+                format!("evidence for {} matches no presented credential", evidence.rev_reg_id),
+            ));
+            continue;
+        }
+        if let Err(e) = check_midnight_evidence(ledger, evidence, &bundle.binding, issued_at, max_age, now) {
+            invalid.insert(evidence.rev_reg_id.as_str());
+            problems.push((
+                ReasonCode::InvalidRevocationEvidence,
+                format!("{}: {}", evidence.rev_reg_id, e.0),
+            ));
+        }
+    }
+
+    let mut checked = BTreeSet::new();
+    for (rev_reg_id, count) in required.iter() {
+        let given = given.get(rev_reg_id.as_str()).map_or(0, BTreeSet::len);
+        if given < *count {
+            problems.push((
+                ReasonCode::MissingRevocationEvidence,
+                format!("{given} of {count} presented credentials of {rev_reg_id} have Midnight evidence"),
+            ));
+        } else if !invalid.contains(rev_reg_id.as_str()) {
+            checked.insert(rev_reg_id.clone());
+        }
+    }
+    (problems, checked)
+}
+
+// Any root the contract held at the evidence's timestamp is accepted, as long as it was read after the request was
+// issued, is still in the contract's root history and, when the policy sets a maximum status age, recent enough.
+// Writes to the registry after the holder read it then do not invalidate the bundle, while a holder revoked before
+// the request cannot answer it with an older root
+fn check_midnight_evidence(
+    ledger: &Ledger,
+    evidence: &MidnightEvidence,
+    binding: &str,
+    issued_at: u64,
+    max_age: Option<u64>,
+    now: u64,
+) -> Result<(), TestError> {
+    let registry = ledger
+        .midnight_rev_regs
+        .get(&RevocationRegistryDefinitionId::new_unchecked(
+            evidence.rev_reg_id.as_str(),
+        ))
+        .ok_or_else(|| TestError("no Midnight registry on the ledger".to_string()))?;
+    if evidence.contract_address != registry.contract_address() {
+        return Err(TestError(format!(
+            "contract {} is not the registry's contract",
+            evidence.contract_address
+        )));
+    }
+    if evidence.timestamp > now {
+        return Err(TestError(format!(
+            "root timestamp {} is in the future",
+            evidence.timestamp
+        )));
+    }
+    if evidence.timestamp < issued_at {
+        return Err(TestError(format!(
+            "root from {} predates the request issued at {issued_at}",
+            evidence.timestamp
+        )));
+    }
+    if let Some(max_age) = max_age
+        && now - evidence.timestamp > max_age
+    {
+        return Err(TestError(format!(
+            "root from {} is older than {max_age}s at {now}",
+            evidence.timestamp
+        )));
+    }
+    if registry.root_at(evidence.timestamp) != Some(evidence.root) {
+        return Err(TestError(format!(
+            "root is not the one the contract held at {}",
+            evidence.timestamp
+        )));
+    }
+    if evidence.siblings.len() != REV_REG_TREE_DEPTH as usize {
+        return Err(TestError(format!(
+            "path has {} siblings, the tree is {REV_REG_TREE_DEPTH} deep",
+            evidence.siblings.len()
+        )));
+    }
+    if evidence.leaf == REVOKED_LEAF {
+        return Err(TestError("path leads to a revoked leaf".to_string()));
+    }
+    if merkle_tree_path_root(&evidence.path()) != evidence.root {
+        return Err(TestError("path does not lead to the root".to_string()));
+    }
+    if persistent_hash(&evidence.holder_key) != evidence.leaf {
+        return Err(TestError("leaf does not commit to the holder key".to_string()));
+    }
+    let signature = from_hex(&evidence.signature)?;
+    verify_holder_signature(&evidence.holder_key, binding.as_bytes(), &signature)
+        .map_err(|e| TestError(format!("binding signature: {}", e.0)))
+}
+
+mod hex32 {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{Bytes32, from_hex, to_hex};
+
+    pub fn serialize<S: Serializer>(value: &Bytes32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes32, D::Error> {
+        let bytes = from_hex(&String::deserialize(deserializer)?).map_err(|e| D::Error::custom(e.0))?;
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::custom("expected 32 bytes of hex"))
+    }
+}
+
+mod hex32_vec {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{Bytes32, from_hex, to_hex};
+
+    pub fn serialize<S: Serializer>(values: &[Bytes32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|value| to_hex(value)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Bytes32>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|value| {
+                from_hex(value)
+                    .map_err(|e| D::Error::custom(e.0))?
+                    .try_into()
+                    .map_err(|_| D::Error::custom("expected 32 bytes of hex"))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::midnight::{MidnightRevReg, generate_issuer_secret_key, holder_commitment, issuer_public_key};
+
+    const REV_REG_ID: &str = "rev_reg";
+
+    fn secret(byte: u8) -> Bytes32 {
+        [byte; 32]
+    }
+
+    // Holders 1 and 2 registered at indices 3 and 4 at time 10
+    fn ledger() -> (Bytes32, Ledger) {
+        let sk = generate_issuer_secret_key();
+        let mut registry = MidnightRevReg::new(issuer_public_key(&sk));
+        let commitments = BTreeMap::from([(3, holder_commitment(&secret(1))), (4, holder_commitment(&secret(2)))]);
+        registry.add_credentials(&sk, &commitments, 10).unwrap();
+        let mut ledger = Ledger::default();
+        ledger
+            .midnight_rev_regs
+            .insert(RevocationRegistryDefinitionId::new_unchecked(REV_REG_ID), registry);
+        (sk, ledger)
+    }
+
+    fn registry(ledger: &mut Ledger) -> &mut MidnightRevReg {
+        ledger
+            .midnight_rev_regs
+            .get_mut(&RevocationRegistryDefinitionId::new_unchecked(REV_REG_ID))
+            .unwrap()
+    }
+
+    // Evidence for the leaf at `index` as the holder of `signer` would give it at `timestamp`
+    fn evidence(ledger: &mut Ledger, index: u32, signer: u8, timestamp: u64) -> MidnightEvidence {
+        let registry = registry(ledger);
+        let path = registry.path_at(index).unwrap();
+        MidnightEvidence {
+            rev_reg_id: REV_REG_ID.to_string(),
+            contract_address: registry.contract_address(),
+            root: registry.root(),
+            timestamp,
+            leaf: path.leaf,
+            index,
+            siblings: path.siblings,
+            holder_key: holder_public_key(&secret(signer)),
+            signature: to_hex(&holder_sign(&secret(signer), b"binding")),
+        }
+    }
+
+    #[test]
+    fn evidence_for_a_replaced_root_is_accepted_while_recent() {
+        let (sk, mut ledger) = ledger();
+        let evidence = evidence(&mut ledger, 3, 1, 12);
+        registry(&mut ledger).revoke_credential(&sk, 4, 20).unwrap();
+
+        check_midnight_evidence(&ledger, &evidence, "binding", 0, None, 25).unwrap();
+        check_midnight_evidence(&ledger, &evidence, "binding", 0, Some(15), 25).unwrap();
+        assert!(check_midnight_evidence(&ledger, &evidence, "binding", 0, Some(5), 25).is_err());
+        assert!(check_midnight_evidence(&ledger, &evidence, "binding", 0, None, 11).is_err());
+    }
+
+    #[test]
+    fn root_must_be_the_one_held_at_the_timestamp() {
+        let (sk, mut ledger) = ledger();
+        registry(&mut ledger).revoke_credential(&sk, 4, 20).unwrap();
+        let mut evidence = evidence(&mut ledger, 3, 1, 15);
+
+        assert!(check_midnight_evidence(&ledger, &evidence, "binding", 0, None, 25).is_err());
+        evidence.timestamp = 20;
+        check_midnight_evidence(&ledger, &evidence, "binding", 0, None, 25).unwrap();
+    }
+
+    #[test]
+    fn roots_read_before_the_request_are_rejected() {
+        let (sk, mut ledger) = ledger();
+        // Holder 2 kept the root from before its revocation and answers a request issued at 25
+        let backdated = evidence(&mut ledger, 4, 2, 15);
+        registry(&mut ledger).revoke_credential(&sk, 4, 20).unwrap();
+        check_midnight_evidence(&ledger, &backdated, "binding", 0, None, 30).unwrap();
+        let error = check_midnight_evidence(&ledger, &backdated, "binding", 25, None, 30).unwrap_err();
+        assert_eq!(error.0, "root from 15 predates the request issued at 25");
+
+        let current = evidence(&mut ledger, 3, 1, 25);
+        check_midnight_evidence(&ledger, &current, "binding", 25, None, 30).unwrap();
+    }
+
+    #[test]
+    fn copied_path_needs_the_key_its_leaf_commits_to() {
+        let (sk, mut ledger) = ledger();
+        // Holder 2 is revoked and presents holder 1's path
+        registry(&mut ledger).revoke_credential(&sk, 4, 10).unwrap();
+        let own_key = evidence(&mut ledger, 3, 2, 10);
+        assert!(check_midnight_evidence(&ledger, &own_key, "binding", 0, None, 10).is_err());
+
+        let mut copied_key = evidence(&mut ledger, 3, 1, 10);
+        copied_key.signature = to_hex(&holder_sign(&secret(2), b"binding"));
+        assert!(check_midnight_evidence(&ledger, &copied_key, "binding", 0, None, 10).is_err());
+
+        let signed_elsewhere = evidence(&mut ledger, 3, 1, 10);
+        assert!(check_midnight_evidence(&ledger, &signed_elsewhere, "other binding", 0, None, 10).is_err());
+        check_midnight_evidence(&ledger, &signed_elsewhere, "binding", 0, None, 10).unwrap();
+    }
+
+    #[test]
+    fn revoked_leaf_has_no_evidence() {
+        let (sk, mut ledger) = ledger();
+        registry(&mut ledger).revoke_credential(&sk, 3, 20).unwrap();
+        let evidence = evidence(&mut ledger, 3, 1, 20);
+
+        assert!(check_midnight_evidence(&ledger, &evidence, "binding", 0, None, 20).is_err());
+    }
+}
@@ -1,242 +1,350 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::{fmt, fs};
 
 use anoncreds::data_types::cred_def::{CredentialDefinition, CredentialDefinitionId};
-use anoncreds::data_types::pres_request::{NonRevokedInterval, PresentationRequestPayload};
+use anoncreds::data_types::issuer_id::IssuerId;
+use anoncreds::data_types::rev_reg_def::RevocationRegistryDefinitionId;
 use anoncreds::data_types::schema::{Schema, SchemaId};
+use anoncreds::issuer;
 use anoncreds::tails::TailsFileWriter;
 use anoncreds::types::{
-    CredentialDefinitionPrivate, CredentialKeyCorrectnessProof, CredentialRevocationState, MakeCredentialValues,
-    PresentCredentials, Presentation, PresentationRequest, RevocationRegistryDefinition,
+    CredentialDefinitionPrivate, CredentialKeyCorrectnessProof, MakeCredentialValues, RevocationRegistryDefinition,
     RevocationRegistryDefinitionPrivate, RevocationStatusList,
 };
 use anoncreds::w3c::types::MakeCredentialAttributes;
-use anoncreds::{issuer, prover, verifier};
-use serde_json::json;
+use serde::{Deserialize, Serialize};
 
-use super::storage::ProverWallet;
+use super::encoding::{AttributeDefinition, AttributeType, encode_attributes};
+use super::mock::TestError;
+use super::query::attr_common_view;
 
-// Government credential related fixtures
-pub const GVT_SCHEMA_NAME: &str = "Government Schema";
-pub const GVT_SCHEMA_ID: &str = "schema:government";
-pub const GVT_SCHEMA_VERSION: &str = "1.0";
-pub const GVT_SCHEMA_ATTRIBUTES: &[&str; 5] = &["id", "name", "age", "sex", "height"];
+// Credential types are described by the JSON files in this directory
+pub const FIXTURES_DIR: &str = "fixtures";
 
-pub const GVT_CRED_DEF_ID: &str = "creddef:government";
-pub const GVT_CRED_DEF_TAG: &str = "govermenttag";
+pub const GVT_CRED: &str = "GVT";
+pub const EMP_CRED: &str = "EMP";
 
-pub const GVT_ISSUER_ID: &str = "issuer:id/path=bar";
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaFixture {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub attributes: Vec<AttributeDefinition>,
+}
 
-pub const GVT_REV_REG_DEF_ID: &str = "revreg:government/id";
-pub const GVT_REV_REG_TAG: &str = "revreggovermenttag";
-pub const GVT_REV_IDX: u32 = 9;
-pub const GVT_REV_MAX_CRED_NUM: u32 = 10;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredDefFixture {
+    pub id: String,
+    pub tag: String,
+}
 
-// Employee credential related fixtures
-pub const EMP_SCHEMA_NAME: &str = "Employee Schema";
-pub const EMP_SCHEMA_ID: &str = "schema:employeebadge";
-pub const EMP_SCHEMA_VERSION: &str = "1.0";
-pub const EMP_SCHEMA_ATTRIBUTES: &[&str; 3] = &["name", "role", "department"];
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevRegFixture {
+    pub id: String,
+    pub tag: String,
+    pub max_cred_num: u32,
+}
 
-pub const EMP_CRED_DEF_ID: &str = "creddef:employee";
-pub const EMP_CRED_DEF_TAG: &str = "employeetag";
+// Everything needed to publish and issue one credential type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialFixture {
+    pub name: String,
+    pub issuer_id: String,
+    pub schema: SchemaFixture,
+    pub cred_def: CredDefFixture,
+    // Absent for credential types that are never revoked
+    pub revocation: Option<RevRegFixture>,
+    // Sample attribute values, attribute name: raw value
+    #[serde(default)]
+    pub values: BTreeMap<String, String>,
+}
 
-pub const EMP_ISSUER_ID: &str = "employer:id/path=bar";
+impl CredentialFixture {
+    pub fn load(path: &Path) -> Result<Self, TestError> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| TestError(format!("Error reading fixture {}: {e}", path.display())))?;
+        let fixture: Self =
+            serde_json::from_str(&json).map_err(|e| TestError(format!("Invalid fixture {}: {e}", path.display())))?;
+        fixture.validate()?;
+        Ok(fixture)
+    }
 
-pub const EMP_REV_REG_DEF_ID: &str = "revreg:employee/id";
-pub const EMP_REV_REG_TAG: &str = "revregemployeetag";
-pub const EMP_REV_IDX: u32 = 9;
-pub const EMP_REV_MAX_CRED_NUM: u32 = 10;
+    fn validate(&self) -> Result<(), TestError> {
+        if self.name.trim().is_empty() {
+            return Err(TestError("Fixture has no name".to_string()));
+        }
+        if self.schema.attributes.is_empty() {
+            return Err(TestError(format!("{}: schema has no attributes", self.name)));
+        }
+        let mut attributes = BTreeSet::new();
+        if let Some(attribute) = self
+            .schema
+            .attributes
+            .iter()
+            .find(|attribute| !attributes.insert(attr_common_view(&attribute.name)))
+        {
+            return Err(TestError(format!(
+                "{}: attribute {} is listed twice",
+                self.name, attribute.name
+            )));
+        }
+        if let Some(revocation) = &self.revocation
+            && revocation.max_cred_num == 0
+        {
+            return Err(TestError(format!("{}: revocation registry has no capacity", self.name)));
+        }
+        if !self.values.is_empty() {
+            self.encode_values(&self.values)
+                .map_err(|e| TestError(format!("{}: sample values: {}", self.name, e.0)))?;
+        }
+        Ok(())
+    }
 
-pub const GVT_CRED: &str = "GVT";
-pub const EMP_CRED: &str = "EMP";
+    pub fn create_schema(&self) -> Result<Schema, TestError> {
+        let attributes: Vec<&str> = self.attribute_names().collect();
+        issuer::create_schema(
+            &self.schema.name,
+            &self.schema.version,
+            self.issuer_id()?,
+            attributes[..].into(),
+        )
+        .map_err(|e| TestError(format!("Error creating {} schema: {e}", self.name)))
+    }
 
-// Create a `GVT` or `EMP` schema
-pub fn create_schema(name: &str) -> (Schema, &str) {
-    match name {
-        GVT_CRED => (
-            issuer::create_schema(
-                GVT_SCHEMA_NAME,
-                GVT_SCHEMA_VERSION,
-                GVT_ISSUER_ID.try_into().unwrap(),
-                GVT_SCHEMA_ATTRIBUTES[..].into(),
-            )
-            .expect("error while creating GVT schema"),
-            GVT_SCHEMA_ID,
-        ),
-        EMP_CRED => (
-            issuer::create_schema(
-                EMP_SCHEMA_NAME,
-                EMP_SCHEMA_VERSION,
-                EMP_ISSUER_ID.try_into().unwrap(),
-                EMP_SCHEMA_ATTRIBUTES[..].into(),
-            )
-            .expect("error while creating EMP schema"),
-            EMP_SCHEMA_ID,
+    pub fn create_cred_def(
+        &self,
+        schema: &Schema,
+        support_revocation: bool,
+    ) -> Result<
+        (
+            CredentialDefinition,
+            CredentialDefinitionPrivate,
+            CredentialKeyCorrectnessProof,
         ),
-        unsupported => panic!("Unsupported schema. {unsupported}"),
+        TestError,
+    > {
+        issuer::create_credential_definition(
+            self.id(SchemaId::try_from(self.schema.id.as_str()))?,
+            schema,
+            self.issuer_id()?,
+            &self.cred_def.tag,
+            anoncreds::types::SignatureType::CL,
+            anoncreds::types::CredentialDefinitionConfig { support_revocation },
+        )
+        .map_err(|e| TestError(format!("Error creating {} cred def: {e}", self.name)))
     }
-}
 
-pub fn create_cred_def(
-    schema: &Schema,
-    support_revocation: bool,
-) -> (
-    (
-        CredentialDefinition,
-        CredentialDefinitionPrivate,
-        CredentialKeyCorrectnessProof,
-    ),
-    &str,
-) {
-    match schema.name.as_str() {
-        GVT_SCHEMA_NAME => (
-            issuer::create_credential_definition(
-                GVT_SCHEMA_ID.try_into().unwrap(),
-                schema,
-                GVT_ISSUER_ID.try_into().unwrap(),
-                GVT_CRED_DEF_TAG,
-                anoncreds::types::SignatureType::CL,
-                anoncreds::types::CredentialDefinitionConfig { support_revocation },
-            )
-            .expect("error while creating GVT cred def"),
-            GVT_CRED_DEF_ID,
-        ),
-        EMP_SCHEMA_NAME => (
-            issuer::create_credential_definition(
-                EMP_SCHEMA_ID.try_into().unwrap(),
-                schema,
-                EMP_ISSUER_ID.try_into().unwrap(),
-                EMP_CRED_DEF_TAG,
-                anoncreds::types::SignatureType::CL,
-                anoncreds::types::CredentialDefinitionConfig { support_revocation },
-            )
-            .expect("error while creating EMP cred def"),
-            EMP_CRED_DEF_ID,
-        ),
-        unsupported => panic!("Unsupported schema name. {unsupported}"),
+    fn issuer_id(&self) -> Result<IssuerId, TestError> {
+        self.id(IssuerId::try_from(self.issuer_id.as_str()))
     }
-}
 
-pub fn create_rev_reg_def<'a>(
-    cred_def: &CredentialDefinition,
-    tf: &mut TailsFileWriter,
-) -> (
-    (RevocationRegistryDefinition, RevocationRegistryDefinitionPrivate),
-    &'a str,
-) {
-    match cred_def.tag.as_str() {
-        GVT_CRED_DEF_TAG => (
-            issuer::create_revocation_registry_def(
-                cred_def,
-                GVT_CRED_DEF_ID.try_into().unwrap(),
-                GVT_REV_REG_TAG,
-                anoncreds::types::RegistryType::CL_ACCUM,
-                GVT_REV_MAX_CRED_NUM,
-                tf,
-            )
-            .expect("Error while creating GVT rev reg"),
-            GVT_REV_REG_DEF_ID,
-        ),
-        EMP_CRED_DEF_TAG => (
-            issuer::create_revocation_registry_def(
-                cred_def,
-                EMP_CRED_DEF_ID.try_into().unwrap(),
-                EMP_REV_REG_TAG,
-                anoncreds::types::RegistryType::CL_ACCUM,
-                EMP_REV_MAX_CRED_NUM,
-                tf,
-            )
-            .expect("Error while creating EMP rev reg"),
-            EMP_REV_REG_DEF_ID,
-        ),
-        unsupported => panic!("Unsupported cred def. {unsupported}"),
+    // Identifiers are checked by anoncreds when they are parsed
+    fn id<T, E: fmt::Display>(&self, parsed: Result<T, E>) -> Result<T, TestError> {
+        parsed.map_err(|e| TestError(format!("{}: invalid identifier: {e}", self.name)))
+    }
+
+    pub fn rev_reg(&self) -> Result<&RevRegFixture, TestError> {
+        self.revocation
+            .as_ref()
+            .ok_or_else(|| TestError(format!("{} has no revocation registry", self.name)))
     }
-}
 
-pub fn create_revocation_status_list(
-    cred_def: &CredentialDefinition,
-    rev_reg_def: &RevocationRegistryDefinition,
-    rev_reg_priv: &RevocationRegistryDefinitionPrivate,
-    time: Option<u64>,
-    issuance_by_default: bool,
-) -> RevocationStatusList {
-    match rev_reg_def.tag.as_str() {
-        GVT_REV_REG_TAG => issuer::create_revocation_status_list(
+    pub fn create_rev_reg_def(
+        &self,
+        cred_def: &CredentialDefinition,
+        tf: &mut TailsFileWriter,
+    ) -> Result<(RevocationRegistryDefinition, RevocationRegistryDefinitionPrivate), TestError> {
+        let rev_reg = self.rev_reg()?;
+        issuer::create_revocation_registry_def(
             cred_def,
-            GVT_REV_REG_DEF_ID.try_into().unwrap(),
-            rev_reg_def,
-            rev_reg_priv,
-            issuance_by_default,
-            time,
+            self.id(CredentialDefinitionId::try_from(self.cred_def.id.as_str()))?,
+            &rev_reg.tag,
+            anoncreds::types::RegistryType::CL_ACCUM,
+            rev_reg.max_cred_num,
+            tf,
         )
-        .expect("Error while creating GVT rev status list"),
-        EMP_REV_REG_TAG => issuer::create_revocation_status_list(
+        .map_err(|e| TestError(format!("Error creating {} rev reg: {e}", self.name)))
+    }
+
+    pub fn create_revocation_status_list(
+        &self,
+        cred_def: &CredentialDefinition,
+        rev_reg_def: &RevocationRegistryDefinition,
+        rev_reg_priv: &RevocationRegistryDefinitionPrivate,
+        time: Option<u64>,
+        issuance_by_default: bool,
+    ) -> Result<RevocationStatusList, TestError> {
+        issuer::create_revocation_status_list(
             cred_def,
-            EMP_REV_REG_DEF_ID.try_into().unwrap(),
+            self.id(RevocationRegistryDefinitionId::try_from(self.rev_reg()?.id.as_str()))?,
             rev_reg_def,
             rev_reg_priv,
             issuance_by_default,
             time,
         )
-        .expect("Error while creating EMP rev status list"),
-        unsupported => panic!("Unsupported rev reg def. {unsupported}"),
+        .map_err(|e| TestError(format!("Error creating {} rev status list: {e}", self.name)))
     }
-}
 
-pub fn credential_values(name: &str) -> MakeCredentialValues {
-    match name {
-        GVT_CRED => {
-            let mut gvt_cred_values = MakeCredentialValues::default();
-            gvt_cred_values
-                .add_raw("id", "example_id")
-                .expect("Error encoding attribute");
-            gvt_cred_values
-                .add_raw("sex", "male")
-                .expect("Error encoding attribute");
-            gvt_cred_values
-                .add_raw("name", "Alex")
-                .expect("Error encoding attribute");
-            gvt_cred_values
-                .add_raw("height", "175")
-                .expect("Error encoding attribute");
-            gvt_cred_values.add_raw("age", "28").expect("Error encoding attribute");
-            gvt_cred_values
+    pub fn attribute_names(&self) -> impl Iterator<Item = &str> {
+        self.schema.attributes.iter().map(|attribute| attribute.name.as_str())
+    }
+
+    pub fn attribute_type(&self, name: &str) -> Option<AttributeType> {
+        let name = attr_common_view(name);
+        self.schema
+            .attributes
+            .iter()
+            .find(|attribute| attr_common_view(&attribute.name) == name)
+            .map(|attribute| attribute.attr_type)
+    }
+
+    // Checks `values` against the schema and returns their canonical raw form
+    pub fn encode_values(&self, values: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, TestError> {
+        encode_attributes(&self.schema.attributes, values)
+    }
+
+    pub fn credential_values_for(&self, values: &BTreeMap<String, String>) -> Result<MakeCredentialValues, TestError> {
+        let mut cred_values = MakeCredentialValues::default();
+        for (name, value) in self.encode_values(values)? {
+            cred_values
+                .add_raw(&name, &value)
+                .map_err(|e| TestError(format!("Error encoding attribute {name}: {e}")))?;
         }
-        EMP_CRED => {
-            let mut emp_cred_values = MakeCredentialValues::default();
-            emp_cred_values
-                .add_raw("name", "John")
-                .expect("Error encoding attribute");
-            emp_cred_values
-                .add_raw("role", "Developer")
-                .expect("Error encoding attribute");
-            emp_cred_values
-                .add_raw("department", "IT")
-                .expect("Error encoding attribute");
-            emp_cred_values
+        Ok(cred_values)
+    }
+
+    // The sample values, validated when the fixture was loaded
+    pub fn credential_values(&self) -> Result<MakeCredentialValues, TestError> {
+        self.credential_values_for(&self.values)
+    }
+
+    pub fn raw_credential_values(&self) -> Result<MakeCredentialAttributes, TestError> {
+        let mut cred_values = MakeCredentialAttributes::default();
+        for (name, value) in self.encode_values(&self.values)?.iter() {
+            cred_values.add(name, value);
         }
-        unsupported => panic!("Unsupported credential values. {unsupported}"),
+        Ok(cred_values)
     }
 }
 
-pub fn raw_credential_values(name: &str) -> MakeCredentialAttributes {
-    match name {
-        GVT_CRED => {
-            let mut gvt_cred_values = MakeCredentialAttributes::default();
-            gvt_cred_values.add("id", "example_id");
-            gvt_cred_values.add("sex", "male");
-            gvt_cred_values.add("name", "Alex");
-            gvt_cred_values.add("height", "175");
-            gvt_cred_values.add("age", "28");
-            gvt_cred_values
+// Every `*.json` fixture in `dir`, by credential type name
+pub fn load_fixtures(dir: &Path) -> Result<BTreeMap<String, CredentialFixture>, TestError> {
+    let entries = fs::read_dir(dir).map_err(|e| TestError(format!("Error reading fixtures {}: {e}", dir.display())))?;
+    let mut fixtures = BTreeMap::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| TestError(format!("Error reading fixtures {}: {e}", dir.display())))?
+            .path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
         }
-        EMP_CRED => {
-            let mut emp_cred_values = MakeCredentialAttributes::default();
-            emp_cred_values.add("name", "John");
-            emp_cred_values.add("role", "Developer");
-            emp_cred_values.add("department", "IT");
-            emp_cred_values
+        let fixture = CredentialFixture::load(&path)?;
+        if let Some(previous) = fixtures.insert(fixture.name.clone(), fixture) {
+            return Err(TestError(format!(
+                "Credential type {} is defined more than once in {}",
+                previous.name,
+                dir.display()
+            )));
         }
-        unsupported => panic!("Unsupported credential values. {unsupported}"),
+    }
+    Ok(fixtures)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::utils::generate_thread_id;
+
+    // A fixture directory holding the given files
+    fn fixture_dir(files: &[(&str, String)]) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join("anoncreds-unit-tests")
+            .join(generate_thread_id());
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn gvt_json() -> Value {
+        serde_json::from_str(&fs::read_to_string(Path::new(FIXTURES_DIR).join("gvt.json")).unwrap()).unwrap()
+    }
+
+    fn rejected(fixture: Value) -> String {
+        let dir = fixture_dir(&[("bad.json", fixture.to_string())]);
+        load_fixtures(&dir).unwrap_err().0
+    }
+
+    #[test]
+    fn loads_the_fixture_directory() {
+        let fixtures = load_fixtures(Path::new(FIXTURES_DIR)).unwrap();
+        assert!(fixtures.contains_key(GVT_CRED));
+        assert!(fixtures.contains_key(EMP_CRED));
+
+        let gvt = &fixtures[GVT_CRED];
+        assert_eq!(gvt.attribute_type("Age"), Some(AttributeType::Integer));
+        assert_eq!(gvt.attribute_type("birth date"), Some(AttributeType::Date));
+        assert_eq!(gvt.attribute_type("nickname"), None);
+        assert!(gvt.rev_reg().is_ok());
+        let values = gvt.encode_values(&gvt.values).unwrap();
+        assert_eq!(values["birthdate"], "9963");
+    }
+
+    #[test]
+    fn other_files_are_ignored() {
+        let dir = fixture_dir(&[
+            ("gvt.json", gvt_json().to_string()),
+            ("README.md", "not a fixture".to_string()),
+        ]);
+        assert_eq!(load_fixtures(&dir).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn malformed_fixtures_are_rejected() {
+        let dir = fixture_dir(&[("bad.json", "{ \"name\": ".to_string())]);
+        assert!(load_fixtures(&dir).unwrap_err().0.starts_with("Invalid fixture"));
+
+        let mut fixture = gvt_json();
+        fixture["schema"]["attributes"] = json!([]);
+        assert_eq!(rejected(fixture), "GVT: schema has no attributes");
+
+        let mut fixture = gvt_json();
+        fixture["schema"]["attributes"][1]["name"] = json!("I D");
+        assert_eq!(rejected(fixture), "GVT: attribute I D is listed twice");
+
+        let mut fixture = gvt_json();
+        fixture["revocation"]["max_cred_num"] = json!(0);
+        assert_eq!(rejected(fixture), "GVT: revocation registry has no capacity");
+
+        let mut fixture = gvt_json();
+        fixture["values"]["age"] = json!("twenty");
+        assert!(rejected(fixture).starts_with("GVT: sample values: Attribute age (integer)"));
+    }
+
+    #[test]
+    fn credential_types_are_defined_once() {
+        let dir = fixture_dir(&[
+            ("gvt.json", gvt_json().to_string()),
+            ("copy.json", gvt_json().to_string()),
+        ]);
+        assert!(
+            load_fixtures(&dir)
+                .unwrap_err()
+                .0
+                .starts_with("Credential type GVT is defined more than once")
+        );
+    }
+
+    #[test]
+    fn missing_revocation_is_an_error() {
+        let mut fixture: CredentialFixture = serde_json::from_value(gvt_json()).unwrap();
+        fixture.revocation = None;
+        assert_eq!(fixture.rev_reg().unwrap_err().0, "GVT has no revocation registry");
     }
 }
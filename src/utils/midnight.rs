@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::mock::TestError;
+use super::vault::to_hex;
+
+// Same tree depth and batch vector size as `revreg.compact`
+pub const REV_REG_TREE_DEPTH: u32 = 4;
+pub const REV_REG_TREE_SIZE: u32 = 1 << REV_REG_TREE_DEPTH;
+pub const REV_REG_BATCH_SIZE: usize = 8;
+// Roots kept after they were replaced. The contract's plain `MerkleTree` only accepts its current root
+pub const ROOT_HISTORY_LEN: usize = 32;
+
+pub type Bytes32 = [u8; 32];
+
+// Leaf written by `revokeCredential`, i.e. `pad(32, "")`
+pub const REVOKED_LEAF: Bytes32 = [0u8; 32];
+
+// Cost of running one or more registry circuits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CircuitCost {
+    pub transactions: u32,
+    pub leaf_writes: u32,
+    pub hash_evaluations: u32,
+}
+
+impl std::ops::Add for CircuitCost {
+    type Output = CircuitCost;
+
+    fn add(self, other: CircuitCost) -> CircuitCost {
+        CircuitCost {
+            transactions: self.transactions + other.transactions,
+            leaf_writes: self.leaf_writes + other.leaf_writes,
+            hash_evaluations: self.hash_evaluations + other.hash_evaluations,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTreePath {
+    pub leaf: Bytes32,
+    pub index: u32,
+    // Sibling hashes from the leaf level up to the root
+    pub siblings: Vec<Bytes32>,
+}
+
+// In-memory simulation of a revocation registry modelled on `revreg.compact`, which it departs from in two ways.
+// Leaves commit to a holder's ed25519 public key rather than to `persistentHash(commitmentSecret)`, and ownership is
+// shown by signing the presentation off-chain, as Compact has no ed25519 verification. Roots stay valid for
+// `ROOT_HISTORY_LEN` writes after they are replaced, where `proofNonRevoked` checks against the current root only
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidnightRevReg {
+    pub issuer_public_key: Bytes32,
+    leaves: Vec<Bytes32>,
+    // Time of the latest write, the current root is held since then
+    #[serde(default)]
+    updated_at: u64,
+    // (root, held since, replaced at) of earlier roots, oldest first
+    #[serde(default)]
+    past_roots: VecDeque<(Bytes32, u64, u64)>,
+}
+
+pub fn issuer_public_key(sk: &Bytes32) -> Bytes32 {
+    Sha256::new()
+        .chain_update(pad32(b"issuer"))
+        .chain_update(sk)
+        .finalize()
+        .into()
+}
+
+pub fn persistent_hash(value: &Bytes32) -> Bytes32 {
+    Sha256::digest(value).into()
+}
+
+// The holder's per-credential secret is an ed25519 key, and the leaf commits to its public half. A verifier
+// can then check that the presenter owns the leaf from a signature, without learning the secret
+pub fn holder_public_key(commitment_secret: &Bytes32) -> Bytes32 {
+    SigningKey::from_bytes(commitment_secret).verifying_key().to_bytes()
+}
+
+pub fn holder_commitment(commitment_secret: &Bytes32) -> Bytes32 {
+    persistent_hash(&holder_public_key(commitment_secret))
+}
+
+pub fn holder_sign(commitment_secret: &Bytes32, message: &[u8]) -> [u8; 64] {
+    SigningKey::from_bytes(commitment_secret).sign(message).to_bytes()
+}
+
+pub fn verify_holder_signature(holder_key: &Bytes32, message: &[u8], signature: &[u8]) -> Result<(), TestError> {
+    let holder_key = VerifyingKey::from_bytes(holder_key).map_err(|_| TestError("invalid holder key".to_string()))?;
+    let signature = Signature::from_slice(signature).map_err(|_| TestError("malformed signature".to_string()))?;
+    holder_key
+        .verify_strict(message, &signature)
+        .map_err(|_| TestError("signature does not verify".to_string()))
+}
+
+pub fn merkle_tree_path_root(path: &MerkleTreePath) -> Bytes32 {
+    let mut node = hash_leaf(&path.leaf);
+    let mut index = path.index;
+    for sibling in path.siblings.iter() {
+        node = if index & 1 == 0 {
+            hash_node(&node, sibling)
+        } else {
+            hash_node(sibling, &node)
+        };
+        index /= 2;
+    }
+    node
+}
+
+fn pad32(value: &[u8]) -> Bytes32 {
+    let mut padded = [0u8; 32];
+    padded[..value.len()].copy_from_slice(value);
+    padded
+}
+
+fn hash_leaf(leaf: &Bytes32) -> Bytes32 {
+    Sha256::new().chain_update([0u8]).chain_update(leaf).finalize().into()
+}
+
+fn hash_node(left: &Bytes32, right: &Bytes32) -> Bytes32 {
+    Sha256::new()
+        .chain_update([1u8])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+impl MidnightRevReg {
+    // Equivalent of the contract constructor
+    pub fn new(issuer_public_key: Bytes32) -> Self {
+        Self {
+            issuer_public_key,
+            leaves: vec![REVOKED_LEAF; REV_REG_TREE_SIZE as usize],
+            updated_at: 0,
+            past_roots: VecDeque::new(),
+        }
+    }
+
+    pub fn root(&self) -> Bytes32 {
+        self.levels().last().unwrap()[0]
+    }
+
+    // The root the contract held at `timestamp`, unless it dropped out of the history
+    pub fn root_at(&self, timestamp: u64) -> Option<Bytes32> {
+        if timestamp >= self.updated_at {
+            return Some(self.root());
+        }
+        self.past_roots
+            .iter()
+            .find(|(_, since, until)| *since <= timestamp && timestamp < *until)
+            .map(|(root, _, _)| *root)
+    }
+
+    // Stands in for the address the contract is deployed at, derived from its owner
+    pub fn contract_address(&self) -> String {
+        let address: Bytes32 = Sha256::new()
+            .chain_update(pad32(b"revreg"))
+            .chain_update(self.issuer_public_key)
+            .finalize()
+            .into();
+        to_hex(&address)
+    }
+
+    pub fn leaf(&self, idx: u32) -> Option<&Bytes32> {
+        self.leaves.get(idx as usize)
+    }
+
+    pub fn find_path_for_leaf(&self, leaf: &Bytes32) -> Option<MerkleTreePath> {
+        let index = self.leaves.iter().position(|l| l == leaf)?;
+        self.path_at(index as u32)
+    }
+
+    // Path of whatever leaf is at `idx`, revoked or not
+    pub fn path_at(&self, idx: u32) -> Option<MerkleTreePath> {
+        let leaf = *self.leaf(idx)?;
+        let levels = self.levels();
+        let mut position = idx as usize;
+        let mut siblings = vec![];
+        for level in levels.iter().take(REV_REG_TREE_DEPTH as usize) {
+            siblings.push(level[position ^ 1]);
+            position /= 2;
+        }
+        Some(MerkleTreePath {
+            leaf,
+            index: idx,
+            siblings,
+        })
+    }
+
+    pub fn add_credential(
+        &mut self,
+        sk: &Bytes32,
+        idx: u32,
+        commitment: Bytes32,
+        now: u64,
+    ) -> Result<CircuitCost, TestError> {
+        self.add_credentials(sk, &BTreeMap::from([(idx, commitment)]), now)
+    }
+
+    pub fn revoke_credential(&mut self, sk: &Bytes32, idx: u32, now: u64) -> Result<CircuitCost, TestError> {
+        self.revoke_credentials(sk, &BTreeSet::from([idx]), now)
+    }
+
+    // Batched `addCredentials` circuit, one transaction per `REV_REG_BATCH_SIZE` entries
+    pub fn add_credentials(
+        &mut self,
+        sk: &Bytes32,
+        commitments: &BTreeMap<u32, Bytes32>,
+        now: u64,
+    ) -> Result<CircuitCost, TestError> {
+        self.check_owner(sk)?;
+        let writes: Vec<(u32, Bytes32)> = commitments.iter().map(|(idx, c)| (*idx, *c)).collect();
+        self.insert_batch(&writes, now)
+    }
+
+    // Batched `revokeCredentials` circuit, one transaction per `REV_REG_BATCH_SIZE` entries
+    pub fn revoke_credentials(
+        &mut self,
+        sk: &Bytes32,
+        indices: &BTreeSet<u32>,
+        now: u64,
+    ) -> Result<CircuitCost, TestError> {
+        self.check_owner(sk)?;
+        let writes: Vec<(u32, Bytes32)> = indices.iter().map(|idx| (*idx, REVOKED_LEAF)).collect();
+        self.insert_batch(&writes, now)
+    }
+
+    // `proofNonRevoked` with this registry's leaves, the secret is the ed25519 key the leaf commits to
+    pub fn proof_non_revoked(&self, path: &MerkleTreePath, commitment_secret: &Bytes32) -> Result<(), TestError> {
+        if path.leaf != holder_commitment(commitment_secret) {
+            return Err(TestError("you are not the holder!!!".to_string()));
+        }
+        if merkle_tree_path_root(path) != self.root() {
+            return Err(TestError("the credential is not valid".to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_owner(&self, sk: &Bytes32) -> Result<(), TestError> {
+        if issuer_public_key(sk) != self.issuer_public_key {
+            return Err(TestError("you are not owner of this contract".to_string()));
+        }
+        Ok(())
+    }
+
+    // Nothing is written unless every index fits the tree and time does not go backwards
+    fn insert_batch(&mut self, writes: &[(u32, Bytes32)], now: u64) -> Result<CircuitCost, TestError> {
+        check_indices(writes.iter().map(|(idx, _)| *idx))?;
+        if now < self.updated_at {
+            return Err(TestError(format!(
+                "write at {now} precedes the latest write at {}",
+                self.updated_at
+            )));
+        }
+
+        // A root replaced within the same second was never observable
+        if now > self.updated_at {
+            self.past_roots.push_back((self.root(), self.updated_at, now));
+            if self.past_roots.len() > ROOT_HISTORY_LEN {
+                self.past_roots.pop_front();
+            }
+        }
+        self.updated_at = now;
+
+        let mut cost = CircuitCost::default();
+        for chunk in writes.chunks(REV_REG_BATCH_SIZE) {
+            for (idx, leaf) in chunk {
+                self.leaves[*idx as usize] = *leaf;
+            }
+            cost = cost + update_cost(chunk.iter().map(|(idx, _)| *idx));
+        }
+        Ok(cost)
+    }
+
+    fn levels(&self) -> Vec<Vec<Bytes32>> {
+        let mut levels = vec![self.leaves.iter().map(hash_leaf).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| hash_node(&pair[0], &pair[1]))
+                .collect();
+            levels.push(next);
+        }
+        levels
+    }
+}
+
+pub fn check_indices(mut indices: impl Iterator<Item = u32>) -> Result<(), TestError> {
+    match indices.find(|idx| *idx >= REV_REG_TREE_SIZE) {
+        Some(idx) => Err(TestError(format!(
+            "index {idx} is out of range for a tree of {REV_REG_TREE_SIZE} leaves"
+        ))),
+        None => Ok(()),
+    }
+}
+
+// A single transaction recomputes every touched leaf and each distinct ancestor once
+fn update_cost(indices: impl Iterator<Item = u32>) -> CircuitCost {
+    let mut touched: BTreeSet<u32> = indices.collect();
+    let leaf_writes = touched.len() as u32;
+    let mut hash_evaluations = leaf_writes;
+    for _ in 0..REV_REG_TREE_DEPTH {
+        touched = touched.iter().map(|idx| idx / 2).collect();
+        hash_evaluations += touched.len() as u32;
+    }
+    CircuitCost {
+        transactions: 1,
+        leaf_writes,
+        hash_evaluations,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchReport {
+    pub indices: BTreeSet<u32>,
+    pub status_list_updates: u32,
+    // `None` when no Midnight registry is deployed for the revocation registry
+    pub merkle: Option<CircuitCost>,
+    pub unbatched_merkle: Option<CircuitCost>,
+}
+
+// What the same writes would cost when submitted one transaction at a time
+pub fn unbatched_cost(count: usize) -> CircuitCost {
+    (0..count).fold(CircuitCost::default(), |cost, _| cost + update_cost(std::iter::once(0)))
+}
+
+pub fn generate_issuer_secret_key() -> Bytes32 {
+    let mut sk = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut sk);
+    sk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> (Bytes32, MidnightRevReg) {
+        let sk = generate_issuer_secret_key();
+        (sk, MidnightRevReg::new(issuer_public_key(&sk)))
+    }
+
+    fn secret(byte: u8) -> Bytes32 {
+        [byte; 32]
+    }
+
+    #[test]
+    fn path_proves_the_leaf_at_its_index() {
+        let (sk, mut registry) = registry();
+        registry
+            .add_credential(&sk, 3, holder_commitment(&secret(1)), 10)
+            .unwrap();
+
+        let path = registry.path_at(3).unwrap();
+        assert_eq!(path.siblings.len(), REV_REG_TREE_DEPTH as usize);
+        assert_eq!(merkle_tree_path_root(&path), registry.root());
+        registry.proof_non_revoked(&path, &secret(1)).unwrap();
+        assert!(registry.proof_non_revoked(&path, &secret(2)).is_err());
+        assert_eq!(registry.find_path_for_leaf(&holder_commitment(&secret(1))), Some(path));
+    }
+
+    #[test]
+    fn revoking_one_credential_keeps_the_others_of_the_holder() {
+        let (sk, mut registry) = registry();
+        let commitments = BTreeMap::from([(3, holder_commitment(&secret(1))), (5, holder_commitment(&secret(2)))]);
+        registry.add_credentials(&sk, &commitments, 10).unwrap();
+        registry.revoke_credential(&sk, 3, 20).unwrap();
+
+        assert_eq!(registry.leaf(3), Some(&REVOKED_LEAF));
+        let revoked = registry.path_at(3).unwrap();
+        assert!(registry.proof_non_revoked(&revoked, &secret(1)).is_err());
+        registry
+            .proof_non_revoked(&registry.path_at(5).unwrap(), &secret(2))
+            .unwrap();
+    }
+
+    #[test]
+    fn stale_path_fails_once_the_root_moved() {
+        let (sk, mut registry) = registry();
+        registry
+            .add_credential(&sk, 1, holder_commitment(&secret(1)), 10)
+            .unwrap();
+        let path = registry.path_at(1).unwrap();
+        registry
+            .add_credential(&sk, 2, holder_commitment(&secret(2)), 20)
+            .unwrap();
+
+        assert!(registry.proof_non_revoked(&path, &secret(1)).is_err());
+    }
+
+    #[test]
+    fn only_the_owner_writes() {
+        let (_, mut registry) = registry();
+        let root = registry.root();
+        assert!(
+            registry
+                .add_credential(&secret(9), 1, holder_commitment(&secret(1)), 10)
+                .is_err()
+        );
+        assert!(registry.revoke_credential(&secret(9), 1, 10).is_err());
+        assert_eq!(registry.root(), root);
+    }
+
+    #[test]
+    fn out_of_range_batch_writes_nothing() {
+        let (sk, mut registry) = registry();
+        let root = registry.root();
+        let commitments = BTreeMap::from([
+            (1, holder_commitment(&secret(1))),
+            (REV_REG_TREE_SIZE, holder_commitment(&secret(2))),
+        ]);
+
+        assert!(registry.add_credentials(&sk, &commitments, 10).is_err());
+        assert_eq!(registry.root_at(0), Some(root));
+        assert_eq!(registry.root(), root);
+        assert_eq!(registry.leaf(1), Some(&REVOKED_LEAF));
+        assert!(check_indices([0, REV_REG_TREE_SIZE - 1].into_iter()).is_ok());
+        assert!(check_indices(std::iter::once(REV_REG_TREE_SIZE)).is_err());
+    }
+
+    #[test]
+    fn batch_costs_less_than_single_writes() {
+        let (sk, mut registry) = registry();
+        let commitments: BTreeMap<u32, Bytes32> = (0..REV_REG_BATCH_SIZE as u32)
+            .map(|idx| (idx, holder_commitment(&secret(idx as u8 + 1))))
+            .collect();
+
+        let batched = registry.add_credentials(&sk, &commitments, 10).unwrap();
+        let unbatched = unbatched_cost(commitments.len());
+        assert_eq!(batched.transactions, 1);
+        assert_eq!(batched.leaf_writes, REV_REG_BATCH_SIZE as u32);
+        // 8 leaves, then 4, 2, 1 and 1 shared ancestors
+        assert_eq!(batched.hash_evaluations, 8 + 4 + 2 + 1 + 1);
+        assert_eq!(unbatched.transactions, REV_REG_BATCH_SIZE as u32);
+        assert_eq!(
+            unbatched.hash_evaluations,
+            REV_REG_BATCH_SIZE as u32 * (1 + REV_REG_TREE_DEPTH)
+        );
+        assert!(batched.hash_evaluations < unbatched.hash_evaluations);
+
+        // Same final tree as writing the leaves one by one
+        let (single_sk, mut single) = (sk, MidnightRevReg::new(registry.issuer_public_key));
+        for (idx, commitment) in commitments.iter() {
+            single.add_credential(&single_sk, *idx, *commitment, 10).unwrap();
+        }
+        assert_eq!(single.root(), registry.root());
+    }
+
+    #[test]
+    fn batches_are_split_into_transactions() {
+        let (sk, mut registry) = registry();
+        let indices: BTreeSet<u32> = (0..REV_REG_BATCH_SIZE as u32 + 2).collect();
+
+        let cost = registry.revoke_credentials(&sk, &indices, 10).unwrap();
+        assert_eq!(cost.transactions, 2);
+        assert_eq!(cost.leaf_writes, indices.len() as u32);
+    }
+
+    #[test]
+    fn replaced_roots_stay_available_for_their_time_window() {
+        let (sk, mut registry) = registry();
+        registry
+            .add_credential(&sk, 1, holder_commitment(&secret(1)), 10)
+            .unwrap();
+        let first = registry.root();
+        registry
+            .add_credential(&sk, 2, holder_commitment(&secret(2)), 20)
+            .unwrap();
+        // Same second, the intermediate root was never observable
+        registry
+            .add_credential(&sk, 3, holder_commitment(&secret(3)), 20)
+            .unwrap();
+
+        assert_eq!(registry.root_at(10), Some(first));
+        assert_eq!(registry.root_at(19), Some(first));
+        assert_eq!(registry.root_at(20), Some(registry.root()));
+        assert_eq!(registry.root_at(99), Some(registry.root()));
+        assert!(registry.revoke_credential(&sk, 1, 15).is_err());
+        assert_eq!(registry.root_at(25), Some(registry.root()));
+    }
+
+    #[test]
+    fn oldest_roots_drop_out_of_the_history() {
+        let (sk, mut registry) = registry();
+        let initial = registry.root();
+        for time in 1..=ROOT_HISTORY_LEN as u64 + 1 {
+            registry
+                .add_credential(&sk, 1, holder_commitment(&secret(time as u8)), time)
+                .unwrap();
+        }
+
+        assert_eq!(registry.root_at(0), None);
+        assert_ne!(registry.root_at(1), Some(initial));
+        assert!(registry.root_at(1).is_some());
+    }
+
+    #[test]
+    fn holder_signature_verifies_only_with_the_committed_key() {
+        let signature = holder_sign(&secret(1), b"binding");
+        let holder_key = holder_public_key(&secret(1));
+
+        assert_eq!(persistent_hash(&holder_key), holder_commitment(&secret(1)));
+        verify_holder_signature(&holder_key, b"binding", &signature).unwrap();
+        assert!(verify_holder_signature(&holder_key, b"other", &signature).is_err());
+        assert!(verify_holder_signature(&holder_public_key(&secret(2)), b"binding", &signature).is_err());
+        assert!(verify_holder_signature(&holder_key, b"binding", &signature[..63]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::create_dir;
 
 use anoncreds::data_types::cred_def::{CredentialDefinition, CredentialDefinitionId};
@@ -10,45 +10,55 @@ use anoncreds::data_types::rev_reg_def::RevocationRegistryDefinitionId;
 use anoncreds::data_types::schema::{Schema, SchemaId};
 use anoncreds::data_types::w3c::VerifiableCredentialSpecVersion;
 use anoncreds::data_types::w3c::credential::W3CCredential;
-use anoncreds::data_types::w3c::credential_attributes::{CredentialAttributeValue, CredentialSubject};
+use anoncreds::data_types::w3c::credential_attributes::CredentialSubject;
 use anoncreds::data_types::w3c::presentation::W3CPresentation;
 use anoncreds::tails::TailsFileWriter;
 use anoncreds::types::{
     CredentialDefinitionConfig, CredentialRequest, CredentialRequestMetadata, CredentialRevocationConfig,
-    CredentialRevocationState, CredentialValues, MakeCredentialValues, PresentCredentials, PresentationRequest,
-    RegistryType, RevocationRegistryDefinition, RevocationStatusList, SignatureType,
+    CredentialRevocationState, CredentialValues, LinkSecret, MakeCredentialValues, PresentCredentials,
+    PresentationRequest, RegistryType, RevocationRegistryDefinition, RevocationStatusList, SignatureType,
 };
 use anoncreds::w3c::credential_conversion::{credential_from_w3c, credential_to_w3c};
 use anoncreds::w3c::types::MakeCredentialAttributes;
 use anoncreds::{issuer, prover, verifier, w3c};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
 
+use super::fixtures::CredentialFixture;
+use super::midnight::{
+    BatchReport, Bytes32, CircuitCost, MidnightRevReg, check_indices, generate_issuer_secret_key, holder_commitment,
+    issuer_public_key, unbatched_cost,
+};
+use super::nonce::unix_now;
 use super::storage::{IssuerWallet, Ledger, ProverWallet, StoredCredDef, StoredRevDef};
-use crate::utils::{VerifierWallet, fixtures};
+use crate::utils::VerifierWallet;
 
 #[derive(Debug)]
-pub struct TestError(String);
+pub struct TestError(pub String);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CredentialFormat {
     Legacy,
     W3C,
 }
 
+// Serialized as a versioned `Envelope`
 #[derive(Debug)]
 pub enum Credentials {
     Legacy(Credential),
     W3C(W3CCredential),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PresentationFormat {
     Legacy,
     W3C,
 }
 
-#[derive(Debug, Serialize)]
+// Serialized as a versioned `Envelope`
+#[derive(Debug)]
 pub enum Presentations {
     Legacy(Presentation),
     W3C(W3CPresentation),
@@ -86,7 +96,7 @@ impl Presentations {
     }
 }
 
-impl<'a> Ledger<'a> {
+impl Ledger {
     pub fn add_schema(&mut self, schema_id: &str, schema: &Schema) {
         let schema_id = SchemaId::new_unchecked(schema_id);
         self.schemas.insert(schema_id, schema.clone());
@@ -102,6 +112,33 @@ impl<'a> Ledger<'a> {
         self.rev_reg_defs.insert(rev_reg_def_id, rev_reg_def.clone());
     }
 
+    pub fn add_rev_status_list(
+        &mut self,
+        rev_reg_def_id: &str,
+        timestamp: u64,
+        rev_status_list: &RevocationStatusList,
+    ) {
+        let rev_reg_def_id = RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id);
+        self.revocation_list
+            .entry(rev_reg_def_id)
+            .or_default()
+            .insert(timestamp, rev_status_list.clone());
+    }
+
+    pub fn resolve_rev_status_list(&self, rev_reg_def_id: &str, timestamp: u64) -> Option<&RevocationStatusList> {
+        let rev_reg_def_id = RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id);
+        self.revocation_list.get(&rev_reg_def_id)?.get(&timestamp)
+    }
+
+    pub fn latest_rev_status_list(&self, rev_reg_def_id: &str) -> Option<(u64, &RevocationStatusList)> {
+        let rev_reg_def_id = RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id);
+        self.revocation_list
+            .get(&rev_reg_def_id)?
+            .iter()
+            .next_back()
+            .map(|(timestamp, list)| (*timestamp, list))
+    }
+
     pub fn resolve_schemas(&self, schema_ids: Vec<&str>) -> HashMap<SchemaId, Schema> {
         let mut schemas = HashMap::new();
         for schema_id in schema_ids {
@@ -137,52 +174,63 @@ impl<'a> Ledger<'a> {
 }
 
 impl IssuerWallet {
-    pub fn create_schema(&self, ledger: &mut Ledger, name: &str) -> (Schema, String) {
-        let (schema, schema_id) = fixtures::create_schema(name);
-        ledger.add_schema(schema_id, &schema);
-        (schema, schema_id.to_string())
+    pub fn create_schema(
+        &self,
+        ledger: &mut Ledger,
+        fixture: &CredentialFixture,
+    ) -> Result<(Schema, String), TestError> {
+        let schema = fixture.create_schema()?;
+        ledger.add_schema(&fixture.schema.id, &schema);
+        Ok((schema, fixture.schema.id.clone()))
     }
 
     pub fn create_cred_def(
         &mut self,
         ledger: &mut Ledger,
+        fixture: &CredentialFixture,
         schema: &Schema,
         support_revocation: bool,
-    ) -> (CredentialDefinition, String) {
-        let ((cred_def, cred_def_priv, cred_key_correctness_proof), cred_def_id) =
-            fixtures::create_cred_def(schema, support_revocation);
+    ) -> Result<(CredentialDefinition, String), TestError> {
+        let (cred_def, cred_def_priv, cred_key_correctness_proof) =
+            fixture.create_cred_def(schema, support_revocation)?;
+        let cred_def_id = fixture.cred_def.id.as_str();
+        let public = cred_def
+            .try_clone()
+            .map_err(|e| TestError(format!("Error cloning cred def {cred_def_id}: {e}")))?;
         ledger.add_cred_def(cred_def_id, &cred_def);
         self.cred_defs.insert(
             cred_def_id.to_string(),
             StoredCredDef {
-                public: cred_def.try_clone().unwrap(),
+                public,
                 private: cred_def_priv,
                 key_proof: cred_key_correctness_proof,
             },
         );
-        (cred_def, cred_def_id.to_string())
+        Ok((cred_def, cred_def_id.to_string()))
     }
 
-    pub fn create_revocation_registry<'b>(
+    pub fn create_revocation_registry(
         &mut self,
         ledger: &mut Ledger,
+        fixture: &CredentialFixture,
         cred_def: &CredentialDefinition,
         time: Option<u64>,
         issuance_by_default: bool,
-    ) -> (String, RevocationRegistryDefinition, RevocationStatusList) {
+    ) -> Result<(String, RevocationRegistryDefinition, RevocationStatusList), TestError> {
         // Create tails file writer
         let mut tf = TailsFileWriter::new(None);
 
-        let ((rev_reg_def, rev_reg_def_priv), rev_reg_def_id) = fixtures::create_rev_reg_def(cred_def, &mut tf);
+        let (rev_reg_def, rev_reg_def_priv) = fixture.create_rev_reg_def(cred_def, &mut tf)?;
+        let rev_reg_def_id = fixture.rev_reg()?.id.as_str();
 
         // Issuer creates revocation status list - to be put on the ledger
-        let revocation_status_list = fixtures::create_revocation_status_list(
+        let revocation_status_list = fixture.create_revocation_status_list(
             cred_def,
             &rev_reg_def,
             &rev_reg_def_priv,
             time,
             issuance_by_default,
-        );
+        )?;
 
         self.rev_defs.insert(
             rev_reg_def_id.to_string(),
@@ -193,8 +241,11 @@ impl IssuerWallet {
         );
 
         ledger.add_rev_reg_def(rev_reg_def_id, &rev_reg_def);
+        if let Some(time) = time {
+            ledger.add_rev_status_list(rev_reg_def_id, time, &revocation_status_list);
+        }
 
-        (rev_reg_def_id.to_string(), rev_reg_def, revocation_status_list)
+        Ok((rev_reg_def_id.to_string(), rev_reg_def, revocation_status_list))
     }
 
     pub fn create_credential_offer(&self, schema_id: &str, cred_def_id: &str) -> CredentialOffer {
@@ -222,25 +273,22 @@ impl IssuerWallet {
         revocation_status_list: Option<&RevocationStatusList>,
         credential_rev_index: Option<u32>,
         version: Option<VerifiableCredentialSpecVersion>,
-    ) -> Credentials {
+    ) -> Result<Credentials, TestError> {
         let cred_def_record = &self
             .cred_defs
             .get(cred_def_id)
-            .expect("Credential Definition not found");
+            .ok_or_else(|| TestError(format!("Credential Definition {cred_def_id} not found")))?;
         let cred_def_private = &cred_def_record.private;
         let cred_def = &cred_def_record.public;
 
-        let revocation_config = match rev_reg_def_id {
-            Some(rev_reg_def_id) => {
-                self.rev_defs
-                    .get(rev_reg_def_id)
-                    .map(|stored_rev_def| CredentialRevocationConfig {
-                        reg_def: &stored_rev_def.public,
-                        reg_def_private: &stored_rev_def.private,
-                        registry_idx: credential_rev_index.expect("Credential Revocation Index must be provided"),
-                        status_list: revocation_status_list.expect("Missing status list"),
-                    })
-            }
+        let revocation_config = match rev_reg_def_id.and_then(|id| self.rev_defs.get(id)) {
+            Some(stored_rev_def) => Some(CredentialRevocationConfig {
+                reg_def: &stored_rev_def.public,
+                reg_def_private: &stored_rev_def.private,
+                registry_idx: credential_rev_index
+                    .ok_or_else(|| TestError("Credential Revocation Index must be provided".to_string()))?,
+                status_list: revocation_status_list.ok_or_else(|| TestError("Missing status list".to_string()))?,
+            }),
             None => None,
         };
 
@@ -254,7 +302,7 @@ impl IssuerWallet {
                     cred_values,
                     revocation_config,
                 )
-                .expect("Error creating credential");
+                .map_err(|e| TestError(format!("Error creating credential: {e}")))?;
                 Credentials::Legacy(issue_cred)
             }
             CredentialFormat::W3C => {
@@ -263,16 +311,17 @@ impl IssuerWallet {
                     cred_def_private,
                     &cred_offer,
                     &cred_request,
-                    CredentialSubject::try_from(&cred_values).expect("Error generating credential attributes"),
+                    CredentialSubject::try_from(&cred_values)
+                        .map_err(|e| TestError(format!("Error generating credential attributes: {e}")))?,
                     revocation_config,
                     version,
                 )
-                .expect("Error creating credential");
+                .map_err(|e| TestError(format!("Error creating credential: {e}")))?;
                 Credentials::W3C(issue_cred)
             }
         };
 
-        credential
+        Ok(credential)
     }
 
     pub fn update_revocation_status_list(
@@ -283,11 +332,11 @@ impl IssuerWallet {
         issued: Option<BTreeSet<u32>>,
         revoked: Option<BTreeSet<u32>>,
         timestamp: Option<u64>,
-    ) -> RevocationStatusList {
+    ) -> Result<RevocationStatusList, TestError> {
         let rev_reg = self
             .rev_defs
             .get(rev_reg_def_id)
-            .expect("Revocation Registry Definition not found");
+            .ok_or_else(|| TestError(format!("Issuer has no revocation registry {rev_reg_def_id}")))?;
         issuer::update_revocation_status_list(
             cred_def,
             &rev_reg.public,
@@ -297,25 +346,195 @@ impl IssuerWallet {
             revoked,
             timestamp,
         )
-        .unwrap()
+        .map_err(|e| TestError(format!("Error updating status list of {rev_reg_def_id}: {e}")))
+    }
+
+    // Every index of the revocation registry needs a leaf in the fixed size tree
+    pub fn deploy_midnight_registry(&mut self, ledger: &mut Ledger, rev_reg_def_id: &str) -> Result<(), TestError> {
+        let rev_reg_def = ledger
+            .rev_reg_defs
+            .get(&RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id))
+            .ok_or_else(|| TestError(format!("RevRegDef {rev_reg_def_id} not found")))?;
+        check_indices(std::iter::once(rev_reg_def.value.max_cred_num))
+            .map_err(|e| TestError(format!("{rev_reg_def_id} cannot use Midnight: {}", e.0)))?;
+
+        let sk = generate_issuer_secret_key();
+        ledger.midnight_rev_regs.insert(
+            RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id),
+            MidnightRevReg::new(issuer_public_key(&sk)),
+        );
+        self.midnight_keys.insert(rev_reg_def_id.to_string(), sk);
+        Ok(())
+    }
+
+    // Registers many indices with one status list update and one Merkle batch update. The status list is only
+    // published once the Merkle update went through, so a rejected batch leaves the ledger unchanged
+    pub fn issue_credentials_batch(
+        &self,
+        ledger: &mut Ledger,
+        cred_def: &CredentialDefinition,
+        rev_reg_def_id: &str,
+        current_list: &RevocationStatusList,
+        commitments: &BTreeMap<u32, Bytes32>,
+        timestamp: Option<u64>,
+    ) -> Result<(RevocationStatusList, BatchReport), TestError> {
+        let indices: BTreeSet<u32> = commitments.keys().copied().collect();
+        if indices.is_empty() {
+            return Err(TestError("Batch must contain at least one index".to_string()));
+        }
+
+        let status_list = self.update_revocation_status_list(
+            cred_def,
+            rev_reg_def_id,
+            current_list,
+            Some(indices.clone()),
+            None,
+            timestamp,
+        )?;
+
+        let rev_reg_id = RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id);
+        let merkle = match (
+            self.midnight_keys.get(rev_reg_def_id),
+            ledger.midnight_rev_regs.get_mut(&rev_reg_id),
+        ) {
+            (Some(sk), Some(registry)) => Some(
+                registry
+                    .add_credentials(sk, commitments, timestamp.unwrap_or_else(unix_now))
+                    .map_err(|e| TestError(format!("Error registering credentials on Midnight: {}", e.0)))?,
+            ),
+            _ => None,
+        };
+
+        if let Some(timestamp) = timestamp {
+            ledger.add_rev_status_list(rev_reg_def_id, timestamp, &status_list);
+        }
+        Ok((status_list, batch_report(indices, merkle)))
+    }
+
+    // Revokes many indices with one status list update and one Merkle batch update
+    pub fn revoke_credentials_batch(
+        &self,
+        ledger: &mut Ledger,
+        cred_def: &CredentialDefinition,
+        rev_reg_def_id: &str,
+        current_list: &RevocationStatusList,
+        indices: &BTreeSet<u32>,
+        timestamp: Option<u64>,
+    ) -> Result<(RevocationStatusList, BatchReport), TestError> {
+        if indices.is_empty() {
+            return Err(TestError("Batch must contain at least one index".to_string()));
+        }
+
+        let status_list = self.update_revocation_status_list(
+            cred_def,
+            rev_reg_def_id,
+            current_list,
+            None,
+            Some(indices.clone()),
+            timestamp,
+        )?;
+
+        let rev_reg_id = RevocationRegistryDefinitionId::new_unchecked(rev_reg_def_id);
+        let merkle = match (
+            self.midnight_keys.get(rev_reg_def_id),
+            ledger.midnight_rev_regs.get_mut(&rev_reg_id),
+        ) {
+            (Some(sk), Some(registry)) => Some(
+                registry
+                    .revoke_credentials(sk, indices, timestamp.unwrap_or_else(unix_now))
+                    .map_err(|e| TestError(format!("Error revoking credentials on Midnight: {}", e.0)))?,
+            ),
+            _ => None,
+        };
+
+        if let Some(timestamp) = timestamp {
+            ledger.add_rev_status_list(rev_reg_def_id, timestamp, &status_list);
+        }
+        Ok((status_list, batch_report(indices.clone(), merkle)))
     }
 }
 
-impl<'a> ProverWallet<'a> {
+fn batch_report(indices: BTreeSet<u32>, merkle: Option<CircuitCost>) -> BatchReport {
+    let unbatched_merkle = merkle.map(|_| unbatched_cost(indices.len()));
+    BatchReport {
+        indices,
+        status_list_updates: 1,
+        merkle,
+        unbatched_merkle,
+    }
+}
+
+impl ProverWallet {
+    pub fn link_secret(&self, id: &str) -> Result<&LinkSecret, TestError> {
+        self.link_secrets
+            .get(id)
+            .ok_or_else(|| TestError(format!("Link secret `{id}` not found")))
+    }
+
+    pub fn create_link_secret(&mut self, id: &str) -> Result<(), TestError> {
+        if self.link_secrets.contains_key(id) {
+            return Err(TestError(format!("Link secret `{id}` already exists")));
+        }
+        let link_secret = LinkSecret::new().map_err(|e| TestError(e.to_string()))?;
+        self.link_secrets.insert(id.to_string(), link_secret);
+        Ok(())
+    }
+
+    pub fn set_default_link_secret(&mut self, id: &str) -> Result<(), TestError> {
+        self.link_secret(id)?;
+        self.default_link_secret = id.to_string();
+        Ok(())
+    }
+
+    // Holder signing key of the credential requested in `thread_id`, and the commitment to its public key the
+    // issuer registers in the Midnight tree. Each credential gets its own leaf, so revoking one does not leave a
+    // path through another
+    pub fn midnight_commitment(&self, thread_id: &str) -> Result<(Bytes32, Bytes32), TestError> {
+        let (_, metadata) = self
+            .cred_reqs
+            .get(thread_id)
+            .ok_or_else(|| TestError(format!("No credential request in session {thread_id}")))?;
+        self.commitment_for(&metadata.link_secret_name, thread_id)
+    }
+
+    // Derived rather than stored until the credential arrives, so an interrupted session needs no extra state
+    pub(crate) fn commitment_for(
+        &self,
+        link_secret_id: &str,
+        thread_id: &str,
+    ) -> Result<(Bytes32, Bytes32), TestError> {
+        let link_secret: Zeroizing<String> = Zeroizing::new(
+            self.link_secret(link_secret_id)?
+                .try_clone()
+                .and_then(|link_secret| link_secret.try_into())
+                .map_err(|e| TestError(e.to_string()))?,
+        );
+        let commitment_secret: Bytes32 = Sha256::new()
+            .chain_update(b"midnight-commitment")
+            .chain_update(link_secret.as_bytes())
+            .chain_update(thread_id.as_bytes())
+            .finalize()
+            .into();
+        Ok((commitment_secret, holder_commitment(&commitment_secret)))
+    }
+
+    // The credential is bound to `link_secret_id`, or to the default link secret when `None`
     pub fn create_credential_request(
         &self,
         cred_def: &CredentialDefinition,
         credential_offer: &CredentialOffer,
-    ) -> (CredentialRequest, CredentialRequestMetadata) {
+        link_secret_id: Option<&str>,
+    ) -> Result<(CredentialRequest, CredentialRequestMetadata), TestError> {
+        let link_secret_id = link_secret_id.unwrap_or(&self.default_link_secret);
         prover::create_credential_request(
-            Some(self.entropy),
+            Some(&offer_entropy(credential_offer)?),
             None,
             cred_def,
-            &self.link_secret,
-            &self.link_secret_id,
+            self.link_secret(link_secret_id)?,
+            link_secret_id,
             credential_offer,
         )
-        .expect("Error creating credential request")
+        .map_err(|e| TestError(format!("Error creating credential request: {e}")))
     }
 
     pub fn store_credential(
@@ -325,31 +544,47 @@ impl<'a> ProverWallet<'a> {
         cred_request_metadata: &CredentialRequestMetadata,
         cred_def: &CredentialDefinition,
         rev_reg_def: Option<&RevocationRegistryDefinition>,
-    ) {
+    ) -> Result<(), TestError> {
+        let link_secret_id = cred_request_metadata.link_secret_name.clone();
+        let link_secret = self.link_secret(&link_secret_id)?;
         match credential {
             Credentials::Legacy(credential) => {
-                prover::process_credential(
-                    credential,
-                    cred_request_metadata,
-                    &self.link_secret,
-                    cred_def,
-                    rev_reg_def,
-                )
-                .expect("Error processing credential");
-                self.credentials.insert(id.to_string(), credential.try_clone().unwrap());
+                prover::process_credential(credential, cred_request_metadata, link_secret, cred_def, rev_reg_def)
+                    .map_err(|e| TestError(format!("Error processing credential: {e}")))?;
+                let credential = credential
+                    .try_clone()
+                    .map_err(|e| TestError(format!("Error copying credential: {e}")))?;
+                self.credentials.insert(id.to_string(), credential);
             }
             Credentials::W3C(credential) => {
-                w3c::prover::process_credential(
-                    credential,
-                    cred_request_metadata,
-                    &self.link_secret,
-                    cred_def,
-                    rev_reg_def,
-                )
-                .expect("Error processing credential");
+                w3c::prover::process_credential(credential, cred_request_metadata, link_secret, cred_def, rev_reg_def)
+                    .map_err(|e| TestError(format!("Error processing credential: {e}")))?;
                 self.w3c_credentials.insert(id.to_string(), credential.clone());
             }
         }
+        self.credential_link_secrets.insert(id.to_string(), link_secret_id);
+        Ok(())
+    }
+
+    // All credentials of a presentation have to be bound to the same link secret
+    fn presentation_link_secret(&self, present_credentials: &[CredentialToPresent]) -> Result<&LinkSecret, TestError> {
+        let mut link_secret_ids: Vec<&String> = present_credentials
+            .iter()
+            .map(|present| {
+                self.credential_link_secrets
+                    .get(&present.id)
+                    .unwrap_or(&self.default_link_secret)
+            })
+            .collect();
+        link_secret_ids.sort();
+        link_secret_ids.dedup();
+        match link_secret_ids.as_slice() {
+            [] => self.link_secret(&self.default_link_secret),
+            [id] => self.link_secret(id),
+            _ => Err(TestError(
+                "Credentials bound to different link secrets cannot be presented together".to_string(),
+            )),
+        }
     }
 
     pub fn create_or_update_revocation_state(
@@ -360,16 +595,75 @@ impl<'a> ProverWallet<'a> {
         rev_reg_idx: u32,
         rev_state: Option<&CredentialRevocationState>,
         old_rev_status_list: Option<&RevocationStatusList>,
-    ) -> CredentialRevocationState {
+    ) -> Result<CredentialRevocationState, TestError> {
         prover::create_or_update_revocation_state(
             tails_location,
-            &rev_reg_def,
+            rev_reg_def,
             rev_status_list,
             rev_reg_idx,
             rev_state,
             old_rev_status_list,
         )
-        .expect("Error creating revocation state")
+        .map_err(|e| TestError(format!("Error creating revocation state: {e}")))
+    }
+
+    // Brings the witness of every held revocable credential up to the latest status list on the ledger. Witnesses
+    // are per index, so each credential keeps its own, even next to others of the same registry. States refreshed
+    // before an error are kept
+    pub fn refresh_revocation_states(&mut self, ledger: &Ledger) -> Result<Vec<(String, u64)>, TestError> {
+        let revocable: BTreeMap<String, (String, u32)> = self
+            .credentials
+            .iter()
+            .filter_map(|(id, credential)| {
+                Some((id.clone(), (credential.rev_reg_id()?.0, credential.rev_reg_index()?)))
+            })
+            .chain(self.w3c_credentials.iter().filter_map(|(id, credential)| {
+                Some((id.clone(), (credential.rev_reg_id()?.0, credential.rev_reg_index()?)))
+            }))
+            .collect();
+        self.rev_states.retain(|id, _| revocable.contains_key(id));
+
+        let mut refreshed = vec![];
+        for (credential_id, (rev_reg_id, rev_reg_idx)) in revocable {
+            let Some((timestamp, rev_status_list)) = ledger.latest_rev_status_list(&rev_reg_id) else {
+                continue;
+            };
+            let (rev_state, known_timestamp) = self.rev_states.get(&credential_id).cloned().unwrap_or((None, None));
+            if rev_state.is_some() && known_timestamp == Some(timestamp) {
+                continue;
+            }
+
+            let rev_reg_def = ledger
+                .rev_reg_defs
+                .get(&RevocationRegistryDefinitionId::new_unchecked(rev_reg_id.as_str()))
+                .ok_or_else(|| TestError(format!("No revocation registry definition {rev_reg_id} on the ledger")))?;
+
+            // The witness is updated incrementally when the previously used list is still known
+            let old_rev_status_list = known_timestamp.and_then(|t| ledger.resolve_rev_status_list(&rev_reg_id, t));
+            let rev_state = match (rev_state, old_rev_status_list) {
+                (Some(rev_state), Some(old_rev_status_list)) => self.create_or_update_revocation_state(
+                    &rev_reg_def.value.tails_location,
+                    rev_reg_def,
+                    rev_status_list,
+                    rev_reg_idx,
+                    Some(&rev_state),
+                    Some(old_rev_status_list),
+                )?,
+                _ => self.create_or_update_revocation_state(
+                    &rev_reg_def.value.tails_location,
+                    rev_reg_def,
+                    rev_status_list,
+                    rev_reg_idx,
+                    None,
+                    None,
+                )?,
+            };
+
+            self.rev_states
+                .insert(credential_id.clone(), (Some(rev_state), Some(timestamp)));
+            refreshed.push((credential_id, timestamp));
+        }
+        Ok(refreshed)
     }
 
     pub fn prepare_credentials_to_present<'b, T: RevocableCredential>(
@@ -382,11 +676,7 @@ impl<'a> ProverWallet<'a> {
         for present_credential in present_credentials.iter() {
             let credential = credentials.get(&present_credential.id).expect("Credential not found");
 
-            let (rev_state, timestamp) = if let Some(id) = &credential.rev_reg_id() {
-                self.rev_states.get(&id.0).unwrap()
-            } else {
-                &(None, None)
-            };
+            let (rev_state, timestamp) = self.rev_states.get(&present_credential.id).unwrap_or(&(None, None));
 
             let mut cred = present.add_credential(credential, *timestamp, rev_state.as_ref());
             for data in present_credential.attributes.iter() {
@@ -415,7 +705,7 @@ impl<'a> ProverWallet<'a> {
         present_credentials: &Vec<CredentialToPresent>,
         self_attested_credentials: Option<HashMap<String, String>>,
         version: Option<VerifiableCredentialSpecVersion>,
-    ) -> Presentations {
+    ) -> Result<Presentations, TestError> {
         match format {
             PresentationFormat::Legacy => {
                 let present = self.prepare_credentials_to_present(&self.credentials, present_credentials);
@@ -423,25 +713,34 @@ impl<'a> ProverWallet<'a> {
                     pres_request,
                     present,
                     self_attested_credentials,
-                    &self.link_secret,
+                    self.presentation_link_secret(present_credentials)?,
                     schemas,
                     cred_defs,
                 )
-                .expect("Error creating presentation");
-                Presentations::Legacy(presentation)
+                .map_err(|e| TestError(format!("Error creating presentation: {e}")))?;
+                Ok(Presentations::Legacy(presentation))
             }
             PresentationFormat::W3C => {
+                // W3C presentations only carry credential-backed claims
+                if let Some(referents) = self_attested_credentials.filter(|attested| !attested.is_empty()) {
+                    let mut referents: Vec<String> = referents.into_keys().collect();
+                    referents.sort();
+                    return Err(TestError(format!(
+                        "W3C presentations cannot carry self-attested attributes ({}), use the legacy format",
+                        referents.join(", ")
+                    )));
+                }
                 let present = self.prepare_credentials_to_present(&self.w3c_credentials, present_credentials);
                 let presentation = w3c::prover::create_presentation(
                     pres_request,
                     present,
-                    &self.link_secret,
+                    self.presentation_link_secret(present_credentials)?,
                     schemas,
                     cred_defs,
                     version,
                 )
-                .expect("Error creating presentation");
-                Presentations::W3C(presentation)
+                .map_err(|e| TestError(format!("Error creating presentation: {e}")))?;
+                Ok(Presentations::W3C(presentation))
             }
         }
     }
@@ -468,175 +767,58 @@ impl<'a> ProverWallet<'a> {
     }
 }
 
-impl VerifierWallet {
-    pub fn generate_nonce(&self) -> Nonce {
-        verifier::generate_nonce().expect("Error generating presentation request nonce")
+// Requests carry the offer's nonce as their entropy, which anoncreds signs into the credential, so the issuer can
+// tell which offer a request answers
+pub(crate) fn offer_entropy(offer: &CredentialOffer) -> Result<String, TestError> {
+    match serde_json::to_value(&offer.nonce) {
+        Ok(serde_json::Value::String(nonce)) => Ok(nonce),
+        _ => Err(TestError("Offer nonce is not a string".to_string())),
     }
+}
 
-    pub fn verify_presentation(
-        &self,
-        presentation: &Presentations,
-        pres_req: &PresentationRequest,
-        schemas: &HashMap<SchemaId, Schema>,
-        cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
-        rev_reg_defs: Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>>,
-        rev_status_lists: Option<Vec<RevocationStatusList>>,
-        nonrevoke_interval_override: Option<&HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>>>,
-    ) -> Result<bool, TestError> {
-        match presentation {
-            Presentations::Legacy(presentation) => verifier::verify_presentation(
-                presentation,
-                pres_req,
-                schemas,
-                cred_defs,
-                rev_reg_defs,
-                rev_status_lists,
-                nonrevoke_interval_override,
-            )
-            .map_err(|e| TestError(e.to_string())),
-            Presentations::W3C(presentation) => w3c::verifier::verify_presentation(
-                presentation,
-                pres_req,
-                schemas,
-                cred_defs,
-                rev_reg_defs,
-                rev_status_lists,
-                nonrevoke_interval_override,
-            )
-            .map_err(|e| TestError(e.to_string())),
-        }
+impl VerifierWallet {
+    // The nonce is remembered until it expires or a presentation answering it is verified
+    pub fn generate_nonce(&mut self) -> Nonce {
+        self.generate_nonce_at(unix_now())
     }
 
-    pub fn check_presentation_attribute(&self, presentation: &Presentations, attribute: PresentedAttribute) {
-        match presentation {
-            Presentations::Legacy(presentation) => {
-                match attribute.expected {
-                    ExpectedAttributeValue::RevealedAttribute(expected) => {
-                        assert_eq!(
-                            expected.to_string(),
-                            presentation
-                                .requested_proof
-                                .revealed_attrs
-                                .get(attribute.referent)
-                                .unwrap()
-                                .raw
-                        );
-                    }
-                    ExpectedAttributeValue::GroupedAttribute(expected) => {
-                        let revealed_attr_groups = presentation
-                            .requested_proof
-                            .revealed_attr_groups
-                            .get(attribute.referent)
-                            .unwrap();
-                        assert_eq!(
-                            expected.to_string(),
-                            revealed_attr_groups.values.get(attribute.name).unwrap().raw
-                        );
-                    }
-                    ExpectedAttributeValue::UnrevealedAttribute(expected) => {
-                        assert_eq!(
-                            expected,
-                            presentation
-                                .requested_proof
-                                .unrevealed_attrs
-                                .get(attribute.referent)
-                                .unwrap()
-                                .sub_proof_index
-                        );
-                    }
-                    ExpectedAttributeValue::Predicate => {
-                        presentation.requested_proof.predicates.get(attribute.referent).unwrap();
-                    }
-                };
-            }
-            Presentations::W3C(presentation) => {
-                match attribute.expected {
-                    ExpectedAttributeValue::RevealedAttribute(expected)
-                    | ExpectedAttributeValue::GroupedAttribute(expected) => {
-                        let credential = presentation
-                            .verifiable_credential
-                            .iter()
-                            .find(|credential| {
-                                credential
-                                    .credential_subject
-                                    .0
-                                    .contains_key(&attribute.name.to_lowercase())
-                            })
-                            .unwrap();
-
-                        assert_eq!(
-                            expected,
-                            credential
-                                .credential_subject
-                                .0
-                                .get(&attribute.name.to_lowercase())
-                                .unwrap()
-                                .clone()
-                        );
-                    }
-                    ExpectedAttributeValue::UnrevealedAttribute(expected) => {
-                        // not checking here
-                    }
-                    ExpectedAttributeValue::Predicate => {
-                        let credential = presentation
-                            .verifiable_credential
-                            .iter()
-                            .find(|credential| {
-                                credential
-                                    .credential_subject
-                                    .0
-                                    .contains_key(&attribute.name.to_lowercase())
-                            })
-                            .unwrap();
-                        credential
-                            .credential_subject
-                            .0
-                            .get(&attribute.name.to_lowercase())
-                            .unwrap();
-                    }
-                };
-            }
-        }
+    pub fn generate_nonce_at(&mut self, now: u64) -> Nonce {
+        self.nonces.issue(now)
     }
 }
 
+#[derive(Debug)]
 pub struct CredentialToPresent {
     pub id: String,
     pub attributes: Vec<PresentAttribute>,
 }
 
+#[derive(Debug)]
 pub struct PresentAttribute {
     pub referent: String,
     pub form: PresentAttributeForm,
 }
 
+#[derive(Debug)]
 pub enum PresentAttributeForm {
     RevealedAttribute,
     UnrevealedAttribute,
     Predicate,
 }
 
-pub struct PresentedAttribute<'a> {
-    pub referent: &'a str,
-    pub name: &'a str,
-    pub expected: ExpectedAttributeValue,
-}
-
-pub enum ExpectedAttributeValue {
-    RevealedAttribute(CredentialAttributeValue),
-    UnrevealedAttribute(u32),
-    GroupedAttribute(CredentialAttributeValue),
-    Predicate,
-}
-
 pub trait RevocableCredential {
     fn rev_reg_id(&self) -> Option<RevocationRegistryDefinitionId>;
+    fn rev_reg_index(&self) -> Option<u32>;
 }
 
 impl RevocableCredential for Credential {
     fn rev_reg_id(&self) -> Option<RevocationRegistryDefinitionId> {
         self.rev_reg_id.clone()
     }
+
+    fn rev_reg_index(&self) -> Option<u32> {
+        self.signature.extract_index()
+    }
 }
 
 impl RevocableCredential for W3CCredential {
@@ -645,4 +827,63 @@ impl RevocableCredential for W3CCredential {
             .ok()
             .and_then(|proof| proof.rev_reg_id.clone())
     }
+
+    fn rev_reg_index(&self) -> Option<u32> {
+        self.get_credential_signature_proof()
+            .ok()
+            .and_then(|proof| proof.signature.extract_index())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DEFAULT_LINK_SECRET;
+    use crate::utils::testing::{NOW, issued_with};
+
+    #[test]
+    fn midnight_commitments_differ_per_credential() {
+        let wallet = ProverWallet::default();
+        let (secret_a, commitment_a) = wallet.commitment_for(DEFAULT_LINK_SECRET, "thread-a").unwrap();
+        let (secret_b, commitment_b) = wallet.commitment_for(DEFAULT_LINK_SECRET, "thread-b").unwrap();
+
+        assert_ne!(secret_a, secret_b);
+        assert_ne!(commitment_a, commitment_b);
+        assert_eq!(commitment_a, holder_commitment(&secret_a));
+        assert_eq!(
+            wallet.commitment_for(DEFAULT_LINK_SECRET, "thread-a").unwrap(),
+            (secret_a, commitment_a)
+        );
+        assert!(wallet.commitment_for("unknown", "thread-a").is_err());
+    }
+
+    #[test]
+    fn refresh_drops_states_of_credentials_no_longer_held() {
+        let mut wallet = ProverWallet::default();
+        // Left over from wallets that kept one state per registry
+        wallet.rev_states.insert("rev_reg:1".to_string(), (None, Some(10)));
+        assert!(wallet.refresh_revocation_states(&Ledger::default()).unwrap().is_empty());
+        assert!(wallet.rev_states.is_empty());
+    }
+
+    #[test]
+    fn refresh_reports_a_registry_missing_from_the_ledger() {
+        let mut issued = issued_with(CredentialFormat::Legacy, true);
+        assert_eq!(
+            issued.holder.refresh_revocation_states(&issued.ledger).unwrap(),
+            vec![("GVT".to_string(), NOW)]
+        );
+        // Up to date, nothing to do
+        assert!(
+            issued
+                .holder
+                .refresh_revocation_states(&issued.ledger)
+                .unwrap()
+                .is_empty()
+        );
+
+        issued.holder.rev_states.clear();
+        issued.ledger.rev_reg_defs.clear();
+        assert!(issued.holder.refresh_revocation_states(&issued.ledger).is_err());
+    }
 }
@@ -1,9 +1,47 @@
 #![allow(unused)]
 
+pub mod actions;
+pub mod disclosure;
+pub mod encoding;
+pub mod envelope;
+pub mod evidence;
 pub mod fixtures;
+pub mod midnight;
 pub mod mock;
+pub mod nonce;
+pub mod policy;
+pub mod query;
+pub mod report;
+pub mod request;
+pub mod resolve;
+pub mod scenario;
+pub mod selection;
+pub mod session;
 pub mod storage;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod validation;
+pub mod vault;
+pub mod workspace;
 
+pub use actions::*;
+pub use disclosure::*;
+pub use encoding::*;
+pub use envelope::*;
+pub use evidence::*;
 pub use fixtures::*;
+pub use midnight::*;
 pub use mock::*;
+pub use nonce::*;
+pub use policy::*;
+pub use query::*;
+pub use report::*;
+pub use request::*;
+pub use resolve::*;
+pub use scenario::*;
+pub use selection::*;
+pub use session::*;
 pub use storage::*;
+pub use validation::*;
+pub use vault::*;
+pub use workspace::*;
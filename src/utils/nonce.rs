@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use anoncreds::data_types::nonce::Nonce;
+use anoncreds::verifier;
+use serde::{Deserialize, Serialize};
+
+use super::report::ReasonCode;
+
+// Seconds a presentation request stays answerable
+pub const NONCE_TTL: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuedNonce {
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub consumed_at: Option<u64>,
+}
+
+// Nonces handed out in presentation requests, so that each one is answered at most once
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NonceStore {
+    pub ttl: u64,
+    nonces: HashMap<String, IssuedNonce>,
+}
+
+impl Default for NonceStore {
+    fn default() -> Self {
+        Self::new(NONCE_TTL)
+    }
+}
+
+impl NonceStore {
+    pub fn new(ttl: u64) -> Self {
+        Self {
+            ttl,
+            nonces: HashMap::new(),
+        }
+    }
+
+    pub fn issue(&mut self, now: u64) -> Nonce {
+        self.purge_expired(now);
+        let nonce = verifier::generate_nonce().expect("Error generating presentation request nonce");
+        self.nonces.insert(
+            nonce_key(&nonce),
+            IssuedNonce {
+                issued_at: now,
+                expires_at: now.saturating_add(self.ttl),
+                consumed_at: None,
+            },
+        );
+        nonce
+    }
+
+    pub fn get(&self, nonce: &Nonce) -> Option<&IssuedNonce> {
+        self.nonces.get(&nonce_key(nonce))
+    }
+
+    pub fn check(&self, nonce: &Nonce, now: u64) -> Result<(), (ReasonCode, String)> {
+        match self.get(nonce) {
+            None => Err((
+                ReasonCode::UnknownNonce,
+                "the request nonce was not issued by this verifier".to_string(),
+            )),
+            Some(IssuedNonce {
+                consumed_at: Some(consumed_at),
+                ..
+            }) => Err((
+                ReasonCode::ReplayedNonce,
+                format!("the request nonce was already answered at {consumed_at}"),
+            )),
+            Some(issued) if now > issued.expires_at => Err((
+                ReasonCode::ExpiredNonce,
+                format!("the request nonce expired at {}", issued.expires_at),
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    pub fn consume(&mut self, nonce: &Nonce, now: u64) {
+        self.purge_expired(now);
+        if let Some(issued) = self.nonces.get_mut(&nonce_key(nonce)) {
+            issued.consumed_at.get_or_insert(now);
+        }
+    }
+
+    // Forgets expired nonces, which happens whenever one is issued or consumed. A replay of one of them is then
+    // reported as unknown
+    pub fn purge_expired(&mut self, now: u64) -> usize {
+        let before = self.nonces.len();
+        self.nonces.retain(|_, issued| now <= issued.expires_at);
+        before - self.nonces.len()
+    }
+}
+
+// `Nonce` serializes to its decimal string
+fn nonce_key(nonce: &Nonce) -> String {
+    match serde_json::to_value(nonce) {
+        Ok(serde_json::Value::String(value)) => value,
+        Ok(value) => value.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(store: &NonceStore, nonce: &Nonce, now: u64) -> Option<ReasonCode> {
+        store.check(nonce, now).err().map(|(reason, _)| reason)
+    }
+
+    #[test]
+    fn nonces_expire_after_their_ttl() {
+        let mut store = NonceStore::new(10);
+        let nonce = store.issue(100);
+        assert_eq!(reason(&store, &nonce, 110), None);
+        assert_eq!(reason(&store, &nonce, 111), Some(ReasonCode::ExpiredNonce));
+
+        // Issuing another one forgets it
+        store.issue(111);
+        assert_eq!(reason(&store, &nonce, 111), Some(ReasonCode::UnknownNonce));
+    }
+
+    #[test]
+    fn nonces_are_answered_once() {
+        let mut store = NonceStore::new(10);
+        let nonce = store.issue(100);
+        store.consume(&nonce, 105);
+        assert_eq!(reason(&store, &nonce, 106), Some(ReasonCode::ReplayedNonce));
+
+        // Consuming again keeps the first answer
+        store.consume(&nonce, 107);
+        assert_eq!(store.get(&nonce).unwrap().consumed_at, Some(105));
+    }
+
+    #[test]
+    fn unknown_nonces_are_rejected() {
+        let mut store = NonceStore::new(10);
+        let issued = store.issue(100);
+        let unknown: Nonce = serde_json::from_value(serde_json::json!("1234567890")).unwrap();
+        assert_eq!(reason(&store, &unknown, 100), Some(ReasonCode::UnknownNonce));
+
+        // Consuming an unknown nonce does not make it known
+        store.consume(&unknown, 100);
+        assert!(store.get(&unknown).is_none());
+        assert_eq!(reason(&store, &issued, 100), None);
+    }
+
+    #[test]
+    fn consuming_purges_expired_nonces() {
+        let mut store = NonceStore::new(10);
+        let old = store.issue(100);
+        let fresh = store.issue(105);
+        store.consume(&fresh, 112);
+        assert!(store.get(&old).is_none());
+        assert!(store.get(&fresh).is_some());
+    }
+}
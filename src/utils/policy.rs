@@ -0,0 +1,234 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use anoncreds::data_types::cred_def::CredentialDefinitionId;
+use serde::{Deserialize, Serialize};
+
+use super::mock::TestError;
+use super::report::{PresentedCredential, VerificationReport};
+use super::storage::{Ledger, VerifierWallet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationMechanism {
+    // anoncreds non-revocation proof against a status list
+    Anoncreds,
+    // Commitment registered in a Midnight revocation registry contract, proven by the evidence of the presentation
+    Midnight,
+}
+
+// Rules for credentials of one schema. Empty lists accept anything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchemaPolicy {
+    pub trusted_issuers: Vec<String>,
+    // Cred def tags act as the cred def version
+    pub allowed_cred_def_tags: Vec<String>,
+    pub required_revocation: Vec<RevocationMechanism>,
+    // Overrides the global maximum age
+    pub max_revocation_status_age: Option<u64>,
+}
+
+// How a requested non_revoked `from` is moved for registries that did not exist yet at that time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntervalOverridePolicy {
+    // Move a `from` before the first published status list of a registry to that list's timestamp
+    pub automatic: bool,
+    // rev_reg_def_id: requested from: overriding from. Takes precedence over the automatic overrides
+    pub explicit: HashMap<String, HashMap<u64, u64>>,
+}
+
+impl Default for IntervalOverridePolicy {
+    fn default() -> Self {
+        Self {
+            automatic: true,
+            explicit: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustPolicy {
+    // schema_id: SchemaPolicy
+    pub schemas: HashMap<String, SchemaPolicy>,
+    pub reject_unlisted_schemas: bool,
+    // Seconds between the status list a non-revocation proof used and the time of verification
+    pub max_revocation_status_age: Option<u64>,
+    pub interval_overrides: IntervalOverridePolicy,
+}
+
+impl TrustPolicy {
+    pub fn load(path: &Path) -> Result<Self, TestError> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| TestError(format!("Error reading trust policy {}: {e}", path.display())))?;
+        serde_json::from_str(&json).map_err(|e| TestError(format!("Invalid trust policy {}: {e}", path.display())))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    UnlistedSchema,
+    UntrustedIssuer,
+    CredDefVersionNotAllowed,
+    RevocationMechanismMissing(RevocationMechanism),
+    RevocationStatusTooOld,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyViolation {
+    pub rule: PolicyRule,
+    pub referent: String,
+    pub detail: String,
+}
+
+impl VerificationReport {
+    // Cryptographically valid and compliant with the verifier policy
+    pub fn accepted(&self) -> bool {
+        self.verified && self.policy_violations.is_empty()
+    }
+}
+
+impl VerifierWallet {
+    pub fn with_policy(policy: TrustPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    // Only verified presentations are checked against the policy, `now` is in the ledger time scale
+    pub fn apply_policy(&self, report: &mut VerificationReport, ledger: &Ledger, now: u64) {
+        if !report.verified {
+            return;
+        }
+        let mut violations = vec![];
+        for entry in report.referents.iter() {
+            if let Some(credential) = &entry.credential {
+                for (rule, detail) in self.check_policy(credential, &report.midnight_checked, ledger, now) {
+                    violations.push(PolicyViolation {
+                        rule,
+                        referent: entry.referent.clone(),
+                        detail,
+                    });
+                }
+            }
+        }
+        report.policy_violations = violations;
+    }
+
+    // Midnight revocation counts only when the presentation came with valid evidence for the credential's registry
+    fn check_policy(
+        &self,
+        credential: &PresentedCredential,
+        midnight_checked: &BTreeSet<String>,
+        ledger: &Ledger,
+        now: u64,
+    ) -> Vec<(PolicyRule, String)> {
+        let mut violations = vec![];
+        let Some(schema_policy) = self.policy.schemas.get(&credential.schema_id) else {
+            if self.policy.reject_unlisted_schemas {
+                violations.push((
+                    PolicyRule::UnlistedSchema,
+                    format!("schema {} is not covered by the policy", credential.schema_id),
+                ));
+            }
+            return violations;
+        };
+
+        match ledger
+            .cred_defs
+            .get(&CredentialDefinitionId::new_unchecked(credential.cred_def_id.as_str()))
+        {
+            Some(cred_def) => {
+                let issuer_id = &cred_def.issuer_id.0;
+                if !schema_policy.trusted_issuers.is_empty() && !schema_policy.trusted_issuers.contains(issuer_id) {
+                    violations.push((
+                        PolicyRule::UntrustedIssuer,
+                        format!("issuer {issuer_id} is not trusted for schema {}", credential.schema_id),
+                    ));
+                }
+                if !schema_policy.allowed_cred_def_tags.is_empty()
+                    && !schema_policy.allowed_cred_def_tags.contains(&cred_def.tag)
+                {
+                    violations.push((
+                        PolicyRule::CredDefVersionNotAllowed,
+                        format!("cred def {} has tag {}", credential.cred_def_id, cred_def.tag),
+                    ));
+                }
+            }
+            None => violations.push((
+                PolicyRule::UntrustedIssuer,
+                format!("cred def {} is not published on the ledger", credential.cred_def_id),
+            )),
+        }
+
+        for mechanism in schema_policy.required_revocation.iter() {
+            let present = match mechanism {
+                RevocationMechanism::Anoncreds => credential.rev_reg_id.is_some() && credential.timestamp.is_some(),
+                RevocationMechanism::Midnight => credential
+                    .rev_reg_id
+                    .as_ref()
+                    .is_some_and(|id| midnight_checked.contains(id)),
+            };
+            if !present {
+                violations.push((
+                    PolicyRule::RevocationMechanismMissing(*mechanism),
+                    format!("credential from cred def {} lacks it", credential.cred_def_id),
+                ));
+            }
+        }
+
+        let max_age = schema_policy
+            .max_revocation_status_age
+            .or(self.policy.max_revocation_status_age);
+        if let (Some(max_age), Some(timestamp)) = (max_age, credential.timestamp)
+            && now.saturating_sub(timestamp) > max_age
+        {
+            violations.push((
+                PolicyRule::RevocationStatusTooOld,
+                format!("status list from {timestamp} is older than {max_age}s at {now}"),
+            ));
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midnight_revocation_needs_checked_evidence() {
+        let verifier = VerifierWallet::with_policy(TrustPolicy {
+            schemas: HashMap::from([(
+                "schema:government".to_string(),
+                SchemaPolicy {
+                    required_revocation: vec![RevocationMechanism::Anoncreds, RevocationMechanism::Midnight],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+        let credential = PresentedCredential {
+            schema_id: "schema:government".to_string(),
+            cred_def_id: "creddef:government".to_string(),
+            rev_reg_id: Some("rev_reg:1".to_string()),
+            timestamp: Some(10),
+        };
+        let midnight_missing = |checked: &[&str]| {
+            let checked = checked.iter().map(|id| id.to_string()).collect();
+            verifier
+                .check_policy(&credential, &checked, &Ledger::default(), 10)
+                .iter()
+                .any(|(rule, _)| *rule == PolicyRule::RevocationMechanismMissing(RevocationMechanism::Midnight))
+        };
+
+        assert!(midnight_missing(&[]));
+        assert!(midnight_missing(&["rev_reg:2"]));
+        assert!(!midnight_missing(&["rev_reg:1"]));
+    }
+}
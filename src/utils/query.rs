@@ -0,0 +1,550 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+use anoncreds::data_types::cred_def::CredentialDefinitionId;
+use anoncreds::data_types::credential::Credential;
+use anoncreds::data_types::schema::SchemaId;
+use anoncreds::data_types::w3c::credential::W3CCredential;
+use serde_json::{Value, json};
+
+use super::mock::{CredentialFormat, RevocableCredential, TestError};
+use super::storage::{Ledger, ProverWallet};
+
+// Common read access to credentials held in either the legacy or the W3C store
+pub trait HeldCredential: RevocableCredential {
+    fn schema_id(&self) -> SchemaId;
+    fn cred_def_id(&self) -> CredentialDefinitionId;
+    fn issuer_id(&self) -> Option<String>;
+    fn raw_values(&self) -> HashMap<String, String>;
+}
+
+impl HeldCredential for Credential {
+    fn schema_id(&self) -> SchemaId {
+        self.schema_id.clone()
+    }
+
+    fn cred_def_id(&self) -> CredentialDefinitionId {
+        self.cred_def_id.clone()
+    }
+
+    // Legacy credentials only carry the issuer inside the credential definition
+    fn issuer_id(&self) -> Option<String> {
+        None
+    }
+
+    fn raw_values(&self) -> HashMap<String, String> {
+        self.values
+            .0
+            .iter()
+            .map(|(name, value)| (attr_common_view(name), value.raw.clone()))
+            .collect()
+    }
+}
+
+impl HeldCredential for W3CCredential {
+    fn schema_id(&self) -> SchemaId {
+        self.get_credential_signature_proof()
+            .expect("W3C credential without signature proof")
+            .schema_id
+            .clone()
+    }
+
+    fn cred_def_id(&self) -> CredentialDefinitionId {
+        self.get_credential_signature_proof()
+            .expect("W3C credential without signature proof")
+            .cred_def_id
+            .clone()
+    }
+
+    fn issuer_id(&self) -> Option<String> {
+        Some(self.issuer.0.clone())
+    }
+
+    fn raw_values(&self) -> HashMap<String, String> {
+        self.credential_subject
+            .0
+            .iter()
+            .map(|(name, value)| (attr_common_view(name), value.to_string()))
+            .collect()
+    }
+}
+
+// Attribute names are compared the way anoncreds does: case-insensitive and without spaces
+pub fn attr_common_view(name: &str) -> String {
+    name.replace(' ', "").to_lowercase()
+}
+
+// Searchable tags of a credential, named as in the anoncreds restriction spec
+pub fn credential_tags<T: HeldCredential>(credential: &T, ledger: &Ledger) -> HashMap<String, String> {
+    let schema_id = credential.schema_id();
+    let cred_def_id = credential.cred_def_id();
+
+    let mut tags = HashMap::from([
+        ("schema_id".to_string(), schema_id.0.clone()),
+        ("cred_def_id".to_string(), cred_def_id.0.clone()),
+    ]);
+
+    if let Some(schema) = ledger.schemas.get(&schema_id) {
+        tags.insert("schema_name".to_string(), schema.name.clone());
+        tags.insert("schema_version".to_string(), schema.version.clone());
+        tags.insert("schema_issuer_id".to_string(), schema.issuer_id.0.clone());
+        tags.insert("schema_issuer_did".to_string(), schema.issuer_id.0.clone());
+    }
+
+    let issuer_id = credential.issuer_id().or_else(|| {
+        ledger
+            .cred_defs
+            .get(&cred_def_id)
+            .map(|cred_def| cred_def.issuer_id.0.clone())
+    });
+    if let Some(issuer_id) = issuer_id {
+        tags.insert("issuer_id".to_string(), issuer_id.clone());
+        tags.insert("issuer_did".to_string(), issuer_id);
+    }
+
+    if let Some(rev_reg_id) = credential.rev_reg_id() {
+        tags.insert("rev_reg_id".to_string(), rev_reg_id.0);
+    }
+
+    for (name, raw) in credential.raw_values() {
+        tags.insert(format!("attr::{name}::value"), raw);
+        tags.insert(format!("attr::{name}::marker"), "1".to_string());
+    }
+
+    tags
+}
+
+// WQL query over credential tags, the same language anoncreds uses for restrictions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialQuery {
+    And(Vec<CredentialQuery>),
+    Or(Vec<CredentialQuery>),
+    Not(Box<CredentialQuery>),
+    Eq(String, String),
+    Neq(String, String),
+    Gt(String, String),
+    Gte(String, String),
+    Lt(String, String),
+    Lte(String, String),
+    Like(String, String),
+    In(String, Vec<String>),
+    Exist(Vec<String>),
+}
+
+impl CredentialQuery {
+    pub fn schema_id(schema_id: &str) -> Self {
+        CredentialQuery::Eq("schema_id".to_string(), schema_id.to_string())
+    }
+
+    pub fn cred_def_id(cred_def_id: &str) -> Self {
+        CredentialQuery::Eq("cred_def_id".to_string(), cred_def_id.to_string())
+    }
+
+    pub fn issuer_id(issuer_id: &str) -> Self {
+        CredentialQuery::Eq("issuer_id".to_string(), issuer_id.to_string())
+    }
+
+    pub fn rev_reg_id(rev_reg_id: &str) -> Self {
+        CredentialQuery::Eq("rev_reg_id".to_string(), rev_reg_id.to_string())
+    }
+
+    pub fn attr_value(name: &str, value: &str) -> Self {
+        CredentialQuery::Eq(format!("attr::{}::value", attr_common_view(name)), value.to_string())
+    }
+
+    pub fn attr_marker(name: &str) -> Self {
+        CredentialQuery::Eq(format!("attr::{}::marker", attr_common_view(name)), "1".to_string())
+    }
+
+    // WQL form, as accepted in presentation request restrictions
+    pub fn to_json(&self) -> Value {
+        match self {
+            CredentialQuery::And(queries) => {
+                json!({"$and": queries.iter().map(CredentialQuery::to_json).collect::<Vec<_>>()})
+            }
+            CredentialQuery::Or(queries) => {
+                json!({"$or": queries.iter().map(CredentialQuery::to_json).collect::<Vec<_>>()})
+            }
+            CredentialQuery::Not(query) => json!({"$not": query.to_json()}),
+            CredentialQuery::Eq(tag, value) => json!({ tag: value }),
+            CredentialQuery::Neq(tag, value) => json!({ tag: {"$neq": value} }),
+            CredentialQuery::Gt(tag, value) => json!({ tag: {"$gt": value} }),
+            CredentialQuery::Gte(tag, value) => json!({ tag: {"$gte": value} }),
+            CredentialQuery::Lt(tag, value) => json!({ tag: {"$lt": value} }),
+            CredentialQuery::Lte(tag, value) => json!({ tag: {"$lte": value} }),
+            CredentialQuery::Like(tag, value) => json!({ tag: {"$like": value} }),
+            CredentialQuery::In(tag, values) => json!({ tag: {"$in": values} }),
+            CredentialQuery::Exist(tags) => json!({"$exist": tags}),
+        }
+    }
+
+    pub fn from_json(query: &Value) -> Result<Self, TestError> {
+        match query {
+            // A list of restrictions is satisfied by any of them
+            Value::Array(queries) => Ok(CredentialQuery::Or(
+                queries
+                    .iter()
+                    .map(CredentialQuery::from_json)
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::Object(clauses) => {
+                let mut parsed = clauses
+                    .iter()
+                    .map(|(key, value)| parse_clause(key, value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if parsed.len() == 1 {
+                    Ok(parsed.remove(0))
+                } else {
+                    Ok(CredentialQuery::And(parsed))
+                }
+            }
+            other => Err(TestError(format!("Invalid query: {other}"))),
+        }
+    }
+
+    pub fn matches(&self, tags: &HashMap<String, String>) -> bool {
+        match self {
+            CredentialQuery::And(queries) => queries.iter().all(|query| query.matches(tags)),
+            CredentialQuery::Or(queries) => queries.iter().any(|query| query.matches(tags)),
+            CredentialQuery::Not(query) => !query.matches(tags),
+            CredentialQuery::Eq(tag, value) => tags.get(tag) == Some(value),
+            CredentialQuery::Neq(tag, value) => tags.get(tag) != Some(value),
+            CredentialQuery::Gt(tag, value) => compare(tags.get(tag), value) == Some(Ordering::Greater),
+            CredentialQuery::Gte(tag, value) => {
+                matches!(compare(tags.get(tag), value), Some(Ordering::Greater | Ordering::Equal))
+            }
+            CredentialQuery::Lt(tag, value) => compare(tags.get(tag), value) == Some(Ordering::Less),
+            CredentialQuery::Lte(tag, value) => {
+                matches!(compare(tags.get(tag), value), Some(Ordering::Less | Ordering::Equal))
+            }
+            CredentialQuery::Like(tag, pattern) => tags.get(tag).is_some_and(|actual| like(actual, pattern)),
+            CredentialQuery::In(tag, values) => tags.get(tag).is_some_and(|actual| values.contains(actual)),
+            CredentialQuery::Exist(names) => names.iter().all(|tag| tags.contains_key(tag)),
+        }
+    }
+}
+
+impl TryFrom<&str> for CredentialQuery {
+    type Error = TestError;
+
+    fn try_from(query: &str) -> Result<Self, Self::Error> {
+        let query: Value = serde_json::from_str(query).map_err(|e| TestError(e.to_string()))?;
+        CredentialQuery::from_json(&query)
+    }
+}
+
+fn parse_clause(key: &str, value: &Value) -> Result<CredentialQuery, TestError> {
+    match key {
+        "$and" => Ok(CredentialQuery::And(parse_list(value)?)),
+        "$or" => Ok(CredentialQuery::Or(parse_list(value)?)),
+        "$not" => Ok(CredentialQuery::Not(Box::new(CredentialQuery::from_json(value)?))),
+        "$exist" => match value {
+            Value::String(tag) => Ok(CredentialQuery::Exist(vec![normalize_tag(tag)])),
+            Value::Array(tags) => Ok(CredentialQuery::Exist(
+                tags.iter()
+                    .map(|tag| as_string(tag).map(|tag| normalize_tag(&tag)))
+                    .collect::<Result<_, _>>()?,
+            )),
+            other => Err(TestError(format!("Invalid `$exist` operand: {other}"))),
+        },
+        tag => {
+            let tag = normalize_tag(tag);
+            match value {
+                Value::String(expected) => Ok(CredentialQuery::Eq(tag, expected.clone())),
+                Value::Object(operator) if operator.len() == 1 => {
+                    let (operator, operand) = operator.iter().next().unwrap();
+                    match operator.as_str() {
+                        "$neq" => Ok(CredentialQuery::Neq(tag, as_string(operand)?)),
+                        "$gt" => Ok(CredentialQuery::Gt(tag, as_string(operand)?)),
+                        "$gte" => Ok(CredentialQuery::Gte(tag, as_string(operand)?)),
+                        "$lt" => Ok(CredentialQuery::Lt(tag, as_string(operand)?)),
+                        "$lte" => Ok(CredentialQuery::Lte(tag, as_string(operand)?)),
+                        "$like" => Ok(CredentialQuery::Like(tag, as_string(operand)?)),
+                        "$in" => match operand {
+                            Value::Array(values) => Ok(CredentialQuery::In(
+                                tag,
+                                values.iter().map(as_string).collect::<Result<_, _>>()?,
+                            )),
+                            other => Err(TestError(format!("Invalid `$in` operand: {other}"))),
+                        },
+                        unsupported => Err(TestError(format!("Unsupported query operator: {unsupported}"))),
+                    }
+                }
+                other => Err(TestError(format!("Invalid value for tag `{tag}`: {other}"))),
+            }
+        }
+    }
+}
+
+fn parse_list(value: &Value) -> Result<Vec<CredentialQuery>, TestError> {
+    match value {
+        Value::Array(queries) => queries.iter().map(CredentialQuery::from_json).collect(),
+        other => Err(TestError(format!("Expected a list of queries: {other}"))),
+    }
+}
+
+fn as_string(value: &Value) -> Result<String, TestError> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        other => Err(TestError(format!("Expected a string: {other}"))),
+    }
+}
+
+// `attr::Some Name::value` refers to the same tag as `attr::somename::value`
+fn normalize_tag(tag: &str) -> String {
+    match tag.split("::").collect::<Vec<_>>().as_slice() {
+        ["attr", name, kind] => format!("attr::{}::{kind}", attr_common_view(name)),
+        _ => tag.to_string(),
+    }
+}
+
+// Numbers compare numerically, everything else lexicographically
+fn compare(actual: Option<&String>, expected: &str) -> Option<Ordering> {
+    let actual = actual?;
+    match (actual.parse::<i64>(), expected.parse::<i64>()) {
+        (Ok(actual), Ok(expected)) => Some(actual.cmp(&expected)),
+        _ => Some(actual.as_str().cmp(expected)),
+    }
+}
+
+// SQL `LIKE` with `%` matching any sequence and `_` matching a single character
+fn like(actual: &str, pattern: &str) -> bool {
+    let actual: Vec<char> = actual.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut matched = vec![vec![false; pattern.len() + 1]; actual.len() + 1];
+    matched[0][0] = true;
+    for j in 1..=pattern.len() {
+        matched[0][j] = pattern[j - 1] == '%' && matched[0][j - 1];
+    }
+    for i in 1..=actual.len() {
+        for j in 1..=pattern.len() {
+            matched[i][j] = match pattern[j - 1] {
+                '%' => matched[i][j - 1] || matched[i - 1][j],
+                '_' => matched[i - 1][j - 1],
+                c => c == actual[i - 1] && matched[i - 1][j - 1],
+            };
+        }
+    }
+    matched[actual.len()][pattern.len()]
+}
+
+// A row of the holder's "my credentials" view
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialSummary {
+    pub id: String,
+    pub format: CredentialFormat,
+    pub schema_id: String,
+    pub cred_def_id: String,
+    pub issuer_id: Option<String>,
+    pub rev_reg_id: Option<String>,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl ProverWallet {
+    // Ids of the credentials in the given store that match the query
+    pub fn search_credentials(
+        &self,
+        ledger: &Ledger,
+        format: &CredentialFormat,
+        query: &CredentialQuery,
+    ) -> Vec<String> {
+        let mut ids = match format {
+            CredentialFormat::Legacy => search(&self.credentials, ledger, query),
+            CredentialFormat::W3C => search(&self.w3c_credentials, ledger, query),
+        };
+        ids.sort();
+        ids
+    }
+
+    // Every credential held in either store, optionally filtered by a query
+    pub fn list_credentials(&self, ledger: &Ledger, query: Option<&CredentialQuery>) -> Vec<CredentialSummary> {
+        let mut summaries: Vec<CredentialSummary> = summarize(&self.credentials, ledger, CredentialFormat::Legacy)
+            .chain(summarize(&self.w3c_credentials, ledger, CredentialFormat::W3C))
+            .filter(|(_, tags)| query.is_none_or(|query| query.matches(tags)))
+            .map(|(summary, _)| summary)
+            .collect();
+        summaries.sort_by(|a, b| a.id.cmp(&b.id));
+        summaries
+    }
+}
+
+fn search<T: HeldCredential>(
+    credentials: &HashMap<String, T>,
+    ledger: &Ledger,
+    query: &CredentialQuery,
+) -> Vec<String> {
+    credentials
+        .iter()
+        .filter(|(_, credential)| query.matches(&credential_tags(*credential, ledger)))
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+fn summarize<'c, T: HeldCredential>(
+    credentials: &'c HashMap<String, T>,
+    ledger: &'c Ledger,
+    format: CredentialFormat,
+) -> impl Iterator<Item = (CredentialSummary, HashMap<String, String>)> + 'c {
+    credentials.iter().map(move |(id, credential)| {
+        let tags = credential_tags(credential, ledger);
+        let summary = CredentialSummary {
+            id: id.clone(),
+            format: format.clone(),
+            schema_id: tags["schema_id"].clone(),
+            cred_def_id: tags["cred_def_id"].clone(),
+            issuer_id: tags.get("issuer_id").cloned(),
+            rev_reg_id: tags.get("rev_reg_id").cloned(),
+            attributes: credential.raw_values().into_iter().collect(),
+        };
+        (summary, tags)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock::Credentials;
+    use crate::utils::testing::issued;
+
+    fn tags(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(tag, value)| (tag.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_operators_and_normalizes_attribute_tags() {
+        let query = CredentialQuery::from_json(&json!({
+            "schema_id": "schema:government",
+            "attr::First Name::value": { "$like": "Al%" },
+            "$not": { "attr::age::value": { "$in": ["17", 18] } },
+            "$exist": "attr::Sex::marker",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            query,
+            CredentialQuery::And(vec![
+                CredentialQuery::Exist(vec!["attr::sex::marker".to_string()]),
+                CredentialQuery::Not(Box::new(CredentialQuery::In(
+                    "attr::age::value".to_string(),
+                    vec!["17".to_string(), "18".to_string()]
+                ))),
+                CredentialQuery::Like("attr::firstname::value".to_string(), "Al%".to_string()),
+                CredentialQuery::schema_id("schema:government"),
+            ])
+        );
+        assert_eq!(CredentialQuery::from_json(&query.to_json()).unwrap(), query);
+    }
+
+    #[test]
+    fn a_list_of_restrictions_is_any_of_them() {
+        let query = CredentialQuery::try_from(r#"[{"issuer_id": "a"}, {"issuer_id": "b"}]"#).unwrap();
+
+        assert_eq!(
+            query,
+            CredentialQuery::Or(vec![CredentialQuery::issuer_id("a"), CredentialQuery::issuer_id("b")])
+        );
+        assert!(query.matches(&tags(&[("issuer_id", "b")])));
+        assert!(!query.matches(&tags(&[("issuer_id", "c")])));
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        for query in [
+            json!("schema_id"),
+            json!({ "schema_id": 1 }),
+            json!({ "schema_id": { "$regex": "x" } }),
+            json!({ "schema_id": { "$neq": "a", "$gt": "b" } }),
+            json!({ "schema_id": { "$in": "a" } }),
+            json!({ "$and": { "schema_id": "a" } }),
+            json!({ "$exist": 1 }),
+        ] {
+            assert!(CredentialQuery::from_json(&query).is_err(), "{query}");
+        }
+    }
+
+    #[test]
+    fn like_matches_any_sequence_and_single_characters() {
+        let name = tags(&[("attr::name::value", "Alex")]);
+        let like = |pattern: &str| CredentialQuery::Like("attr::name::value".to_string(), pattern.to_string());
+
+        for pattern in ["Alex", "A%", "%x", "%", "A__x", "_le_", "%l%"] {
+            assert!(like(pattern).matches(&name), "{pattern}");
+        }
+        for pattern in ["alex", "A_x", "Alex_", "B%", ""] {
+            assert!(!like(pattern).matches(&name), "{pattern}");
+        }
+        assert!(!like("%").matches(&tags(&[])));
+    }
+
+    #[test]
+    fn numbers_compare_numerically_and_missing_tags_never_match_comparisons() {
+        let age = tags(&[("attr::age::value", "9"), ("attr::name::value", "b")]);
+        let query = |value: Value| CredentialQuery::from_json(&value).unwrap();
+
+        assert!(query(json!({ "attr::age::value": { "$lt": "10" } })).matches(&age));
+        assert!(query(json!({ "attr::age::value": { "$gte": 9 } })).matches(&age));
+        assert!(!query(json!({ "attr::age::value": { "$gt": "10" } })).matches(&age));
+        assert!(query(json!({ "attr::name::value": { "$gt": "a" } })).matches(&age));
+        assert!(query(json!({ "attr::name::value": { "$lte": "b" } })).matches(&age));
+        assert!(!query(json!({ "attr::height::value": { "$lt": "1000" } })).matches(&age));
+        assert!(query(json!({ "attr::height::value": { "$neq": "1" } })).matches(&age));
+        assert!(!query(json!({ "$exist": ["attr::age::value", "attr::height::value"] })).matches(&age));
+    }
+
+    #[test]
+    fn legacy_credentials_take_the_issuer_from_the_ledger_cred_def() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let by_issuer = CredentialQuery::issuer_id(&issued.fixture.issuer_id);
+        let credential = &issued.holder.credentials["GVT"];
+
+        assert_eq!(credential.issuer_id(), None);
+        let tags = credential_tags(credential, &issued.ledger);
+        assert_eq!(tags["issuer_did"], issued.fixture.issuer_id);
+        assert_eq!(tags["schema_name"], issued.fixture.schema.name);
+        assert_eq!(tags["attr::name::value"], "Alex");
+        assert_eq!(
+            issued
+                .holder
+                .search_credentials(&issued.ledger, &CredentialFormat::Legacy, &by_issuer),
+            vec!["GVT".to_string()]
+        );
+
+        // Without the cred def there is nothing to take the issuer from
+        issued.ledger.cred_defs.clear();
+        assert!(!credential_tags(&issued.holder.credentials["GVT"], &issued.ledger).contains_key("issuer_id"));
+        assert!(
+            issued
+                .holder
+                .search_credentials(&issued.ledger, &CredentialFormat::Legacy, &by_issuer)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn lists_credentials_of_both_stores() {
+        let mut issued = issued(CredentialFormat::W3C);
+        let credential = Credentials::W3C(issued.holder.w3c_credentials["GVT"].clone());
+        let cred_def =
+            &issued.ledger.cred_defs[&CredentialDefinitionId::new_unchecked(issued.fixture.cred_def.id.as_str())];
+        issued.holder.convert_credential("GVT-legacy", &credential, cred_def);
+        let summaries = issued.holder.list_credentials(&issued.ledger, None);
+
+        let ids: Vec<&str> = summaries.iter().map(|summary| summary.id.as_str()).collect();
+        assert_eq!(ids, ["GVT", "GVT-legacy"]);
+        assert_eq!(summaries[0].format, CredentialFormat::W3C);
+        assert_eq!(summaries[1].format, CredentialFormat::Legacy);
+        for summary in summaries.iter() {
+            assert_eq!(summary.issuer_id.as_deref(), Some(issued.fixture.issuer_id.as_str()));
+            assert_eq!(summary.attributes["age"], "28");
+        }
+
+        let older = CredentialQuery::from_json(&json!({ "attr::age::value": { "$gt": "30" } })).unwrap();
+        assert!(issued.holder.list_credentials(&issued.ledger, Some(&older)).is_empty());
+        let younger = CredentialQuery::from_json(&json!({ "attr::age::value": { "$lte": "28" } })).unwrap();
+        assert_eq!(issued.holder.list_credentials(&issued.ledger, Some(&younger)).len(), 2);
+    }
+}
@@ -0,0 +1,739 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anoncreds::data_types::cred_def::{CredentialDefinition, CredentialDefinitionId};
+use anoncreds::data_types::pres_request::{AttributeInfo, NonRevokedInterval, PredicateInfo, PredicateTypes};
+use anoncreds::data_types::rev_reg_def::RevocationRegistryDefinitionId;
+use anoncreds::data_types::schema::{Schema, SchemaId};
+use anoncreds::types::{PresentationRequest, RevocationRegistryDefinition, RevocationStatusList};
+use anoncreds::{verifier, w3c};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::mock::{Presentations, TestError};
+use super::nonce::unix_now;
+use super::policy::PolicyViolation;
+use super::query::{CredentialQuery, attr_common_view};
+use super::selection::allows_self_attestation;
+use super::storage::VerifierWallet;
+
+// Stable codes for why a presentation, or one of its referents, was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasonCode {
+    MissingReferent,
+    SelfAttestedNotAllowed,
+    RestrictionNotSatisfied,
+    UnknownSchema,
+    UnknownCredDef,
+    UnknownRevocationRegistry,
+    MissingRevocationStatus,
+    MissingNonRevocationTimestamp,
+    TimestampOutsideInterval,
+    // A heuristic: the proof did not verify, and the status list the referent's own credential proved
+    // non-revocation against revokes entries. The verifier cannot tell which index the credential has, so the
+    // failure may still be elsewhere in the proof
+    RevocationCheckFailed,
+    // The proof did not verify, and nothing points at the part of it that failed
+    ProofRejected,
+    MalformedPresentation,
+    UnknownNonce,
+    ExpiredNonce,
+    ReplayedNonce,
+    // Midnight evidence is absent for a presented credential, or does not check out against the ledger
+    MissingRevocationEvidence,
+    InvalidRevocationEvidence,
+    // The bundle's binding does not cover its presentation, evidence and the request nonce
+    UnboundRevocationEvidence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferentKind {
+    RevealedAttribute,
+    AttributeGroup,
+    UnrevealedAttribute,
+    SelfAttestedAttribute,
+    Predicate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PresentedCredential {
+    pub schema_id: String,
+    pub cred_def_id: String,
+    pub rev_reg_id: Option<String>,
+    // Timestamp of the status list the non-revocation proof was made against
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReferentReport {
+    pub referent: String,
+    pub kind: ReferentKind,
+    // attribute name: raw value, empty for unrevealed attributes and predicates
+    pub revealed: BTreeMap<String, String>,
+    // e.g. `age >= 18`
+    pub predicate: Option<String>,
+    // Only known once the proof verified
+    pub predicate_satisfied: Option<bool>,
+    pub credential: Option<PresentedCredential>,
+    pub failure: Option<ReasonCode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerificationFailure {
+    pub reason: ReasonCode,
+    pub referent: Option<String>,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerificationReport {
+    pub verified: bool,
+    pub referents: Vec<ReferentReport>,
+    pub failures: Vec<VerificationFailure>,
+    // Revocation registries whose presented credentials all came with valid Midnight evidence, filled by
+    // `VerifierWallet::verify_bundle_at`
+    pub midnight_checked: BTreeSet<String>,
+    // Filled by `VerifierWallet::apply_policy`, kept apart from cryptographic failures
+    pub policy_violations: Vec<PolicyViolation>,
+}
+
+impl VerificationReport {
+    pub fn failed(&self, reason: ReasonCode) -> bool {
+        self.failures.iter().any(|failure| failure.reason == reason)
+    }
+
+    pub fn referent(&self, referent: &str) -> Option<&ReferentReport> {
+        self.referents.iter().find(|report| report.referent == referent)
+    }
+
+    pub(crate) fn fail(&mut self, reason: ReasonCode, referent: Option<&str>, detail: String) {
+        if let Some(referent) = referent
+            && let Some(report) = self.referents.iter_mut().find(|report| report.referent == referent)
+        {
+            report.failure.get_or_insert(reason);
+        }
+        self.failures.push(VerificationFailure {
+            reason,
+            referent: referent.map(str::to_string),
+            detail,
+        });
+    }
+}
+
+fn describe_predicate(info: &PredicateInfo) -> String {
+    let operator = match info.p_type {
+        PredicateTypes::GE => ">=",
+        PredicateTypes::LE => "<=",
+        PredicateTypes::GT => ">",
+        PredicateTypes::LT => "<",
+    };
+    format!("{} {operator} {}", info.name, info.p_value)
+}
+
+// What each referent was answered with, before any cryptographic check
+pub(crate) fn collect_referents(
+    presentation: &Presentations,
+    pres_req: &PresentationRequest,
+) -> Result<Vec<ReferentReport>, TestError> {
+    let request = pres_req.value();
+    let mut referents = vec![];
+
+    let report = |referent: &str, kind: ReferentKind, credential: Option<PresentedCredential>| ReferentReport {
+        referent: referent.to_string(),
+        kind,
+        revealed: BTreeMap::new(),
+        predicate: request.requested_predicates.get(referent).map(describe_predicate),
+        predicate_satisfied: None,
+        credential,
+        failure: None,
+    };
+
+    match presentation {
+        Presentations::Legacy(presentation) => {
+            let proof = &presentation.requested_proof;
+            let credential = |index: u32| {
+                presentation
+                    .identifiers
+                    .get(index as usize)
+                    .map(|identifier| PresentedCredential {
+                        schema_id: identifier.schema_id.0.clone(),
+                        cred_def_id: identifier.cred_def_id.0.clone(),
+                        rev_reg_id: identifier.rev_reg_id.as_ref().map(|id| id.0.clone()),
+                        timestamp: identifier.timestamp,
+                    })
+                    .ok_or_else(|| TestError(format!("Sub proof {index} has no identifier")))
+            };
+
+            for (referent, info) in proof.revealed_attrs.iter() {
+                let mut entry = report(
+                    referent,
+                    ReferentKind::RevealedAttribute,
+                    Some(credential(info.sub_proof_index)?),
+                );
+                let name = request
+                    .requested_attributes
+                    .get(referent)
+                    .and_then(|attribute| attribute.name.clone())
+                    .unwrap_or_else(|| referent.clone());
+                entry.revealed.insert(name, info.raw.clone());
+                referents.push(entry);
+            }
+            for (referent, info) in proof.revealed_attr_groups.iter() {
+                let mut entry = report(
+                    referent,
+                    ReferentKind::AttributeGroup,
+                    Some(credential(info.sub_proof_index)?),
+                );
+                for (name, value) in info.values.iter() {
+                    entry.revealed.insert(name.clone(), value.raw.clone());
+                }
+                referents.push(entry);
+            }
+            for (referent, info) in proof.unrevealed_attrs.iter() {
+                referents.push(report(
+                    referent,
+                    ReferentKind::UnrevealedAttribute,
+                    Some(credential(info.sub_proof_index)?),
+                ));
+            }
+            for (referent, value) in proof.self_attested_attrs.iter() {
+                let mut entry = report(referent, ReferentKind::SelfAttestedAttribute, None);
+                let name = request
+                    .requested_attributes
+                    .get(referent)
+                    .and_then(|attribute| attribute.name.clone())
+                    .unwrap_or_else(|| referent.clone());
+                entry.revealed.insert(name, value.clone());
+                referents.push(entry);
+            }
+            for (referent, info) in proof.predicates.iter() {
+                referents.push(report(
+                    referent,
+                    ReferentKind::Predicate,
+                    Some(credential(info.sub_proof_index)?),
+                ));
+            }
+        }
+        Presentations::W3C(presentation) => {
+            for verifiable_credential in presentation.verifiable_credential.iter() {
+                let proof = verifiable_credential
+                    .get_presentation_proof()
+                    .map_err(|e| TestError(format!("Invalid W3C presentation proof: {e}")))?;
+                let credential = PresentedCredential {
+                    schema_id: proof.schema_id.0.clone(),
+                    cred_def_id: proof.cred_def_id.0.clone(),
+                    rev_reg_id: proof.rev_reg_id.as_ref().map(|id| id.0.clone()),
+                    timestamp: proof.timestamp,
+                };
+                let subject = &verifiable_credential.credential_subject.0;
+                let revealed = |names: Vec<String>| -> BTreeMap<String, String> {
+                    names
+                        .into_iter()
+                        .filter_map(|name| {
+                            let value = subject.get(&attr_common_view(&name))?;
+                            Some((name, value.to_string()))
+                        })
+                        .collect()
+                };
+
+                for referent in proof.mapping.revealed_attributes.iter() {
+                    let mut entry = report(referent, ReferentKind::RevealedAttribute, Some(credential.clone()));
+                    let names = requested_names(request.requested_attributes.get(referent));
+                    entry.revealed = revealed(names);
+                    referents.push(entry);
+                }
+                for referent in proof.mapping.revealed_attribute_groups.iter() {
+                    let mut entry = report(referent, ReferentKind::AttributeGroup, Some(credential.clone()));
+                    let names = requested_names(request.requested_attributes.get(referent));
+                    entry.revealed = revealed(names);
+                    referents.push(entry);
+                }
+                for referent in proof.mapping.unrevealed_attributes.iter() {
+                    referents.push(report(
+                        referent,
+                        ReferentKind::UnrevealedAttribute,
+                        Some(credential.clone()),
+                    ));
+                }
+                for referent in proof.mapping.predicates.iter() {
+                    referents.push(report(referent, ReferentKind::Predicate, Some(credential.clone())));
+                }
+            }
+        }
+    }
+
+    referents.sort_by(|a, b| a.referent.cmp(&b.referent));
+    Ok(referents)
+}
+
+fn requested_names(info: Option<&AttributeInfo>) -> Vec<String> {
+    match info {
+        Some(AttributeInfo { name: Some(name), .. }) => vec![name.clone()],
+        Some(AttributeInfo { names: Some(names), .. }) => names.clone(),
+        _ => vec![],
+    }
+}
+
+// Restriction tags the verifier can compute from public data, as in the anoncreds restriction spec
+fn verifier_tags(
+    credential: &PresentedCredential,
+    revealed: &BTreeMap<String, String>,
+    schemas: &HashMap<SchemaId, Schema>,
+    cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
+) -> HashMap<String, String> {
+    let mut tags = HashMap::from([
+        ("schema_id".to_string(), credential.schema_id.clone()),
+        ("cred_def_id".to_string(), credential.cred_def_id.clone()),
+    ]);
+    if let Some(schema) = schemas.get(&SchemaId::new_unchecked(credential.schema_id.as_str())) {
+        tags.insert("schema_name".to_string(), schema.name.clone());
+        tags.insert("schema_version".to_string(), schema.version.clone());
+        tags.insert("schema_issuer_id".to_string(), schema.issuer_id.0.clone());
+        tags.insert("schema_issuer_did".to_string(), schema.issuer_id.0.clone());
+        for name in schema.attr_names.0.iter() {
+            tags.insert(format!("attr::{}::marker", attr_common_view(name)), "1".to_string());
+        }
+    }
+    if let Some(cred_def) = cred_defs.get(&CredentialDefinitionId::new_unchecked(credential.cred_def_id.as_str())) {
+        tags.insert("issuer_id".to_string(), cred_def.issuer_id.0.clone());
+        tags.insert("issuer_did".to_string(), cred_def.issuer_id.0.clone());
+    }
+    if let Some(rev_reg_id) = &credential.rev_reg_id {
+        tags.insert("rev_reg_id".to_string(), rev_reg_id.clone());
+    }
+    for (name, value) in revealed.iter() {
+        tags.insert(format!("attr::{}::value", attr_common_view(name)), value.clone());
+    }
+    tags
+}
+
+// `RevocationStatusList` keeps its fields private, so they are read from the serialized form
+pub(crate) fn status_list_view(list: &RevocationStatusList) -> (Option<String>, Option<u64>, Vec<bool>) {
+    let value = serde_json::to_value(list).unwrap_or(Value::Null);
+    let rev_reg_def_id = value["revRegDefId"].as_str().map(str::to_string);
+    let timestamp = value["timestamp"].as_u64();
+    let revoked = value["revocationList"]
+        .as_array()
+        .map(|bits| bits.iter().map(|bit| bit.as_u64() == Some(1)).collect())
+        .unwrap_or_default();
+    (rev_reg_def_id, timestamp, revoked)
+}
+
+impl VerifierWallet {
+    pub fn verify_presentation(
+        &mut self,
+        presentation: &Presentations,
+        pres_req: &PresentationRequest,
+        schemas: &HashMap<SchemaId, Schema>,
+        cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
+        rev_reg_defs: Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>>,
+        rev_status_lists: Option<Vec<RevocationStatusList>>,
+        nonrevoke_interval_override: Option<&HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>>>,
+    ) -> VerificationReport {
+        self.verify_presentation_at(
+            presentation,
+            pres_req,
+            schemas,
+            cred_defs,
+            rev_reg_defs,
+            rev_status_lists,
+            nonrevoke_interval_override,
+            unix_now(),
+        )
+    }
+
+    // A successful verification consumes the request nonce, so the same presentation is not accepted twice
+    pub fn verify_presentation_at(
+        &mut self,
+        presentation: &Presentations,
+        pres_req: &PresentationRequest,
+        schemas: &HashMap<SchemaId, Schema>,
+        cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
+        rev_reg_defs: Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>>,
+        rev_status_lists: Option<Vec<RevocationStatusList>>,
+        nonrevoke_interval_override: Option<&HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>>>,
+        now: u64,
+    ) -> VerificationReport {
+        let report = self.check_presentation_at(
+            presentation,
+            pres_req,
+            schemas,
+            cred_defs,
+            rev_reg_defs,
+            rev_status_lists,
+            nonrevoke_interval_override,
+            now,
+        );
+        if report.verified {
+            self.nonces.consume(&pres_req.value().nonce, now);
+        }
+        report
+    }
+
+    // Verifies without consuming the request nonce, for callers with checks of their own to pass first
+    pub(crate) fn check_presentation_at(
+        &self,
+        presentation: &Presentations,
+        pres_req: &PresentationRequest,
+        schemas: &HashMap<SchemaId, Schema>,
+        cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
+        rev_reg_defs: Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>>,
+        rev_status_lists: Option<Vec<RevocationStatusList>>,
+        nonrevoke_interval_override: Option<&HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>>>,
+        now: u64,
+    ) -> VerificationReport {
+        let mut report = VerificationReport {
+            verified: false,
+            referents: vec![],
+            failures: vec![],
+            midnight_checked: BTreeSet::new(),
+            policy_violations: vec![],
+        };
+        match collect_referents(presentation, pres_req) {
+            Ok(referents) => report.referents = referents,
+            Err(e) => {
+                report.fail(ReasonCode::MalformedPresentation, None, e.0);
+                return report;
+            }
+        }
+
+        let nonce = &pres_req.value().nonce;
+        if let Err((reason, detail)) = self.nonces.check(nonce, now) {
+            report.fail(reason, None, detail);
+            return report;
+        }
+
+        let status_lists: Vec<(Option<String>, Option<u64>, Vec<bool>)> =
+            rev_status_lists.iter().flatten().map(status_list_view).collect();
+        self.check_structure(
+            &mut report,
+            matches!(presentation, Presentations::W3C(_)),
+            pres_req,
+            schemas,
+            cred_defs,
+            rev_reg_defs,
+            &status_lists,
+            nonrevoke_interval_override,
+        );
+        if !report.failures.is_empty() {
+            return report;
+        }
+
+        let verified = match presentation {
+            Presentations::Legacy(presentation) => verifier::verify_presentation(
+                presentation,
+                pres_req,
+                schemas,
+                cred_defs,
+                rev_reg_defs,
+                rev_status_lists,
+                nonrevoke_interval_override,
+            ),
+            Presentations::W3C(presentation) => w3c::verifier::verify_presentation(
+                presentation,
+                pres_req,
+                schemas,
+                cred_defs,
+                rev_reg_defs,
+                rev_status_lists,
+                nonrevoke_interval_override,
+            ),
+        };
+
+        match verified {
+            Ok(true) => {
+                report.verified = true;
+                for entry in report.referents.iter_mut() {
+                    if entry.kind == ReferentKind::Predicate {
+                        entry.predicate_satisfied = Some(true);
+                    }
+                }
+            }
+            Ok(false) => {
+                // The proof is verified as a whole, so a failure is attributed to every referent it covers
+                let failures: Vec<(String, ReasonCode)> = report
+                    .referents
+                    .iter()
+                    .map(|entry| {
+                        let reason = rejection_reason(entry.credential.as_ref(), &status_lists);
+                        (entry.referent.clone(), reason)
+                    })
+                    .collect();
+                for (referent, reason) in failures {
+                    let detail = if reason == ReasonCode::RevocationCheckFailed {
+                        "the presentation proof did not verify, possibly because the credential is revoked in the \
+                         status list it was proven against"
+                    } else {
+                        "the presentation proof did not verify"
+                    };
+                    report.fail(reason, Some(&referent), detail.to_string());
+                }
+            }
+            Err(e) => report.fail(ReasonCode::MalformedPresentation, None, e.to_string()),
+        }
+        report
+    }
+
+    fn check_structure(
+        &self,
+        report: &mut VerificationReport,
+        w3c: bool,
+        pres_req: &PresentationRequest,
+        schemas: &HashMap<SchemaId, Schema>,
+        cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
+        rev_reg_defs: Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>>,
+        status_lists: &[(Option<String>, Option<u64>, Vec<bool>)],
+        nonrevoke_interval_override: Option<&HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>>>,
+    ) {
+        let request = pres_req.value();
+
+        let mut attributes: Vec<_> = request.requested_attributes.iter().collect();
+        attributes.sort_by_key(|(referent, _)| *referent);
+        let mut predicates: Vec<_> = request.requested_predicates.iter().collect();
+        predicates.sort_by_key(|(referent, _)| *referent);
+
+        for (referent, info) in attributes {
+            let Some(entry) = report.referent(referent).cloned() else {
+                let detail = if w3c && allows_self_attestation(info) {
+                    "not answered, self-attested attributes require the legacy format".to_string()
+                } else {
+                    "not answered by the presentation".to_string()
+                };
+                report.fail(ReasonCode::MissingReferent, Some(referent), detail);
+                continue;
+            };
+            if entry.kind == ReferentKind::SelfAttestedAttribute {
+                if !allows_self_attestation(info) {
+                    report.fail(
+                        ReasonCode::SelfAttestedNotAllowed,
+                        Some(referent),
+                        "self-attested value given for a restricted attribute".to_string(),
+                    );
+                }
+                continue;
+            }
+            let restrictions = info.restrictions.as_ref().and_then(|r| serde_json::to_value(r).ok());
+            self.check_credential(
+                report,
+                &entry,
+                restrictions.as_ref(),
+                info.non_revoked.as_ref().or(request.non_revoked.as_ref()),
+                schemas,
+                cred_defs,
+                rev_reg_defs,
+                status_lists,
+                nonrevoke_interval_override,
+            );
+        }
+
+        for (referent, info) in predicates {
+            let Some(entry) = report.referent(referent).cloned() else {
+                report.fail(
+                    ReasonCode::MissingReferent,
+                    Some(referent),
+                    "not answered by the presentation".to_string(),
+                );
+                continue;
+            };
+            let restrictions = info.restrictions.as_ref().and_then(|r| serde_json::to_value(r).ok());
+            self.check_credential(
+                report,
+                &entry,
+                restrictions.as_ref(),
+                info.non_revoked.as_ref().or(request.non_revoked.as_ref()),
+                schemas,
+                cred_defs,
+                rev_reg_defs,
+                status_lists,
+                nonrevoke_interval_override,
+            );
+        }
+
+        let unrequested: Vec<String> = report
+            .referents
+            .iter()
+            .filter(|entry| {
+                !request.requested_attributes.contains_key(&entry.referent)
+                    && !request.requested_predicates.contains_key(&entry.referent)
+            })
+            .map(|entry| entry.referent.clone())
+            .collect();
+        for referent in unrequested {
+            report.fail(
+                ReasonCode::MalformedPresentation,
+                Some(&referent),
+                "answers a referent that was not requested".to_string(),
+            );
+        }
+    }
+
+    fn check_credential(
+        &self,
+        report: &mut VerificationReport,
+        entry: &ReferentReport,
+        restrictions: Option<&Value>,
+        non_revoked: Option<&NonRevokedInterval>,
+        schemas: &HashMap<SchemaId, Schema>,
+        cred_defs: &HashMap<CredentialDefinitionId, CredentialDefinition>,
+        rev_reg_defs: Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>>,
+        status_lists: &[(Option<String>, Option<u64>, Vec<bool>)],
+        nonrevoke_interval_override: Option<&HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>>>,
+    ) {
+        let referent = Some(entry.referent.as_str());
+        let Some(credential) = &entry.credential else {
+            return;
+        };
+
+        if !schemas.contains_key(&SchemaId::new_unchecked(credential.schema_id.as_str())) {
+            report.fail(
+                ReasonCode::UnknownSchema,
+                referent,
+                format!("schema {}", credential.schema_id),
+            );
+            return;
+        }
+        if !cred_defs.contains_key(&CredentialDefinitionId::new_unchecked(credential.cred_def_id.as_str())) {
+            report.fail(
+                ReasonCode::UnknownCredDef,
+                referent,
+                format!("cred def {}", credential.cred_def_id),
+            );
+            return;
+        }
+
+        if let Some(restrictions) = restrictions {
+            match CredentialQuery::from_json(restrictions) {
+                Ok(query) => {
+                    if !query.matches(&verifier_tags(credential, &entry.revealed, schemas, cred_defs)) {
+                        report.fail(
+                            ReasonCode::RestrictionNotSatisfied,
+                            referent,
+                            format!("credential from cred def {} does not match", credential.cred_def_id),
+                        );
+                    }
+                }
+                Err(e) => report.fail(ReasonCode::MalformedPresentation, referent, e.0),
+            }
+        }
+
+        let (Some(interval), Some(rev_reg_id)) = (non_revoked, &credential.rev_reg_id) else {
+            return;
+        };
+        let known_registry = rev_reg_defs
+            .is_some_and(|defs| defs.contains_key(&RevocationRegistryDefinitionId::new_unchecked(rev_reg_id.as_str())));
+        if !known_registry {
+            report.fail(
+                ReasonCode::UnknownRevocationRegistry,
+                referent,
+                format!("revocation registry {rev_reg_id}"),
+            );
+            return;
+        }
+        let Some(timestamp) = credential.timestamp else {
+            report.fail(
+                ReasonCode::MissingNonRevocationTimestamp,
+                referent,
+                "non-revocation was requested but not proven".to_string(),
+            );
+            return;
+        };
+        let listed = status_lists
+            .iter()
+            .any(|(id, ts, _)| id.as_deref() == Some(rev_reg_id.as_str()) && *ts == Some(timestamp));
+        if !listed {
+            report.fail(
+                ReasonCode::MissingRevocationStatus,
+                referent,
+                format!("no status list of {rev_reg_id} at {timestamp}"),
+            );
+            return;
+        }
+
+        let (from, to) = interval_bounds(interval);
+        let from = from.map(|from| {
+            nonrevoke_interval_override
+                .and_then(|overrides| {
+                    overrides.get(&RevocationRegistryDefinitionId::new_unchecked(rev_reg_id.as_str()))
+                })
+                .and_then(|overrides| overrides.get(&from))
+                .copied()
+                .unwrap_or(from)
+        });
+        if from.is_some_and(|from| timestamp < from) || to.is_some_and(|to| timestamp > to) {
+            report.fail(
+                ReasonCode::TimestampOutsideInterval,
+                referent,
+                format!("timestamp {timestamp} is outside the requested interval"),
+            );
+        }
+    }
+}
+
+// A revocation failure is only reported for a credential whose own non-revocation proof was made against a
+// status list that revokes entries, anything else about a rejected proof is unknown. Even then it is a guess:
+// anoncreds checks non-revocation against the list's accumulator rather than its bits, and the proof does not
+// disclose the credential's index, so there is no way to verify the proof again with revocation ruled out
+fn rejection_reason(
+    credential: Option<&PresentedCredential>,
+    status_lists: &[(Option<String>, Option<u64>, Vec<bool>)],
+) -> ReasonCode {
+    let Some((rev_reg_id, timestamp)) =
+        credential.and_then(|credential| Some((credential.rev_reg_id.as_deref()?, credential.timestamp?)))
+    else {
+        return ReasonCode::ProofRejected;
+    };
+    let revokes_entries = status_lists.iter().any(|(id, ts, revoked)| {
+        id.as_deref() == Some(rev_reg_id) && *ts == Some(timestamp) && revoked.iter().any(|bit| *bit)
+    });
+    if revokes_entries {
+        ReasonCode::RevocationCheckFailed
+    } else {
+        ReasonCode::ProofRejected
+    }
+}
+
+// `NonRevokedInterval` is read from its serialized form, like the other anoncreds types with private fields
+pub(crate) fn interval_bounds(interval: &NonRevokedInterval) -> (Option<u64>, Option<u64>) {
+    let value = serde_json::to_value(interval).unwrap_or(Value::Null);
+    (value["from"].as_u64(), value["to"].as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential(rev_reg_id: Option<&str>, timestamp: Option<u64>) -> PresentedCredential {
+        PresentedCredential {
+            schema_id: "schema:government".to_string(),
+            cred_def_id: "creddef:government".to_string(),
+            rev_reg_id: rev_reg_id.map(str::to_string),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn rejected_proofs_only_blame_revocation_of_the_referents_own_list() {
+        let status_lists = vec![
+            (Some("rev_reg:1".to_string()), Some(10), vec![false, true]),
+            (Some("rev_reg:1".to_string()), Some(5), vec![false, false]),
+            (Some("rev_reg:2".to_string()), Some(10), vec![false, false]),
+        ];
+        let reason = |credential: Option<PresentedCredential>| rejection_reason(credential.as_ref(), &status_lists);
+
+        assert_eq!(
+            reason(Some(credential(Some("rev_reg:1"), Some(10)))),
+            ReasonCode::RevocationCheckFailed
+        );
+        assert_eq!(
+            reason(Some(credential(Some("rev_reg:1"), Some(5)))),
+            ReasonCode::ProofRejected
+        );
+        assert_eq!(
+            reason(Some(credential(Some("rev_reg:2"), Some(10)))),
+            ReasonCode::ProofRejected
+        );
+        assert_eq!(reason(Some(credential(None, None))), ReasonCode::ProofRejected);
+        assert_eq!(reason(None), ReasonCode::ProofRejected);
+    }
+}
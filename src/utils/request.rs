@@ -0,0 +1,533 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anoncreds::data_types::pres_request::PredicateTypes;
+use anoncreds::types::PresentationRequest;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use super::encoding::{date_in, date_to_day_number, latest_birthdate};
+use super::mock::TestError;
+use super::query::{CredentialQuery, attr_common_view};
+use super::storage::VerifierWallet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationInterval {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<u64>,
+}
+
+impl RevocationInterval {
+    pub fn new(from: Option<u64>, to: Option<u64>) -> Self {
+        Self { from, to }
+    }
+
+    // Not revoked at a single point in time
+    pub fn at(timestamp: u64) -> Self {
+        Self::new(Some(timestamp), Some(timestamp))
+    }
+
+    fn validate(&self, referent: &str) -> Result<(), TestError> {
+        match (self.from, self.to) {
+            (None, None) => Err(TestError(format!("{referent}: non_revoked interval has no bounds"))),
+            (Some(from), Some(to)) if from > to => Err(TestError(format!(
+                "{referent}: non_revoked interval starts at {from} after it ends at {to}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestedAttribute {
+    names: Vec<String>,
+    group: bool,
+    restrictions: Option<CredentialQuery>,
+    non_revoked: Option<RevocationInterval>,
+}
+
+impl RequestedAttribute {
+    pub fn name(name: &str) -> Self {
+        Self {
+            names: vec![name.to_string()],
+            group: false,
+            restrictions: None,
+            non_revoked: None,
+        }
+    }
+
+    // Attributes revealed together from the same credential
+    pub fn group(names: &[&str]) -> Self {
+        Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            group: true,
+            restrictions: None,
+            non_revoked: None,
+        }
+    }
+
+    // Several restrictions on the same item are all required
+    pub fn restrict(mut self, restriction: CredentialQuery) -> Self {
+        self.restrictions = Some(and(self.restrictions.take(), restriction));
+        self
+    }
+
+    pub fn non_revoked(mut self, interval: RevocationInterval) -> Self {
+        self.non_revoked = Some(interval);
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        let mut info = Map::new();
+        if self.group {
+            info.insert("names".to_string(), json!(self.names));
+        } else {
+            info.insert("name".to_string(), json!(self.names[0]));
+        }
+        insert_common(&mut info, self.restrictions.as_ref(), self.non_revoked.as_ref());
+        Value::Object(info)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestedPredicate {
+    name: String,
+    p_type: PredicateTypes,
+    p_value: i32,
+    restrictions: Option<CredentialQuery>,
+    non_revoked: Option<RevocationInterval>,
+}
+
+impl RequestedPredicate {
+    pub fn new(name: &str, p_type: PredicateTypes, p_value: i32) -> Self {
+        Self {
+            name: name.to_string(),
+            p_type,
+            p_value,
+            restrictions: None,
+            non_revoked: None,
+        }
+    }
+
+    pub fn ge(name: &str, p_value: i32) -> Self {
+        Self::new(name, PredicateTypes::GE, p_value)
+    }
+
+    pub fn le(name: &str, p_value: i32) -> Self {
+        Self::new(name, PredicateTypes::LE, p_value)
+    }
+
+    pub fn gt(name: &str, p_value: i32) -> Self {
+        Self::new(name, PredicateTypes::GT, p_value)
+    }
+
+    pub fn lt(name: &str, p_value: i32) -> Self {
+        Self::new(name, PredicateTypes::LT, p_value)
+    }
+
+    // At least `years` old on `today`, proven from a date attribute issued as its day number
+    pub fn older_than(name: &str, years: u32, today: NaiveDate) -> Result<Self, TestError> {
+        Ok(Self::le(name, date_to_day_number(latest_birthdate(years, today)?)))
+    }
+
+    pub fn restrict(mut self, restriction: CredentialQuery) -> Self {
+        self.restrictions = Some(and(self.restrictions.take(), restriction));
+        self
+    }
+
+    pub fn non_revoked(mut self, interval: RevocationInterval) -> Self {
+        self.non_revoked = Some(interval);
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        let mut info = Map::new();
+        info.insert("name".to_string(), json!(self.name));
+        info.insert("p_type".to_string(), json!(self.p_type));
+        info.insert("p_value".to_string(), json!(self.p_value));
+        insert_common(&mut info, self.restrictions.as_ref(), self.non_revoked.as_ref());
+        Value::Object(info)
+    }
+}
+
+fn and(current: Option<CredentialQuery>, restriction: CredentialQuery) -> CredentialQuery {
+    match current {
+        None => restriction,
+        Some(CredentialQuery::And(mut queries)) => {
+            queries.push(restriction);
+            CredentialQuery::And(queries)
+        }
+        Some(query) => CredentialQuery::And(vec![query, restriction]),
+    }
+}
+
+fn insert_common(
+    info: &mut Map<String, Value>,
+    restrictions: Option<&CredentialQuery>,
+    non_revoked: Option<&RevocationInterval>,
+) {
+    if let Some(restrictions) = restrictions {
+        info.insert("restrictions".to_string(), restrictions.to_json());
+    }
+    if let Some(non_revoked) = non_revoked {
+        info.insert("non_revoked".to_string(), json!(non_revoked));
+    }
+}
+
+// An empty list of restrictions is any of nothing, which no credential satisfies
+fn validate_restrictions(referent: &str, restrictions: Option<&CredentialQuery>) -> Result<(), TestError> {
+    match restrictions {
+        Some(CredentialQuery::Or(queries)) if queries.is_empty() => {
+            Err(TestError(format!("{referent}: restrictions are an empty list")))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Items keep the order they were added in, so build errors point at the first offending referent
+#[derive(Debug)]
+pub struct PresentationRequestBuilder {
+    name: String,
+    version: String,
+    attributes: Vec<(String, RequestedAttribute)>,
+    predicates: Vec<(String, RequestedPredicate)>,
+    non_revoked: Option<RevocationInterval>,
+}
+
+impl PresentationRequestBuilder {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            attributes: vec![],
+            predicates: vec![],
+            non_revoked: None,
+        }
+    }
+
+    pub fn attribute(mut self, referent: &str, attribute: RequestedAttribute) -> Self {
+        self.attributes.push((referent.to_string(), attribute));
+        self
+    }
+
+    pub fn predicate(mut self, referent: &str, predicate: RequestedPredicate) -> Self {
+        self.predicates.push((referent.to_string(), predicate));
+        self
+    }
+
+    // Applies to every item without its own interval
+    pub fn non_revoked(mut self, interval: RevocationInterval) -> Self {
+        self.non_revoked = Some(interval);
+        self
+    }
+
+    // The nonce is only issued once the request is known to be valid, so a rejected one leaves nothing to expire
+    pub fn build(self, verifier_wallet: &mut VerifierWallet, now: u64) -> Result<PresentationRequest, TestError> {
+        self.validate()?;
+
+        let requested_attributes: Map<String, Value> = self
+            .attributes
+            .iter()
+            .map(|(referent, attribute)| (referent.clone(), attribute.to_json()))
+            .collect();
+        let requested_predicates: Map<String, Value> = self
+            .predicates
+            .iter()
+            .map(|(referent, predicate)| (referent.clone(), predicate.to_json()))
+            .collect();
+
+        let mut request = json!({
+            "nonce": verifier_wallet.generate_nonce_at(now),
+            "name": self.name,
+            "version": self.version,
+            "requested_attributes": requested_attributes,
+            "requested_predicates": requested_predicates,
+        });
+        if let Some(non_revoked) = self.non_revoked {
+            request["non_revoked"] = json!(non_revoked);
+        }
+
+        serde_json::from_value(request).map_err(|e| TestError(format!("Error creating presentation request: {e}")))
+    }
+
+    fn validate(&self) -> Result<(), TestError> {
+        if self.attributes.is_empty() && self.predicates.is_empty() {
+            return Err(TestError("Presentation request asks for nothing".to_string()));
+        }
+
+        // Attribute and predicate referents share one namespace in the presentation
+        let mut referents = BTreeSet::new();
+        let all_referents = self
+            .attributes
+            .iter()
+            .map(|(referent, _)| referent)
+            .chain(self.predicates.iter().map(|(referent, _)| referent));
+        for referent in all_referents {
+            if referent.is_empty() {
+                return Err(TestError("Empty referent".to_string()));
+            }
+            if !referents.insert(referent) {
+                return Err(TestError(format!("{referent}: referent is used more than once")));
+            }
+        }
+
+        for (referent, attribute) in self.attributes.iter() {
+            if attribute.names.is_empty() || attribute.names.iter().any(|name| name.trim().is_empty()) {
+                return Err(TestError(format!("{referent}: attribute name is empty")));
+            }
+            let mut names = BTreeSet::new();
+            if let Some(name) = attribute
+                .names
+                .iter()
+                .find(|name| !names.insert(attr_common_view(name)))
+            {
+                return Err(TestError(format!("{referent}: attribute `{name}` is requested twice")));
+            }
+            validate_restrictions(referent, attribute.restrictions.as_ref())?;
+            if let Some(interval) = attribute.non_revoked {
+                interval.validate(referent)?;
+            }
+        }
+
+        for (referent, predicate) in self.predicates.iter() {
+            if predicate.name.trim().is_empty() {
+                return Err(TestError(format!("{referent}: predicate attribute name is empty")));
+            }
+            validate_restrictions(referent, predicate.restrictions.as_ref())?;
+            if let Some(interval) = predicate.non_revoked {
+                interval.validate(referent)?;
+            }
+        }
+
+        if let Some(interval) = self.non_revoked {
+            interval.validate("non_revoked")?;
+        }
+        Ok(())
+    }
+}
+
+// A presentation request as written in a JSON file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestSpec {
+    pub name: String,
+    #[serde(default = "default_request_version")]
+    pub version: String,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, AttributeSpec>,
+    #[serde(default)]
+    pub predicates: BTreeMap<String, PredicateSpec>,
+    #[serde(default)]
+    pub non_revoked: Option<RevocationInterval>,
+}
+
+fn default_request_version() -> String {
+    "0.1".to_string()
+}
+
+// Either `name` or `names` for a group
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttributeSpec {
+    pub name: Option<String>,
+    pub names: Vec<String>,
+    // anoncreds restriction query
+    pub restrictions: Option<Value>,
+    pub non_revoked: Option<RevocationInterval>,
+}
+
+// Either `p_type` and `p_value`, or `older_than` years for a date attribute
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PredicateSpec {
+    pub name: String,
+    #[serde(default)]
+    pub p_type: Option<PredicateTypes>,
+    #[serde(default)]
+    pub p_value: Option<i32>,
+    #[serde(default)]
+    pub older_than: Option<u32>,
+    #[serde(default)]
+    pub restrictions: Option<Value>,
+    #[serde(default)]
+    pub non_revoked: Option<RevocationInterval>,
+}
+
+impl RequestSpec {
+    // `older_than` counts from the UTC date at `now`
+    pub fn build(&self, verifier_wallet: &mut VerifierWallet, now: u64) -> Result<PresentationRequest, TestError> {
+        let today = i64::try_from(now)
+            .ok()
+            .and_then(|now| DateTime::from_timestamp(now, 0))
+            .map(|now| date_in(&Utc, now))
+            .ok_or_else(|| TestError(format!("{now} is not a valid time")))?;
+        let mut builder = PresentationRequestBuilder::new(&self.name, &self.version);
+        for (referent, attribute) in self.attributes.iter() {
+            let mut requested = match (&attribute.name, attribute.names.is_empty()) {
+                (Some(name), true) => RequestedAttribute::name(name),
+                (None, false) => {
+                    let names: Vec<&str> = attribute.names.iter().map(String::as_str).collect();
+                    RequestedAttribute::group(&names)
+                }
+                _ => return Err(TestError(format!("{referent}: give either `name` or `names`"))),
+            };
+            if let Some(restrictions) = &attribute.restrictions {
+                requested = requested.restrict(CredentialQuery::from_json(restrictions)?);
+            }
+            if let Some(interval) = attribute.non_revoked {
+                requested = requested.non_revoked(interval);
+            }
+            builder = builder.attribute(referent, requested);
+        }
+        for (referent, predicate) in self.predicates.iter() {
+            let mut requested = match (predicate.p_type, predicate.p_value, predicate.older_than) {
+                (Some(p_type), Some(p_value), None) => RequestedPredicate::new(&predicate.name, p_type, p_value),
+                (None, None, Some(years)) => RequestedPredicate::older_than(&predicate.name, years, today)
+                    .map_err(|e| TestError(format!("{referent}: {}", e.0)))?,
+                _ => {
+                    return Err(TestError(format!(
+                        "{referent}: give either `p_type` and `p_value`, or `older_than`"
+                    )));
+                }
+            };
+            if let Some(restrictions) = &predicate.restrictions {
+                requested = requested.restrict(CredentialQuery::from_json(restrictions)?);
+            }
+            if let Some(interval) = predicate.non_revoked {
+                requested = requested.non_revoked(interval);
+            }
+            builder = builder.predicate(referent, requested);
+        }
+        if let Some(interval) = self.non_revoked {
+            builder = builder.non_revoked(interval);
+        }
+        builder.build(verifier_wallet, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(builder: PresentationRequestBuilder) -> (Result<PresentationRequest, TestError>, VerifierWallet) {
+        let mut verifier = VerifierWallet::default();
+        let request = builder.build(&mut verifier, 100);
+        (request, verifier)
+    }
+
+    // Fails to build, without issuing a nonce for the request
+    fn rejected(builder: PresentationRequestBuilder) -> String {
+        let (request, verifier) = build(builder);
+        assert_eq!(serde_json::to_value(&verifier.nonces).unwrap()["nonces"], json!({}));
+        request.unwrap_err().0
+    }
+
+    fn builder() -> PresentationRequestBuilder {
+        PresentationRequestBuilder::new("proof", "1.0")
+    }
+
+    #[test]
+    fn empty_requests_are_rejected() {
+        assert_eq!(rejected(builder()), "Presentation request asks for nothing");
+    }
+
+    #[test]
+    fn referents_are_unique_across_attributes_and_predicates() {
+        let error = rejected(
+            builder()
+                .attribute("age", RequestedAttribute::name("age"))
+                .predicate("age", RequestedPredicate::ge("age", 18)),
+        );
+        assert_eq!(error, "age: referent is used more than once");
+        assert_eq!(
+            rejected(builder().attribute("", RequestedAttribute::name("name"))),
+            "Empty referent"
+        );
+    }
+
+    #[test]
+    fn intervals_have_to_be_bounded_and_ordered() {
+        let error = rejected(
+            builder()
+                .attribute("name", RequestedAttribute::name("name"))
+                .non_revoked(RevocationInterval::new(Some(20), Some(10))),
+        );
+        assert_eq!(
+            error,
+            "non_revoked: non_revoked interval starts at 20 after it ends at 10"
+        );
+        let error = rejected(builder().predicate(
+            "adult",
+            RequestedPredicate::ge("age", 18).non_revoked(RevocationInterval::new(None, None)),
+        ));
+        assert_eq!(error, "adult: non_revoked interval has no bounds");
+    }
+
+    #[test]
+    fn attribute_names_are_required_and_distinct() {
+        assert_eq!(
+            rejected(builder().attribute("name", RequestedAttribute::name(" "))),
+            "name: attribute name is empty"
+        );
+        assert_eq!(
+            rejected(builder().attribute("group", RequestedAttribute::group(&[]))),
+            "group: attribute name is empty"
+        );
+        assert_eq!(
+            rejected(builder().attribute("group", RequestedAttribute::group(&["Last Name", "lastname"]))),
+            "group: attribute `lastname` is requested twice"
+        );
+        assert_eq!(
+            rejected(builder().predicate("adult", RequestedPredicate::ge("", 18))),
+            "adult: predicate attribute name is empty"
+        );
+    }
+
+    #[test]
+    fn empty_restriction_lists_are_rejected() {
+        let nothing = CredentialQuery::from_json(&json!([])).unwrap();
+        assert_eq!(
+            rejected(builder().attribute("name", RequestedAttribute::name("name").restrict(nothing.clone()))),
+            "name: restrictions are an empty list"
+        );
+        assert_eq!(
+            rejected(builder().predicate("adult", RequestedPredicate::ge("age", 18).restrict(nothing))),
+            "adult: restrictions are an empty list"
+        );
+    }
+
+    #[test]
+    fn older_than_counts_from_the_request_time() {
+        let p_value = |now: &str| {
+            let now = DateTime::parse_from_rfc3339(now).unwrap().timestamp() as u64;
+            let spec: RequestSpec = serde_json::from_value(json!({
+                "name": "proof",
+                "predicates": { "adult": { "name": "birthdate", "older_than": 18 } },
+            }))
+            .unwrap();
+            let request = spec.build(&mut VerifierWallet::default(), now).unwrap();
+            request.value().requested_predicates["adult"].p_value
+        };
+        let day_number = |date: &str| date_to_day_number(NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap());
+
+        assert_eq!(p_value("2026-02-28T23:59:59Z"), day_number("2008-02-28"));
+        assert_eq!(p_value("2026-03-01T00:00:00Z"), day_number("2008-03-01"));
+    }
+
+    #[test]
+    fn valid_requests_get_a_nonce() {
+        let (request, verifier) = build(
+            builder()
+                .attribute("name", RequestedAttribute::name("name"))
+                .predicate("adult", RequestedPredicate::ge("age", 18)),
+        );
+        let request = request.unwrap();
+        assert!(verifier.nonces.get(&request.value().nonce).is_some());
+        assert_eq!(
+            request.value().requested_attributes["name"].name.as_deref(),
+            Some("name")
+        );
+    }
+}
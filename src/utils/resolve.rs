@@ -0,0 +1,314 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anoncreds::data_types::cred_def::{CredentialDefinition, CredentialDefinitionId};
+use anoncreds::data_types::pres_request::NonRevokedInterval;
+use anoncreds::data_types::rev_reg_def::RevocationRegistryDefinitionId;
+use anoncreds::data_types::schema::{Schema, SchemaId};
+use anoncreds::types::{PresentationRequest, RevocationRegistryDefinition, RevocationStatusList};
+
+use super::mock::{Presentations, TestError};
+use super::nonce::unix_now;
+use super::report::{PresentedCredential, VerificationReport};
+use super::storage::{Ledger, VerifierWallet};
+
+// Ledger objects a presentation refers to, in the shape `verify_presentation` takes them
+#[derive(Debug, Default)]
+pub struct PresentationObjects {
+    pub schemas: HashMap<SchemaId, Schema>,
+    pub cred_defs: HashMap<CredentialDefinitionId, CredentialDefinition>,
+    pub rev_reg_defs: HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>,
+    pub rev_status_lists: Vec<RevocationStatusList>,
+}
+
+impl PresentationObjects {
+    pub fn rev_reg_defs(&self) -> Option<&HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>> {
+        (!self.rev_reg_defs.is_empty()).then_some(&self.rev_reg_defs)
+    }
+
+    pub fn rev_status_lists(&self) -> Option<Vec<RevocationStatusList>> {
+        (!self.rev_status_lists.is_empty()).then(|| self.rev_status_lists.clone())
+    }
+}
+
+// One entry per sub proof, in the order of the presentation
+pub fn presented_credentials(presentation: &Presentations) -> Result<Vec<PresentedCredential>, TestError> {
+    match presentation {
+        Presentations::Legacy(presentation) => Ok(presentation
+            .identifiers
+            .iter()
+            .map(|identifier| PresentedCredential {
+                schema_id: identifier.schema_id.0.clone(),
+                cred_def_id: identifier.cred_def_id.0.clone(),
+                rev_reg_id: identifier.rev_reg_id.as_ref().map(|id| id.0.clone()),
+                timestamp: identifier.timestamp,
+            })
+            .collect()),
+        Presentations::W3C(presentation) => presentation
+            .verifiable_credential
+            .iter()
+            .map(|verifiable_credential| {
+                let proof = verifiable_credential
+                    .get_presentation_proof()
+                    .map_err(|e| TestError(format!("Invalid W3C presentation proof: {e}")))?;
+                Ok(PresentedCredential {
+                    schema_id: proof.schema_id.0.clone(),
+                    cred_def_id: proof.cred_def_id.0.clone(),
+                    rev_reg_id: proof.rev_reg_id.as_ref().map(|id| id.0.clone()),
+                    timestamp: proof.timestamp,
+                })
+            })
+            .collect(),
+    }
+}
+
+impl Ledger {
+    // Objects missing from the ledger are left out, verification reports them per referent
+    pub fn resolve_presentation(&self, presentation: &Presentations) -> Result<PresentationObjects, TestError> {
+        let mut objects = PresentationObjects::default();
+        let mut status_lists = BTreeSet::new();
+
+        for credential in presented_credentials(presentation)? {
+            let schema_id = SchemaId::new_unchecked(credential.schema_id.as_str());
+            if let Some(schema) = self.schemas.get(&schema_id) {
+                objects.schemas.insert(schema_id, schema.clone());
+            }
+            let cred_def_id = CredentialDefinitionId::new_unchecked(credential.cred_def_id.as_str());
+            if let Some(cred_def) = self.cred_defs.get(&cred_def_id) {
+                let cred_def = cred_def
+                    .try_clone()
+                    .map_err(|e| TestError(format!("Error cloning cred def {}: {e}", cred_def_id.0)))?;
+                objects.cred_defs.insert(cred_def_id, cred_def);
+            }
+
+            let Some(rev_reg_id) = credential.rev_reg_id else {
+                continue;
+            };
+            let rev_reg_def_id = RevocationRegistryDefinitionId::new_unchecked(rev_reg_id.as_str());
+            if let Some(rev_reg_def) = self.rev_reg_defs.get(&rev_reg_def_id) {
+                objects.rev_reg_defs.insert(rev_reg_def_id, rev_reg_def.clone());
+            }
+            // Each sub proof names the status list it was made against by its timestamp
+            if let Some(timestamp) = credential.timestamp
+                && status_lists.insert((rev_reg_id.clone(), timestamp))
+                && let Some(list) = self.resolve_rev_status_list(&rev_reg_id, timestamp)
+            {
+                objects.rev_status_lists.push(list.clone());
+            }
+        }
+        Ok(objects)
+    }
+}
+
+// Every `from` the request asks for, globally or on a single item
+fn requested_froms(pres_req: &PresentationRequest) -> BTreeSet<u64> {
+    let request = pres_req.value();
+    let intervals = request
+        .non_revoked
+        .iter()
+        .chain(
+            request
+                .requested_attributes
+                .values()
+                .filter_map(|info| info.non_revoked.as_ref()),
+        )
+        .chain(
+            request
+                .requested_predicates
+                .values()
+                .filter_map(|info| info.non_revoked.as_ref()),
+        );
+    intervals.filter_map(interval_from).collect()
+}
+
+fn interval_from(interval: &NonRevokedInterval) -> Option<u64> {
+    serde_json::to_value(interval).ok()?["from"].as_u64()
+}
+
+impl VerifierWallet {
+    pub fn interval_overrides(
+        &self,
+        ledger: &Ledger,
+        pres_req: &PresentationRequest,
+        rev_reg_def_ids: &[&RevocationRegistryDefinitionId],
+    ) -> HashMap<RevocationRegistryDefinitionId, HashMap<u64, u64>> {
+        let policy = &self.policy.interval_overrides;
+        let froms = requested_froms(pres_req);
+        let mut overrides = HashMap::new();
+
+        for rev_reg_def_id in rev_reg_def_ids {
+            let mut registry_overrides: HashMap<u64, u64> = HashMap::new();
+            if policy.automatic
+                && let Some(first) = ledger
+                    .revocation_list
+                    .get(*rev_reg_def_id)
+                    .and_then(|lists| lists.keys().next())
+            {
+                for from in froms.iter().filter(|from| *from < first) {
+                    registry_overrides.insert(*from, *first);
+                }
+            }
+            if let Some(explicit) = policy.explicit.get(&rev_reg_def_id.0) {
+                registry_overrides.extend(explicit.iter().map(|(from, to)| (*from, *to)));
+            }
+            if !registry_overrides.is_empty() {
+                overrides.insert((*rev_reg_def_id).clone(), registry_overrides);
+            }
+        }
+        overrides
+    }
+
+    pub fn verify_presentation_from_ledger(
+        &mut self,
+        ledger: &Ledger,
+        presentation: &Presentations,
+        pres_req: &PresentationRequest,
+    ) -> VerificationReport {
+        self.verify_presentation_from_ledger_at(ledger, presentation, pres_req, unix_now())
+    }
+
+    pub fn verify_presentation_from_ledger_at(
+        &mut self,
+        ledger: &Ledger,
+        presentation: &Presentations,
+        pres_req: &PresentationRequest,
+        now: u64,
+    ) -> VerificationReport {
+        let report = self.check_presentation_from_ledger_at(ledger, presentation, pres_req, now);
+        if report.verified {
+            self.nonces.consume(&pres_req.value().nonce, now);
+        }
+        report
+    }
+
+    // Leaves the request nonce unused, see `check_presentation_at`
+    pub(crate) fn check_presentation_from_ledger_at(
+        &self,
+        ledger: &Ledger,
+        presentation: &Presentations,
+        pres_req: &PresentationRequest,
+        now: u64,
+    ) -> VerificationReport {
+        // A presentation whose identifiers cannot be read is reported as malformed by the verification itself
+        let objects = ledger.resolve_presentation(presentation).unwrap_or_default();
+        let rev_reg_def_ids: Vec<_> = objects.rev_reg_defs.keys().collect();
+        let overrides = self.interval_overrides(ledger, pres_req, &rev_reg_def_ids);
+        self.check_presentation_at(
+            presentation,
+            pres_req,
+            &objects.schemas,
+            &objects.cred_defs,
+            objects.rev_reg_defs(),
+            objects.rev_status_lists(),
+            (!overrides.is_empty()).then_some(&overrides),
+            now,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::testing::{Issued, NOW, issued_with};
+    use crate::utils::{CredentialFormat, PresentationFormat, RequestSpec};
+
+    fn rev_reg_id(issued: &Issued) -> RevocationRegistryDefinitionId {
+        RevocationRegistryDefinitionId::new_unchecked(issued.fixture.rev_reg().unwrap().id.as_str())
+    }
+
+    // Publishes the unchanged status list again at `timestamp`
+    fn republish(issued: &mut Issued, timestamp: u64) {
+        let rev_reg_id = issued.fixture.rev_reg().unwrap().id.clone();
+        let cred_def =
+            &issued.ledger.cred_defs[&CredentialDefinitionId::new_unchecked(issued.fixture.cred_def.id.as_str())];
+        let (_, current) = issued.ledger.latest_rev_status_list(&rev_reg_id).unwrap();
+        let list = issued
+            .issuer
+            .update_revocation_status_list(cred_def, &rev_reg_id, current, None, None, Some(timestamp))
+            .unwrap();
+        issued.ledger.add_rev_status_list(&rev_reg_id, timestamp, &list);
+    }
+
+    fn request_from(issued: &mut Issued, from: u64) -> PresentationRequest {
+        let spec: RequestSpec = serde_json::from_value(json!({
+            "name": "proof",
+            "attributes": { "name": { "name": "name" } },
+            "non_revoked": { "from": from, "to": NOW + 100 },
+        }))
+        .unwrap();
+        spec.build(&mut issued.verifier, NOW).unwrap()
+    }
+
+    #[test]
+    fn status_lists_are_picked_by_the_timestamp_of_each_sub_proof() {
+        let mut issued = issued_with(CredentialFormat::Legacy, true);
+        issued.holder.refresh_revocation_states(&issued.ledger).unwrap();
+        republish(&mut issued, NOW + 50);
+
+        let spec = json!({
+            "name": "proof",
+            "attributes": { "name": { "name": "name" } },
+            "non_revoked": { "from": NOW, "to": NOW },
+        });
+        let (_, presentation) = issued.present(spec, &PresentationFormat::Legacy);
+        let objects = issued.ledger.resolve_presentation(&presentation).unwrap();
+
+        assert_eq!(objects.rev_status_lists.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&objects.rev_status_lists[0]).unwrap()["timestamp"],
+            json!(NOW)
+        );
+        assert!(objects.rev_reg_defs.contains_key(&rev_reg_id(&issued)));
+    }
+
+    #[test]
+    fn froms_before_the_first_status_list_are_moved_to_it() {
+        let mut issued = issued_with(CredentialFormat::Legacy, true);
+        republish(&mut issued, NOW + 50);
+        let id = rev_reg_id(&issued);
+
+        let early = request_from(&mut issued, NOW - 50);
+        let overrides = issued.verifier.interval_overrides(&issued.ledger, &early, &[&id]);
+        assert_eq!(overrides[&id], HashMap::from([(NOW - 50, NOW)]));
+
+        // A `from` the registry already had a list for is left alone
+        let late = request_from(&mut issued, NOW + 10);
+        assert!(
+            issued
+                .verifier
+                .interval_overrides(&issued.ledger, &late, &[&id])
+                .is_empty()
+        );
+
+        issued.verifier.policy.interval_overrides.automatic = false;
+        assert!(
+            issued
+                .verifier
+                .interval_overrides(&issued.ledger, &early, &[&id])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn explicit_overrides_take_precedence() {
+        let mut issued = issued_with(CredentialFormat::Legacy, true);
+        let id = rev_reg_id(&issued);
+        issued.verifier.policy.interval_overrides.explicit.insert(
+            id.0.clone(),
+            HashMap::from([(NOW - 50, NOW + 20), (NOW + 10, NOW + 30)]),
+        );
+
+        let early = request_from(&mut issued, NOW - 50);
+        let overrides = issued.verifier.interval_overrides(&issued.ledger, &early, &[&id]);
+        assert_eq!(
+            overrides[&id],
+            HashMap::from([(NOW - 50, NOW + 20), (NOW + 10, NOW + 30)])
+        );
+
+        // Explicit overrides apply without the automatic ones
+        issued.verifier.policy.interval_overrides.automatic = false;
+        let overrides = issued.verifier.interval_overrides(&issued.ledger, &early, &[&id]);
+        assert_eq!(overrides[&id][&(NOW - 50)], NOW + 20);
+    }
+}
@@ -0,0 +1,620 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anoncreds::types::PresentationRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::evidence::PresentationBundle;
+use super::fixtures::{CredentialFixture, FIXTURES_DIR, load_fixtures};
+use super::mock::{CredentialFormat, PresentationFormat, TestError};
+use super::policy::{PolicyRule, TrustPolicy};
+use super::report::{ReasonCode, VerificationReport};
+use super::request::RequestSpec;
+use super::selection::HolderChoices;
+use super::session::SessionStore;
+use super::storage::{IssuerWallet, Ledger, ProverWallet, VerifierWallet};
+
+pub const SCENARIOS_DIR: &str = "scenarios";
+
+// Scenario wallets only live for the run, a fixed passphrase is enough to go through their encryption
+const SCENARIO_PASSPHRASE: &str = "correct horse battery staple";
+
+// A scripted run of issuers, holders and verifiers against one ledger
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_fixtures_dir")]
+    pub fixtures: PathBuf,
+    // Applied by every verifier, none means everything is accepted once verified
+    #[serde(default)]
+    pub trust_policy: Option<PathBuf>,
+    #[serde(default = "default_credential_format")]
+    pub credential_format: CredentialFormat,
+    #[serde(default = "default_presentation_format")]
+    pub presentation_format: PresentationFormat,
+    // Ledger time the scenario starts at
+    #[serde(default)]
+    pub start_time: u64,
+    pub actors: Actors,
+    pub steps: Vec<Step>,
+}
+
+fn default_fixtures_dir() -> PathBuf {
+    PathBuf::from(FIXTURES_DIR)
+}
+
+fn default_credential_format() -> CredentialFormat {
+    CredentialFormat::W3C
+}
+
+fn default_presentation_format() -> PresentationFormat {
+    PresentationFormat::W3C
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Actors {
+    pub issuers: Vec<String>,
+    pub holders: Vec<String>,
+    pub verifiers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    // Publishes the schema and cred def of a fixture, and its revocation registry when revocable
+    Publish {
+        issuer: String,
+        credential_type: String,
+        #[serde(default)]
+        revocable: bool,
+        #[serde(default)]
+        midnight: bool,
+    },
+    Issue {
+        holder: String,
+        credential_type: String,
+        credential_id: String,
+        // Next free index when absent
+        #[serde(default)]
+        rev_idx: Option<u32>,
+        // Replace the fixture's sample values
+        #[serde(default)]
+        values: BTreeMap<String, String>,
+    },
+    Revoke {
+        credential_type: String,
+        indices: BTreeSet<u32>,
+    },
+    AdvanceClock {
+        seconds: u64,
+    },
+    // Saves every issuer and holder wallet encrypted and continues with the restored copies
+    ReloadWallets,
+    Present {
+        holder: String,
+        verifier: String,
+        request: RequestSpec,
+        #[serde(default)]
+        self_attested: HashMap<String, String>,
+        // Referents proven from a credential without revealing the value
+        #[serde(default)]
+        unrevealed: BTreeSet<String>,
+        // Seconds between the request and the verification
+        #[serde(default)]
+        respond_after: u64,
+        #[serde(default)]
+        expect: Expectation,
+    },
+    // Sends the last presentation to `verifier` again
+    Replay {
+        verifier: String,
+        #[serde(default)]
+        expect: Expectation,
+    },
+}
+
+impl Step {
+    pub fn action(&self) -> &'static str {
+        match self {
+            Step::Publish { .. } => "publish",
+            Step::Issue { .. } => "issue",
+            Step::Revoke { .. } => "revoke",
+            Step::AdvanceClock { .. } => "advance_clock",
+            Step::ReloadWallets => "reload_wallets",
+            Step::Present { .. } => "present",
+            Step::Replay { .. } => "replay",
+        }
+    }
+}
+
+// Only the listed outcomes are checked
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expectation {
+    pub verified: Option<bool>,
+    pub accepted: Option<bool>,
+    // Each has to be among the failures
+    pub failures: Vec<ReasonCode>,
+    pub policy_violations: Vec<PolicyRule>,
+    // referent: attribute name: raw value
+    pub disclosed: BTreeMap<String, BTreeMap<String, String>>,
+    // referent: satisfied
+    pub predicates: BTreeMap<String, bool>,
+    // The holder cannot answer the request, and the error contains this text
+    pub error: Option<String>,
+}
+
+impl Expectation {
+    fn check(&self, report: &VerificationReport) -> Vec<String> {
+        let mut mismatches = vec![];
+        if self.error.is_some() {
+            mismatches.push("expected the holder to fail, but a presentation was made".to_string());
+        }
+        if let Some(verified) = self.verified
+            && report.verified != verified
+        {
+            mismatches.push(format!("verified is {}, expected {verified}", report.verified));
+        }
+        if let Some(accepted) = self.accepted
+            && report.accepted() != accepted
+        {
+            mismatches.push(format!("accepted is {}, expected {accepted}", report.accepted()));
+        }
+        for reason in self.failures.iter() {
+            if !report.failed(*reason) {
+                mismatches.push(format!("no {reason:?} failure"));
+            }
+        }
+        for rule in self.policy_violations.iter() {
+            if !report.policy_violations.iter().any(|violation| &violation.rule == rule) {
+                mismatches.push(format!("no {rule:?} policy violation"));
+            }
+        }
+
+        let disclosure = report.disclosure();
+        for (referent, values) in self.disclosed.iter() {
+            for (name, expected) in values.iter() {
+                match disclosure.value(referent, name) {
+                    Some(value) if value == expected => {}
+                    found => mismatches.push(format!("{referent}.{name} is {found:?}, expected {expected}")),
+                }
+            }
+        }
+        for (referent, expected) in self.predicates.iter() {
+            let satisfied = disclosure
+                .predicates
+                .get(referent)
+                .and_then(|predicate| predicate.satisfied);
+            if satisfied != Some(*expected) {
+                mismatches.push(format!("{referent} is {satisfied:?}, expected {expected}"));
+            }
+        }
+        mismatches
+    }
+
+    fn check_error(&self, error: &TestError) -> Vec<String> {
+        match &self.error {
+            Some(expected) if error.0.contains(expected.as_str()) => vec![],
+            _ => vec![format!("presentation failed: {}", error.0)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepOutcome {
+    pub index: usize,
+    pub action: &'static str,
+    pub mismatches: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub steps: Vec<StepOutcome>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.mismatches.is_empty())
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, TestError> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| TestError(format!("Error reading scenario {}: {e}", path.display())))?;
+        serde_json::from_str(&json).map_err(|e| TestError(format!("Invalid scenario {}: {e}", path.display())))
+    }
+
+    // Steps that cannot be carried out end the run with an error, unexpected outcomes are reported
+    pub fn run(&self) -> Result<ScenarioReport, TestError> {
+        let mut runner = ScenarioRunner::new(self)?;
+        let mut steps = vec![];
+        for (index, step) in self.steps.iter().enumerate() {
+            let mismatches = runner
+                .run_step(step)
+                .map_err(|e| TestError(format!("{}: step {index} ({}): {}", self.name, step.action(), e.0)))?;
+            steps.push(StepOutcome {
+                index,
+                action: step.action(),
+                mismatches,
+            });
+        }
+        Ok(ScenarioReport {
+            name: self.name.clone(),
+            steps,
+        })
+    }
+}
+
+// Every `*.json` scenario in `dir`, ordered by file name
+pub fn load_scenarios(dir: &Path) -> Result<Vec<Scenario>, TestError> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| TestError(format!("Error reading scenarios {}: {e}", dir.display())))?;
+    let mut paths = vec![];
+    for entry in entries {
+        let path = entry
+            .map_err(|e| TestError(format!("Error reading scenarios {}: {e}", dir.display())))?
+            .path();
+        if path.extension().and_then(|extension| extension.to_str()) == Some("json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths.iter().map(|path| Scenario::load(path)).collect()
+}
+
+// A published credential type
+struct Publication {
+    issuer: String,
+    rev_reg_id: Option<String>,
+    next_rev_idx: u32,
+}
+
+struct ScenarioRunner<'a> {
+    scenario: &'a Scenario,
+    now: u64,
+    ledger: Ledger,
+    fixtures: BTreeMap<String, CredentialFixture>,
+    issuers: BTreeMap<String, IssuerWallet>,
+    holders: BTreeMap<String, ProverWallet>,
+    verifiers: BTreeMap<String, VerifierWallet>,
+    publications: BTreeMap<String, Publication>,
+    sessions: SessionStore,
+    wallet_dir: PathBuf,
+    last_presentation: Option<(PresentationBundle, PresentationRequest)>,
+}
+
+impl<'a> ScenarioRunner<'a> {
+    fn new(scenario: &'a Scenario) -> Result<Self, TestError> {
+        let fixtures = load_fixtures(&scenario.fixtures)?;
+        let policy = match &scenario.trust_policy {
+            Some(path) => TrustPolicy::load(path)?,
+            None => TrustPolicy::default(),
+        };
+        let wallet_dir = std::env::temp_dir().join("anoncreds-scenarios").join(&scenario.name);
+        Ok(Self {
+            scenario,
+            now: scenario.start_time,
+            ledger: Ledger::default(),
+            fixtures,
+            issuers: actors(&scenario.actors.issuers, IssuerWallet::default)?,
+            holders: actors(&scenario.actors.holders, ProverWallet::default)?,
+            verifiers: actors(&scenario.actors.verifiers, || {
+                VerifierWallet::with_policy(policy.clone())
+            })?,
+            publications: BTreeMap::new(),
+            sessions: SessionStore::new(wallet_dir.join("sessions"))?,
+            wallet_dir,
+            last_presentation: None,
+        })
+    }
+
+    fn run_step(&mut self, step: &Step) -> Result<Vec<String>, TestError> {
+        match step {
+            Step::Publish {
+                issuer,
+                credential_type,
+                revocable,
+                midnight,
+            } => self
+                .publish(issuer, credential_type, *revocable, *midnight)
+                .map(|_| vec![]),
+            Step::Issue {
+                holder,
+                credential_type,
+                credential_id,
+                rev_idx,
+                values,
+            } => self
+                .issue(holder, credential_type, credential_id, *rev_idx, values)
+                .map(|_| vec![]),
+            Step::Revoke {
+                credential_type,
+                indices,
+            } => self.revoke(credential_type, indices).map(|_| vec![]),
+            Step::AdvanceClock { seconds } => {
+                self.now += seconds;
+                Ok(vec![])
+            }
+            Step::ReloadWallets => self.reload_wallets().map(|_| vec![]),
+            Step::Present {
+                holder,
+                verifier,
+                request,
+                self_attested,
+                unrevealed,
+                respond_after,
+                expect,
+            } => {
+                let choices = HolderChoices {
+                    self_attested: self_attested.clone(),
+                    unrevealed: unrevealed.clone(),
+                };
+                self.present(holder, verifier, request, &choices, *respond_after, expect)
+            }
+            Step::Replay { verifier, expect } => self.replay(verifier, expect),
+        }
+    }
+
+    fn publish(
+        &mut self,
+        issuer: &str,
+        credential_type: &str,
+        revocable: bool,
+        midnight: bool,
+    ) -> Result<(), TestError> {
+        if self.publications.contains_key(credential_type) {
+            return Err(TestError(format!("{credential_type} is already published")));
+        }
+        let fixture = fixture(&self.fixtures, credential_type)?;
+        let wallet = actor(&mut self.issuers, issuer)?;
+
+        let (schema, _) = wallet.create_schema(&mut self.ledger, fixture)?;
+        let (cred_def, _) = wallet.create_cred_def(&mut self.ledger, fixture, &schema, revocable)?;
+        let rev_reg_id = if revocable {
+            let (rev_reg_id, _, _) =
+                wallet.create_revocation_registry(&mut self.ledger, fixture, &cred_def, Some(self.now), true)?;
+            if midnight {
+                wallet.deploy_midnight_registry(&mut self.ledger, &rev_reg_id)?;
+            }
+            Some(rev_reg_id)
+        } else {
+            None
+        };
+
+        self.publications.insert(
+            credential_type.to_string(),
+            Publication {
+                issuer: issuer.to_string(),
+                rev_reg_id,
+                next_rev_idx: 1,
+            },
+        );
+        Ok(())
+    }
+
+    fn issue(
+        &mut self,
+        holder: &str,
+        credential_type: &str,
+        credential_id: &str,
+        rev_idx: Option<u32>,
+        values: &BTreeMap<String, String>,
+    ) -> Result<(), TestError> {
+        let fixture = fixture(&self.fixtures, credential_type)?;
+        let publication = self
+            .publications
+            .get_mut(credential_type)
+            .ok_or_else(|| TestError(format!("{credential_type} is not published")))?;
+        let issuer = self
+            .issuers
+            .get_mut(&publication.issuer)
+            .ok_or_else(|| TestError(format!("Unknown issuer {}", publication.issuer)))?;
+        let prover = actor(&mut self.holders, holder)?;
+
+        let values = if values.is_empty() { &fixture.values } else { values };
+        let cred_values = fixture.credential_values_for(values)?;
+
+        let (thread_id, offer) = issuer.offer_credential(&self.sessions, &fixture.schema.id, &fixture.cred_def.id)?;
+        prover.receive_offer(&self.sessions, &self.ledger, &thread_id, offer)?;
+        let cred_request = prover.request_credential(&self.sessions, &self.ledger, &thread_id, None)?;
+
+        let revocation = match &publication.rev_reg_id {
+            Some(rev_reg_id) => {
+                let rev_idx = rev_idx.unwrap_or(publication.next_rev_idx);
+                publication.next_rev_idx = publication.next_rev_idx.max(rev_idx + 1);
+                let (_, status_list) = self
+                    .ledger
+                    .latest_rev_status_list(rev_reg_id)
+                    .ok_or_else(|| TestError(format!("No status list for {rev_reg_id}")))?;
+                Some((rev_reg_id.clone(), rev_idx, status_list.clone()))
+            }
+            None => None,
+        };
+
+        issuer.receive_request(&self.sessions, &thread_id, &cred_request)?;
+        let mut credential = issuer.issue_requested_credential(
+            &self.sessions,
+            &thread_id,
+            &self.scenario.credential_format,
+            cred_values.into(),
+            revocation.as_ref().map(|(rev_reg_id, _, _)| rev_reg_id.as_str()),
+            revocation.as_ref().map(|(_, _, status_list)| status_list),
+            revocation.as_ref().map(|(_, rev_idx, _)| *rev_idx),
+            None,
+        )?;
+
+        if let Some((rev_reg_id, rev_idx, status_list)) = &revocation {
+            let (_, commitment) = prover.midnight_commitment(&thread_id)?;
+            let cred_def = &issuer
+                .cred_defs
+                .get(&fixture.cred_def.id)
+                .ok_or_else(|| TestError(format!("Issuer has no cred def {}", fixture.cred_def.id)))?
+                .public;
+            let (_, batch) = issuer.issue_credentials_batch(
+                &mut self.ledger,
+                cred_def,
+                rev_reg_id,
+                status_list,
+                &BTreeMap::from([(*rev_idx, commitment)]),
+                Some(self.now),
+            )?;
+            tracing::info!(?batch, "registered credentials");
+        }
+
+        prover.receive_credential(&self.sessions, &self.ledger, &thread_id, credential_id, &mut credential)
+    }
+
+    fn revoke(&mut self, credential_type: &str, indices: &BTreeSet<u32>) -> Result<(), TestError> {
+        let fixture = fixture(&self.fixtures, credential_type)?;
+        let publication = self
+            .publications
+            .get(credential_type)
+            .ok_or_else(|| TestError(format!("{credential_type} is not published")))?;
+        let rev_reg_id = publication
+            .rev_reg_id
+            .as_ref()
+            .ok_or_else(|| TestError(format!("{credential_type} is not revocable")))?;
+        let issuer = self
+            .issuers
+            .get(&publication.issuer)
+            .ok_or_else(|| TestError(format!("Unknown issuer {}", publication.issuer)))?;
+        if indices.is_empty() {
+            return Err(TestError("Nothing to revoke".to_string()));
+        }
+
+        let (_, status_list) = self
+            .ledger
+            .latest_rev_status_list(rev_reg_id)
+            .ok_or_else(|| TestError(format!("No status list for {rev_reg_id}")))?;
+        let status_list = status_list.clone();
+        let cred_def = &issuer
+            .cred_defs
+            .get(&fixture.cred_def.id)
+            .ok_or_else(|| TestError(format!("Issuer has no cred def {}", fixture.cred_def.id)))?
+            .public;
+        let (_, batch) = issuer.revoke_credentials_batch(
+            &mut self.ledger,
+            cred_def,
+            rev_reg_id,
+            &status_list,
+            indices,
+            Some(self.now),
+        )?;
+        tracing::info!(?batch, "revoked credentials");
+        Ok(())
+    }
+
+    fn reload_wallets(&mut self) -> Result<(), TestError> {
+        for (name, wallet) in self.issuers.iter_mut() {
+            let path = self.wallet_dir.join(format!("issuer-{name}.json"));
+            wallet.save_encrypted(&path, SCENARIO_PASSPHRASE)?;
+            *wallet = IssuerWallet::load_encrypted(&path, SCENARIO_PASSPHRASE)?;
+        }
+        for (name, wallet) in self.holders.iter_mut() {
+            let path = self.wallet_dir.join(format!("holder-{name}.json"));
+            wallet.save_encrypted(&path, SCENARIO_PASSPHRASE)?;
+            *wallet = ProverWallet::load_encrypted(&path, SCENARIO_PASSPHRASE)?;
+        }
+        Ok(())
+    }
+
+    fn present(
+        &mut self,
+        holder: &str,
+        verifier: &str,
+        spec: &RequestSpec,
+        choices: &HolderChoices,
+        respond_after: u64,
+        expect: &Expectation,
+    ) -> Result<Vec<String>, TestError> {
+        let verifier_wallet = actor(&mut self.verifiers, verifier)?;
+        let pres_request = spec.build(verifier_wallet, self.now)?;
+
+        let prover = actor(&mut self.holders, holder)?;
+        let refreshed = prover.refresh_revocation_states(&self.ledger)?;
+        tracing::info!(?refreshed, "refreshed revocation states");
+
+        let presentation = match prover.present_with_evidence(
+            &self.ledger,
+            &self.scenario.presentation_format,
+            &pres_request,
+            choices,
+            self.now,
+        ) {
+            Ok(presentation) => presentation,
+            Err(e) => return Ok(expect.check_error(&e)),
+        };
+
+        let report = self.verify(verifier, &presentation, &pres_request, self.now + respond_after)?;
+        self.last_presentation = Some((presentation, pres_request));
+        Ok(expect.check(&report))
+    }
+
+    fn replay(&mut self, verifier: &str, expect: &Expectation) -> Result<Vec<String>, TestError> {
+        let (presentation, pres_request) = self
+            .last_presentation
+            .take()
+            .ok_or_else(|| TestError("No presentation to replay".to_string()))?;
+        let report = self.verify(verifier, &presentation, &pres_request, self.now)?;
+        self.last_presentation = Some((presentation, pres_request));
+        Ok(expect.check(&report))
+    }
+
+    fn verify(
+        &mut self,
+        verifier: &str,
+        presentation: &PresentationBundle,
+        pres_request: &PresentationRequest,
+        now: u64,
+    ) -> Result<VerificationReport, TestError> {
+        let verifier_wallet = actor(&mut self.verifiers, verifier)?;
+        let mut report = verifier_wallet.verify_bundle_at(&self.ledger, presentation, pres_request, now);
+        verifier_wallet.apply_policy(&mut report, &self.ledger, now);
+        tracing::info!(?report, "verification report");
+        Ok(report)
+    }
+}
+
+fn fixture<'f>(
+    fixtures: &'f BTreeMap<String, CredentialFixture>,
+    credential_type: &str,
+) -> Result<&'f CredentialFixture, TestError> {
+    fixtures
+        .get(credential_type)
+        .ok_or_else(|| TestError(format!("No fixture for credential type {credential_type}")))
+}
+
+fn actors<T>(names: &[String], create: impl Fn() -> T) -> Result<BTreeMap<String, T>, TestError> {
+    let mut wallets = BTreeMap::new();
+    for name in names {
+        if wallets.insert(name.clone(), create()).is_some() {
+            return Err(TestError(format!("Actor {name} is declared twice")));
+        }
+    }
+    Ok(wallets)
+}
+
+fn actor<'w, T>(wallets: &'w mut BTreeMap<String, T>, name: &str) -> Result<&'w mut T, TestError> {
+    wallets
+        .get_mut(name)
+        .ok_or_else(|| TestError(format!("Unknown actor {name}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_scenarios_parse() {
+        let scenarios = load_scenarios(Path::new(SCENARIOS_DIR)).unwrap();
+        assert!(scenarios.iter().any(|scenario| scenario.name == "gvt_shared_registry"));
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anoncreds::data_types::pres_request::{AttributeInfo, NonRevokedInterval, PredicateTypes};
+use anoncreds::types::PresentationRequest;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::mock::{
+    CredentialToPresent, PresentAttribute, PresentAttributeForm, PresentationFormat, Presentations, TestError,
+};
+use super::query::{CredentialQuery, HeldCredential, attr_common_view, credential_tags};
+use super::report::interval_bounds;
+use super::storage::{Ledger, ProverWallet};
+
+// The holder's choices for a request: referents answered by their own claim, and single attributes that are
+// only proven to be held without revealing their value. Every other attribute is revealed
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HolderChoices {
+    pub self_attested: HashMap<String, String>,
+    pub unrevealed: BTreeSet<String>,
+}
+
+fn satisfies_predicate(p_type: &PredicateTypes, value: i32, p_value: i32) -> bool {
+    match p_type {
+        PredicateTypes::GE => value >= p_value,
+        PredicateTypes::LE => value <= p_value,
+        PredicateTypes::GT => value > p_value,
+        PredicateTypes::LT => value < p_value,
+    }
+}
+
+impl ProverWallet {
+    // Selects credentials for `pres_request` and creates the presentation against everything on the ledger
+    pub fn present(
+        &self,
+        ledger: &Ledger,
+        format: &PresentationFormat,
+        pres_request: &PresentationRequest,
+        choices: &HolderChoices,
+    ) -> Result<Presentations, TestError> {
+        self.present_selected(ledger, format, pres_request, choices)
+            .map(|(presentation, _)| presentation)
+    }
+
+    // Same as `present`, also returning which credentials were presented
+    pub(crate) fn present_selected(
+        &self,
+        ledger: &Ledger,
+        format: &PresentationFormat,
+        pres_request: &PresentationRequest,
+        choices: &HolderChoices,
+    ) -> Result<(Presentations, Vec<CredentialToPresent>), TestError> {
+        let present_credentials = self.select_credentials_with(ledger, format, pres_request, choices)?;
+        let self_attested = &choices.self_attested;
+        let schemas = ledger.resolve_schemas(ledger.schemas.keys().map(|id| id.0.as_str()).collect());
+        let cred_defs = ledger.resolve_cred_defs(ledger.cred_defs.keys().map(|id| id.0.as_str()).collect());
+        let presentation = self.create_presentation(
+            format,
+            &schemas,
+            &cred_defs,
+            pres_request,
+            &present_credentials,
+            (!self_attested.is_empty()).then(|| self_attested.clone()),
+            None,
+        )?;
+        Ok((presentation, present_credentials))
+    }
+
+    // Proposes the credentials to present for a request, revealing every attribute, or explains why a referent
+    // cannot be satisfied
+    pub fn select_credentials(
+        &self,
+        ledger: &Ledger,
+        format: &PresentationFormat,
+        pres_request: &PresentationRequest,
+    ) -> Result<Vec<CredentialToPresent>, TestError> {
+        self.select_credentials_with(ledger, format, pres_request, &HolderChoices::default())
+    }
+
+    // Same as `select_credentials`, leaving the self-attested referents out of the proposal and keeping the
+    // unrevealed ones hidden
+    pub fn select_credentials_with(
+        &self,
+        ledger: &Ledger,
+        format: &PresentationFormat,
+        pres_request: &PresentationRequest,
+        choices: &HolderChoices,
+    ) -> Result<Vec<CredentialToPresent>, TestError> {
+        let requested = &pres_request.value().requested_attributes;
+        let self_attested = &choices.self_attested;
+        let mut referents: Vec<&String> = self_attested.keys().collect();
+        referents.sort();
+        for referent in referents {
+            let info = requested
+                .get(referent)
+                .ok_or_else(|| TestError(format!("{referent}: self-attested attribute was not requested")))?;
+            if !allows_self_attestation(info) {
+                return Err(TestError(format!(
+                    "{referent}: restricted attributes cannot be self-attested"
+                )));
+            }
+        }
+
+        // Anoncreds only hides single attributes, attribute groups are always revealed
+        for referent in &choices.unrevealed {
+            let info = requested
+                .get(referent)
+                .ok_or_else(|| TestError(format!("{referent}: unrevealed attribute was not requested")))?;
+            if info.name.is_none() {
+                return Err(TestError(format!(
+                    "{referent}: attribute groups cannot be left unrevealed"
+                )));
+            }
+            if self_attested.contains_key(referent) {
+                return Err(TestError(format!(
+                    "{referent}: an attribute cannot be both self-attested and unrevealed"
+                )));
+            }
+        }
+
+        match format {
+            PresentationFormat::Legacy => self.select_from(ledger, &self.credentials, pres_request, choices),
+            PresentationFormat::W3C => self.select_from(ledger, &self.w3c_credentials, pres_request, choices),
+        }
+    }
+
+    fn select_from<T: HeldCredential>(
+        &self,
+        ledger: &Ledger,
+        credentials: &HashMap<String, T>,
+        pres_request: &PresentationRequest,
+        choices: &HolderChoices,
+    ) -> Result<Vec<CredentialToPresent>, TestError> {
+        let request = pres_request.value();
+        let self_attested = &choices.self_attested;
+
+        // Sorted for a deterministic proposal
+        let candidates: BTreeMap<&String, (&T, HashMap<String, String>)> = credentials
+            .iter()
+            .map(|(id, credential)| (id, (credential, credential_tags(credential, ledger))))
+            .collect();
+
+        let mut selected: BTreeMap<String, Vec<PresentAttribute>> = BTreeMap::new();
+
+        let mut attributes: Vec<_> = request
+            .requested_attributes
+            .iter()
+            .filter(|(referent, _)| !self_attested.contains_key(*referent))
+            .collect();
+        attributes.sort_by_key(|(referent, _)| *referent);
+        for (referent, info) in attributes {
+            let names: Vec<String> = match (&info.name, &info.names) {
+                (Some(name), _) => vec![name.clone()],
+                (None, Some(names)) => names.clone(),
+                (None, None) => return Err(TestError(format!("{referent}: neither `name` nor `names` is set"))),
+            };
+            let restrictions = parse_restrictions(referent, info.restrictions.as_ref())?;
+            let interval = info.non_revoked.as_ref().or(request.non_revoked.as_ref());
+
+            let mut reasons = vec![];
+            let found = self.pick_candidate(&candidates, &selected, interval, &mut reasons, |credential, tags| {
+                let values = credential.raw_values();
+                if let Some(missing) = names.iter().find(|name| !values.contains_key(&attr_common_view(name))) {
+                    return Err(format!("has no attribute `{missing}`"));
+                }
+                match &restrictions {
+                    Some(restrictions) if !restrictions.matches(tags) => {
+                        Err("does not match the restrictions".to_string())
+                    }
+                    _ => Ok(()),
+                }
+            });
+            let id = found.ok_or_else(|| no_match(referent, &reasons))?;
+            let form = if choices.unrevealed.contains(referent) {
+                PresentAttributeForm::UnrevealedAttribute
+            } else {
+                PresentAttributeForm::RevealedAttribute
+            };
+            selected.entry(id).or_default().push(PresentAttribute {
+                referent: referent.clone(),
+                form,
+            });
+        }
+
+        let mut predicates: Vec<_> = request.requested_predicates.iter().collect();
+        predicates.sort_by_key(|(referent, _)| *referent);
+        for (referent, info) in predicates {
+            let restrictions = parse_restrictions(referent, info.restrictions.as_ref())?;
+            let interval = info.non_revoked.as_ref().or(request.non_revoked.as_ref());
+
+            let mut reasons = vec![];
+            let found = self.pick_candidate(&candidates, &selected, interval, &mut reasons, |credential, tags| {
+                let value = credential
+                    .raw_values()
+                    .remove(&attr_common_view(&info.name))
+                    .ok_or_else(|| format!("has no attribute `{}`", info.name))?;
+                let value: i32 = value
+                    .parse()
+                    .map_err(|_| format!("attribute `{}` is not an integer", info.name))?;
+                if !satisfies_predicate(&info.p_type, value, info.p_value) {
+                    return Err(format!("attribute `{}` does not satisfy the predicate", info.name));
+                }
+                match &restrictions {
+                    Some(restrictions) if !restrictions.matches(tags) => {
+                        Err("does not match the restrictions".to_string())
+                    }
+                    _ => Ok(()),
+                }
+            });
+            let id = found.ok_or_else(|| no_match(referent, &reasons))?;
+            selected.entry(id).or_default().push(PresentAttribute {
+                referent: referent.clone(),
+                form: PresentAttributeForm::Predicate,
+            });
+        }
+
+        Ok(selected
+            .into_iter()
+            .map(|(id, attributes)| CredentialToPresent { id, attributes })
+            .collect())
+    }
+
+    // Prefers a credential that is already part of the proposal, to keep the presentation small
+    fn pick_candidate<T: HeldCredential>(
+        &self,
+        candidates: &BTreeMap<&String, (&T, HashMap<String, String>)>,
+        selected: &BTreeMap<String, Vec<PresentAttribute>>,
+        interval: Option<&NonRevokedInterval>,
+        reasons: &mut Vec<String>,
+        check: impl Fn(&T, &HashMap<String, String>) -> Result<(), String>,
+    ) -> Option<String> {
+        let mut matching = vec![];
+        for (id, (credential, tags)) in candidates.iter() {
+            let result = check(credential, tags).and_then(|_| self.check_revocation_state(id, *credential, interval));
+            match result {
+                Ok(()) => matching.push((*id).clone()),
+                Err(reason) => reasons.push(format!("credential `{id}` {reason}")),
+            }
+        }
+        matching
+            .iter()
+            .find(|id| selected.contains_key(*id))
+            .or(matching.first())
+            .cloned()
+    }
+
+    // Revocable credentials can only be presented once a revocation state is known, and the state has to fall
+    // into the interval the verifier asked for
+    fn check_revocation_state<T: HeldCredential>(
+        &self,
+        id: &str,
+        credential: &T,
+        interval: Option<&NonRevokedInterval>,
+    ) -> Result<(), String> {
+        let Some(rev_reg_id) = credential.rev_reg_id() else {
+            return Ok(());
+        };
+        let timestamp = match self.rev_states.get(id) {
+            Some((Some(_), timestamp)) => *timestamp,
+            _ => return Err(format!("has no revocation state for registry `{}`", rev_reg_id.0)),
+        };
+        match (interval, timestamp) {
+            (Some(interval), Some(timestamp)) => check_interval(timestamp, interval),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_interval(timestamp: u64, interval: &NonRevokedInterval) -> Result<(), String> {
+    let (from, to) = interval_bounds(interval);
+    if from.is_some_and(|from| timestamp < from) || to.is_some_and(|to| timestamp > to) {
+        return Err(format!(
+            "has a revocation state from {timestamp}, outside the requested interval {}..{}",
+            from.map(|from| from.to_string()).unwrap_or_default(),
+            to.map(|to| to.to_string()).unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+// Only a single attribute without restrictions can be answered by the holder's own claim
+pub fn allows_self_attestation(info: &AttributeInfo) -> bool {
+    if info.name.is_none() {
+        return false;
+    }
+    match info.restrictions.as_ref().map(serde_json::to_value) {
+        None => true,
+        Some(Ok(Value::Object(restrictions))) => restrictions.values().all(|value| match value {
+            Value::Array(items) => items.is_empty(),
+            Value::Object(items) => items.is_empty(),
+            _ => false,
+        }),
+        Some(_) => false,
+    }
+}
+
+fn parse_restrictions<T: serde::Serialize>(
+    referent: &str,
+    restrictions: Option<&T>,
+) -> Result<Option<CredentialQuery>, TestError> {
+    restrictions
+        .map(|restrictions| {
+            let restrictions = serde_json::to_value(restrictions).map_err(|e| TestError(e.to_string()))?;
+            CredentialQuery::from_json(&restrictions).map_err(|e| TestError(format!("{referent}: {}", e.0)))
+        })
+        .transpose()
+}
+
+fn no_match(referent: &str, reasons: &[String]) -> TestError {
+    if reasons.is_empty() {
+        TestError(format!("{referent}: the wallet holds no credentials"))
+    } else {
+        TestError(format!("{referent}: no matching credential ({})", reasons.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request() -> PresentationRequest {
+        serde_json::from_value(json!({
+            "nonce": "1234",
+            "name": "pres_req",
+            "version": "0.1",
+            "requested_attributes": {
+                "attr1_referent": { "name": "name" },
+                "attr2_referent": { "names": ["name", "height"] }
+            },
+            "requested_predicates": {}
+        }))
+        .unwrap()
+    }
+
+    fn select(choices: HolderChoices) -> Result<Vec<CredentialToPresent>, TestError> {
+        ProverWallet::default().select_credentials_with(
+            &Ledger::default(),
+            &PresentationFormat::Legacy,
+            &request(),
+            &choices,
+        )
+    }
+
+    #[test]
+    fn only_requested_single_attributes_can_stay_unrevealed() {
+        let unrevealed = |referent: &str| HolderChoices {
+            unrevealed: BTreeSet::from([referent.to_string()]),
+            ..Default::default()
+        };
+        assert!(
+            select(unrevealed("attr3_referent"))
+                .unwrap_err()
+                .0
+                .contains("was not requested")
+        );
+        assert!(
+            select(unrevealed("attr2_referent"))
+                .unwrap_err()
+                .0
+                .contains("attribute groups")
+        );
+        // Valid choices get as far as the empty wallet
+        assert!(
+            select(unrevealed("attr1_referent"))
+                .unwrap_err()
+                .0
+                .contains("holds no credentials")
+        );
+    }
+
+    #[test]
+    fn an_attribute_is_either_self_attested_or_unrevealed() {
+        let choices = HolderChoices {
+            self_attested: HashMap::from([("attr1_referent".to_string(), "Alex".to_string())]),
+            unrevealed: BTreeSet::from(["attr1_referent".to_string()]),
+        };
+        assert!(
+            select(choices)
+                .unwrap_err()
+                .0
+                .contains("both self-attested and unrevealed")
+        );
+    }
+
+    #[test]
+    fn revocation_state_has_to_fall_into_the_interval() {
+        let interval = |value| serde_json::from_value::<NonRevokedInterval>(value).unwrap();
+        assert!(check_interval(50, &interval(json!({ "from": 10, "to": 200 }))).is_ok());
+        assert!(check_interval(10, &interval(json!({ "from": 10, "to": 10 }))).is_ok());
+        assert!(check_interval(5, &interval(json!({ "from": 10, "to": 200 }))).is_err());
+        assert!(check_interval(250, &interval(json!({ "from": 10, "to": 200 }))).is_err());
+        assert!(check_interval(250, &interval(json!({ "from": 10 }))).is_ok());
+    }
+}
@@ -0,0 +1,571 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anoncreds::data_types::w3c::VerifiableCredentialSpecVersion;
+use anoncreds::types::{
+    CredentialOffer, CredentialRequest, CredentialRequestMetadata, CredentialValues, RevocationStatusList,
+};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::mock::{CredentialFormat, Credentials, RevocableCredential, TestError, offer_entropy};
+use super::query::HeldCredential;
+use super::storage::{IssuerWallet, Ledger, ProverWallet};
+use super::vault::to_hex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssuanceRole {
+    Issuer,
+    Holder,
+}
+
+// Offer, then request, then credential, then stored. A state is only saved once the wallet operation that
+// leads to it succeeded: issuers go from `Offered` to `Requested` to `Issued`, holders from `Offered` to
+// `Requested` to `Stored`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssuanceState {
+    Offered,
+    Requested,
+    Issued,
+    Stored,
+}
+
+// Persisted record of one issuance exchange, as seen by one of the parties
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IssuanceSession {
+    pub thread_id: String,
+    pub role: IssuanceRole,
+    pub state: IssuanceState,
+    pub offer: CredentialOffer,
+    pub request: Option<CredentialRequest>,
+    // Holder only
+    pub request_metadata: Option<CredentialRequestMetadata>,
+    pub credential_format: Option<CredentialFormat>,
+    pub credential: Option<Value>,
+    // Holder only, the wallet id the credential is stored under
+    pub credential_id: Option<String>,
+}
+
+impl IssuanceSession {
+    fn expect_state(&self, expected: IssuanceState) -> Result<(), TestError> {
+        if self.state != expected {
+            return Err(TestError(format!(
+                "Session {} is in state {:?}, expected {:?}",
+                self.thread_id, self.state, expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+// One JSON file per session and role, so an exchange can be resumed after a restart
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    pub dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, TestError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| TestError(format!("Error creating session store: {e}")))?;
+        Ok(Self { dir })
+    }
+
+    pub fn save(&self, session: &IssuanceSession) -> Result<(), TestError> {
+        let json = serde_json::to_string_pretty(session).map_err(|e| TestError(e.to_string()))?;
+        fs::write(self.path(session.role, &session.thread_id)?, json)
+            .map_err(|e| TestError(format!("Error writing session {}: {e}", session.thread_id)))
+    }
+
+    pub fn load(&self, role: IssuanceRole, thread_id: &str) -> Result<IssuanceSession, TestError> {
+        let json = fs::read_to_string(self.path(role, thread_id)?)
+            .map_err(|e| TestError(format!("Unknown session {thread_id}: {e}")))?;
+        serde_json::from_str(&json).map_err(|e| TestError(format!("Corrupted session {thread_id}: {e}")))
+    }
+
+    pub fn load_all(&self, role: IssuanceRole) -> Result<Vec<IssuanceSession>, TestError> {
+        let prefix = format!("{}-", role_prefix(role));
+        let mut sessions = vec![];
+        for entry in fs::read_dir(&self.dir).map_err(|e| TestError(e.to_string()))? {
+            let name = entry.map_err(|e| TestError(e.to_string()))?.file_name();
+            let name = name.to_string_lossy();
+            if let Some(thread_id) = name.strip_prefix(&prefix).and_then(|n| n.strip_suffix(".json"))
+                && check_thread_id(thread_id).is_ok()
+            {
+                sessions.push(self.load(role, thread_id)?);
+            }
+        }
+        sessions.sort_by(|a, b| a.thread_id.cmp(&b.thread_id));
+        Ok(sessions)
+    }
+
+    fn path(&self, role: IssuanceRole, thread_id: &str) -> Result<PathBuf, TestError> {
+        check_thread_id(thread_id)?;
+        Ok(self.dir.join(format!("{}-{thread_id}.json", role_prefix(role))))
+    }
+}
+
+fn role_prefix(role: IssuanceRole) -> &'static str {
+    match role {
+        IssuanceRole::Issuer => "issuer",
+        IssuanceRole::Holder => "holder",
+    }
+}
+
+pub fn generate_thread_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+// Thread ids come from the peer and name session files, so only ids as `generate_thread_id` makes them pass
+pub fn check_thread_id(thread_id: &str) -> Result<(), TestError> {
+    if thread_id.len() != 32 || !thread_id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err(TestError(format!("Invalid thread id {thread_id:?}")));
+    }
+    Ok(())
+}
+
+// anoncreds objects are not `Clone`, but every one of them round-trips through serde
+pub fn clone_json<T: Serialize + DeserializeOwned>(value: &T) -> T {
+    serde_json::from_value(serde_json::to_value(value).unwrap()).unwrap()
+}
+
+fn same_json<A: Serialize, B: Serialize>(a: &A, b: &B) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+// `CredentialRequest` keeps its cred def id and entropy private, so they are read from the serialized form
+fn request_field(request: &CredentialRequest, field: &str) -> Option<String> {
+    serde_json::to_value(request).ok()?[field].as_str().map(str::to_string)
+}
+
+fn credential_json(credential: &Credentials) -> (CredentialFormat, Value) {
+    match credential {
+        Credentials::Legacy(credential) => (CredentialFormat::Legacy, serde_json::to_value(credential).unwrap()),
+        Credentials::W3C(credential) => (CredentialFormat::W3C, serde_json::to_value(credential).unwrap()),
+    }
+}
+
+impl IssuerWallet {
+    pub fn offer_credential(
+        &mut self,
+        store: &SessionStore,
+        schema_id: &str,
+        cred_def_id: &str,
+    ) -> Result<(String, CredentialOffer), TestError> {
+        let thread_id = generate_thread_id();
+        let offer = self.create_credential_offer(schema_id, cred_def_id);
+        store.save(&IssuanceSession {
+            thread_id: thread_id.clone(),
+            role: IssuanceRole::Issuer,
+            state: IssuanceState::Offered,
+            offer: clone_json(&offer),
+            request: None,
+            request_metadata: None,
+            credential_format: None,
+            credential: None,
+            credential_id: None,
+        })?;
+        let sent = clone_json(&offer);
+        self.cred_offers.insert(thread_id.clone(), offer);
+        Ok((thread_id, sent))
+    }
+
+    // Takes the holder's request for the offer of `thread_id`. The same request can be given again, so an issue
+    // that failed after it can be retried with the message it came in
+    pub fn receive_request(
+        &mut self,
+        store: &SessionStore,
+        thread_id: &str,
+        cred_request: &CredentialRequest,
+    ) -> Result<(), TestError> {
+        let mut session = store.load(IssuanceRole::Issuer, thread_id)?;
+        if session.state == IssuanceState::Requested
+            && session
+                .request
+                .as_ref()
+                .is_some_and(|request| same_json(request, cred_request))
+        {
+            return Ok(());
+        }
+        session.expect_state(IssuanceState::Offered)?;
+
+        let offer_cred_def_id = &session.offer.cred_def_id.0;
+        if request_field(cred_request, "cred_def_id").as_ref() != Some(offer_cred_def_id) {
+            return Err(TestError(format!(
+                "Request in session {thread_id} does not target the offered cred def {offer_cred_def_id}"
+            )));
+        }
+        if request_field(cred_request, "entropy") != Some(offer_entropy(&session.offer)?) {
+            return Err(TestError(format!(
+                "Request in session {thread_id} was not made for its offer"
+            )));
+        }
+
+        session.state = IssuanceState::Requested;
+        session.request = Some(clone_json(cred_request));
+        store.save(&session)
+    }
+
+    pub fn issue_requested_credential(
+        &mut self,
+        store: &SessionStore,
+        thread_id: &str,
+        format: &CredentialFormat,
+        cred_values: CredentialValues,
+        rev_reg_def_id: Option<&str>,
+        revocation_status_list: Option<&RevocationStatusList>,
+        credential_rev_index: Option<u32>,
+        version: Option<VerifiableCredentialSpecVersion>,
+    ) -> Result<Credentials, TestError> {
+        let mut session = store.load(IssuanceRole::Issuer, thread_id)?;
+        session.expect_state(IssuanceState::Requested)?;
+        let cred_request = session
+            .request
+            .as_ref()
+            .ok_or_else(|| TestError(format!("Session {thread_id} has no request")))?;
+
+        // The session only moves on once the credential exists, a failed attempt can be retried from `Requested`
+        let credential = self.create_credential(
+            format,
+            &session.offer.cred_def_id.0,
+            &session.offer,
+            cred_request,
+            cred_values,
+            rev_reg_def_id,
+            revocation_status_list,
+            credential_rev_index,
+            version,
+        )?;
+
+        let (credential_format, credential_value) = credential_json(&credential);
+        session.state = IssuanceState::Issued;
+        session.credential_format = Some(credential_format);
+        session.credential = Some(credential_value);
+        store.save(&session)?;
+        self.cred_offers.remove(thread_id);
+
+        Ok(credential)
+    }
+
+    // Reloads offers that are still waiting for a request or for their credential
+    pub fn resume_sessions(&mut self, store: &SessionStore) -> Result<(), TestError> {
+        for session in store.load_all(IssuanceRole::Issuer)? {
+            if matches!(session.state, IssuanceState::Offered | IssuanceState::Requested) {
+                self.cred_offers.insert(session.thread_id, session.offer);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProverWallet {
+    pub fn receive_offer(
+        &mut self,
+        store: &SessionStore,
+        ledger: &Ledger,
+        thread_id: &str,
+        offer: CredentialOffer,
+    ) -> Result<(), TestError> {
+        check_thread_id(thread_id)?;
+        if self.cred_offers.contains_key(thread_id) || store.load(IssuanceRole::Holder, thread_id).is_ok() {
+            return Err(TestError(format!("Session {thread_id} already exists")));
+        }
+        self.validate_offer(ledger, &offer)
+            .map_err(|e| TestError(format!("Rejected offer in session {thread_id}: {e}")))?;
+        store.save(&IssuanceSession {
+            thread_id: thread_id.to_string(),
+            role: IssuanceRole::Holder,
+            state: IssuanceState::Offered,
+            offer: clone_json(&offer),
+            request: None,
+            request_metadata: None,
+            credential_format: None,
+            credential: None,
+            credential_id: None,
+        })?;
+        self.cred_offers.insert(thread_id.to_string(), offer);
+        Ok(())
+    }
+
+    pub fn request_credential(
+        &mut self,
+        store: &SessionStore,
+        ledger: &Ledger,
+        thread_id: &str,
+        link_secret_id: Option<&str>,
+    ) -> Result<CredentialRequest, TestError> {
+        let mut session = store.load(IssuanceRole::Holder, thread_id)?;
+        session.expect_state(IssuanceState::Offered)?;
+
+        let cred_def = ledger
+            .cred_defs
+            .get(&session.offer.cred_def_id)
+            .ok_or_else(|| TestError(format!("Offered cred def {} not found", session.offer.cred_def_id.0)))?;
+        let (request, metadata) = self.create_credential_request(cred_def, &session.offer, link_secret_id)?;
+
+        session.state = IssuanceState::Requested;
+        session.request = Some(clone_json(&request));
+        session.request_metadata = Some(clone_json(&metadata));
+        store.save(&session)?;
+
+        let sent = clone_json(&request);
+        self.cred_offers.remove(thread_id);
+        self.cred_reqs.insert(thread_id.to_string(), (request, metadata));
+        Ok(sent)
+    }
+
+    pub fn receive_credential(
+        &mut self,
+        store: &SessionStore,
+        ledger: &Ledger,
+        thread_id: &str,
+        credential_id: &str,
+        credential: &mut Credentials,
+    ) -> Result<(), TestError> {
+        let mut session = store.load(IssuanceRole::Holder, thread_id)?;
+        session.expect_state(IssuanceState::Requested)?;
+
+        let metadata = session
+            .request_metadata
+            .as_ref()
+            .ok_or_else(|| TestError(format!("Session {thread_id} has no request")))?;
+        // The credential names the offered cred def and schema. It carries no nonce, storing it checks its
+        // signature against the nonce of this session's request, which was made for the offer's nonce
+        self.validate_credential(ledger, &session.offer, credential)
+            .map_err(|e| TestError(format!("Rejected credential in session {thread_id}: {e}")))?;
+
+        let cred_def = ledger
+            .cred_defs
+            .get(&session.offer.cred_def_id)
+            .ok_or_else(|| TestError(format!("Cred def {} not found", session.offer.cred_def_id.0)))?;
+        let rev_reg_id = match credential {
+            Credentials::Legacy(credential) => credential.rev_reg_id(),
+            Credentials::W3C(credential) => credential.rev_reg_id(),
+        };
+        let rev_reg_def = rev_reg_id
+            .map(|id| {
+                ledger
+                    .rev_reg_defs
+                    .get(&id)
+                    .ok_or_else(|| TestError(format!("RevRegDef {} not found", id.0)))
+            })
+            .transpose()?;
+
+        // The session stays `Requested` until the wallet holds the credential, so a failed store can be retried
+        let commitment_secret = match rev_reg_def {
+            Some(_) => Some(self.commitment_for(&metadata.link_secret_name, thread_id)?.0),
+            None => None,
+        };
+        self.store_credential(credential_id, credential, metadata, cred_def, rev_reg_def)?;
+        if let Some(commitment_secret) = commitment_secret {
+            self.midnight_secrets
+                .insert(credential_id.to_string(), commitment_secret);
+        }
+
+        let (credential_format, credential_value) = credential_json(credential);
+        session.credential_format = Some(credential_format);
+        session.credential = Some(credential_value);
+        session.state = IssuanceState::Stored;
+        session.credential_id = Some(credential_id.to_string());
+        store.save(&session)?;
+        self.cred_reqs.remove(thread_id);
+        Ok(())
+    }
+
+    // Reloads offers and requests of sessions interrupted before the credential was stored
+    pub fn resume_sessions(&mut self, store: &SessionStore) -> Result<(), TestError> {
+        for session in store.load_all(IssuanceRole::Holder)? {
+            match (session.state, session.request, session.request_metadata) {
+                (IssuanceState::Offered, _, _) => {
+                    self.cred_offers.insert(session.thread_id, session.offer);
+                }
+                (IssuanceState::Requested | IssuanceState::Issued, Some(request), Some(metadata)) => {
+                    self.cred_reqs.insert(session.thread_id, (request, metadata));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{gvt, sessions};
+    use crate::utils::{CredentialFixture, DEFAULT_LINK_SECRET};
+
+    struct Exchange {
+        store: SessionStore,
+        ledger: Ledger,
+        issuer: IssuerWallet,
+        holder: ProverWallet,
+        fixture: CredentialFixture,
+        thread_id: String,
+        request: CredentialRequest,
+    }
+
+    // A GVT exchange that got as far as the holder's request
+    fn requested() -> Exchange {
+        let fixture = gvt();
+        let store = sessions();
+        let mut ledger = Ledger::default();
+        let mut issuer = IssuerWallet::default();
+        let mut holder = ProverWallet::default();
+
+        let (schema, _) = issuer.create_schema(&mut ledger, &fixture).unwrap();
+        issuer.create_cred_def(&mut ledger, &fixture, &schema, false).unwrap();
+        let (thread_id, offer) = issuer
+            .offer_credential(&store, &fixture.schema.id, &fixture.cred_def.id)
+            .unwrap();
+        holder.receive_offer(&store, &ledger, &thread_id, offer).unwrap();
+        let request = holder.request_credential(&store, &ledger, &thread_id, None).unwrap();
+        Exchange {
+            store,
+            ledger,
+            issuer,
+            holder,
+            fixture,
+            thread_id,
+            request,
+        }
+    }
+
+    impl Exchange {
+        fn issue(&mut self) -> Result<Credentials, TestError> {
+            self.issuer
+                .receive_request(&self.store, &self.thread_id, &self.request)?;
+            let values = self.fixture.credential_values_for(&self.fixture.values)?;
+            self.issuer.issue_requested_credential(
+                &self.store,
+                &self.thread_id,
+                &CredentialFormat::Legacy,
+                values.into(),
+                None,
+                None,
+                None,
+                None,
+            )
+        }
+
+        fn state(&self, role: IssuanceRole) -> IssuanceState {
+            self.store.load(role, &self.thread_id).unwrap().state
+        }
+    }
+
+    #[test]
+    fn failed_issue_can_be_retried() {
+        let mut exchange = requested();
+        let cred_def_id = exchange.fixture.cred_def.id.clone();
+        let cred_def = exchange.issuer.cred_defs.remove(&cred_def_id).unwrap();
+
+        assert!(exchange.issue().is_err());
+        assert_eq!(exchange.state(IssuanceRole::Issuer), IssuanceState::Requested);
+
+        exchange.issuer.cred_defs.insert(cred_def_id, cred_def);
+        exchange.issue().unwrap();
+        assert_eq!(exchange.state(IssuanceRole::Issuer), IssuanceState::Issued);
+        assert!(exchange.issue().is_err());
+    }
+
+    #[test]
+    fn requests_are_bound_to_their_offer() {
+        let mut exchange = requested();
+        let (other_thread, other_offer) = exchange
+            .issuer
+            .offer_credential(
+                &exchange.store,
+                &exchange.fixture.schema.id,
+                &exchange.fixture.cred_def.id,
+            )
+            .unwrap();
+        exchange
+            .holder
+            .receive_offer(&exchange.store, &exchange.ledger, &other_thread, other_offer)
+            .unwrap();
+        let other_request = exchange
+            .holder
+            .request_credential(&exchange.store, &exchange.ledger, &other_thread, None)
+            .unwrap();
+
+        assert!(
+            exchange
+                .issuer
+                .receive_request(&exchange.store, &exchange.thread_id, &other_request)
+                .is_err()
+        );
+        assert_eq!(exchange.state(IssuanceRole::Issuer), IssuanceState::Offered);
+
+        let request = clone_json(&exchange.request);
+        exchange
+            .issuer
+            .receive_request(&exchange.store, &exchange.thread_id, &request)
+            .unwrap();
+        assert_eq!(exchange.state(IssuanceRole::Issuer), IssuanceState::Requested);
+        // Given again for a retry, another request is still refused
+        exchange
+            .issuer
+            .receive_request(&exchange.store, &exchange.thread_id, &request)
+            .unwrap();
+        assert!(
+            exchange
+                .issuer
+                .receive_request(&exchange.store, &exchange.thread_id, &other_request)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn failed_store_can_be_retried() {
+        let mut exchange = requested();
+        let mut credential = exchange.issue().unwrap();
+        let link_secret = exchange.holder.link_secrets.remove(DEFAULT_LINK_SECRET).unwrap();
+
+        let receive = |exchange: &mut Exchange, credential: &mut Credentials| {
+            exchange.holder.receive_credential(
+                &exchange.store,
+                &exchange.ledger,
+                &exchange.thread_id,
+                "GVT",
+                credential,
+            )
+        };
+        assert!(receive(&mut exchange, &mut credential).is_err());
+        assert!(exchange.holder.credentials.is_empty());
+        assert_eq!(exchange.state(IssuanceRole::Holder), IssuanceState::Requested);
+
+        exchange.holder.resume_sessions(&exchange.store).unwrap();
+        exchange
+            .holder
+            .link_secrets
+            .insert(DEFAULT_LINK_SECRET.to_string(), link_secret);
+        receive(&mut exchange, &mut credential).unwrap();
+        assert!(exchange.holder.credentials.contains_key("GVT"));
+        assert_eq!(exchange.state(IssuanceRole::Holder), IssuanceState::Stored);
+    }
+
+    #[test]
+    fn only_generated_thread_ids_reach_the_filesystem() {
+        let store = sessions();
+        check_thread_id(&generate_thread_id()).unwrap();
+        for thread_id in [
+            "",
+            "../../etc/passwd",
+            "0123456789abcdef0123456789abcde/",
+            "0123456789ABCDEF0123456789ABCDEF",
+            "0123456789abcdef0123456789abcdef0",
+        ] {
+            assert!(check_thread_id(thread_id).is_err(), "{thread_id}");
+            assert!(store.load(IssuanceRole::Holder, thread_id).is_err());
+        }
+
+        std::fs::write(store.dir.join("holder-notes.json"), "{}").unwrap();
+        assert!(store.load_all(IssuanceRole::Holder).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::{HashMap, linked_list};
+use std::collections::{BTreeMap, HashMap, linked_list};
+use std::fmt;
 
 use anoncreds::data_types::cred_def::{CredentialDefinition, CredentialDefinitionId};
 use anoncreds::data_types::rev_reg_def::RevocationRegistryDefinitionId;
@@ -9,66 +10,185 @@ use anoncreds::types::{
     CredentialRequestMetadata, CredentialRevocationState, LinkSecret, RevocationRegistryDefinition,
     RevocationRegistryDefinitionPrivate, RevocationStatusList,
 };
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+use super::midnight::{Bytes32, MidnightRevReg};
+use super::nonce::NonceStore;
+use super::policy::TrustPolicy;
+use super::vault::{link_secrets_serde, to_hex};
+
+// Private keys and link secrets are never written to logs
+const REDACTED: &str = "<redacted>";
+
+#[derive(Serialize, Deserialize)]
 pub struct StoredCredDef {
     pub public: CredentialDefinition,
     pub private: CredentialDefinitionPrivate,
     pub key_proof: CredentialKeyCorrectnessProof,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct StoredRevDef {
     pub public: RevocationRegistryDefinition,
     pub private: RevocationRegistryDefinitionPrivate,
 }
 
-#[derive(Debug, Default)]
-pub struct Ledger<'a> {
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ledger {
     pub cred_defs: HashMap<CredentialDefinitionId, CredentialDefinition>,
     pub schemas: HashMap<SchemaId, Schema>,
     pub rev_reg_defs: HashMap<RevocationRegistryDefinitionId, RevocationRegistryDefinition>,
-    pub revocation_list: HashMap<&'a str, HashMap<u64, RevocationStatusList>>,
+    // rev_reg_def_id: timestamp: RevocationStatusList
+    pub revocation_list: HashMap<RevocationRegistryDefinitionId, BTreeMap<u64, RevocationStatusList>>,
+    // Midnight revocation registry contracts, deployed per revocation registry
+    pub midnight_rev_regs: HashMap<RevocationRegistryDefinitionId, MidnightRevReg>,
 }
 
 // A struct for keeping all issuer-related objects together
-#[derive(Debug, Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct IssuerWallet {
     // cred_def_id: StoredRevDef
     pub cred_defs: HashMap<String, StoredCredDef>,
     // revocation_reg_id: StoredRevDef
     pub rev_defs: HashMap<String, StoredRevDef>,
+    // revocation_reg_id: Midnight contract secret key
+    pub midnight_keys: HashMap<String, Bytes32>,
+    // thread_id: offers of issuance sessions still waiting for a request
+    #[serde(skip)]
+    pub cred_offers: HashMap<String, CredentialOffer>,
 }
 
 // A struct for keeping all issuer-related objects together
-#[derive(Debug)]
-pub struct ProverWallet<'a> {
-    pub entropy: &'static str,
-    pub link_secret_id: &'static str,
+#[derive(Serialize, Deserialize)]
+pub struct ProverWallet {
+    pub entropy: String,
+    // Link secret used when a request does not name one
+    pub default_link_secret: String,
+    // link_secret_id: LinkSecret
+    #[serde(with = "link_secrets_serde")]
+    pub link_secrets: HashMap<String, LinkSecret>,
     pub credentials: HashMap<String, Credential>,
     pub w3c_credentials: HashMap<String, W3CCredential>,
+    // credential_id: link_secret_id the credential is bound to
+    pub credential_link_secrets: HashMap<String, String>,
+    // credential_id: revocation state of the credential's own index and the status list it was built from
     pub rev_states: HashMap<String, (Option<CredentialRevocationState>, Option<u64>)>,
-    pub link_secret: LinkSecret,
-    pub cred_offers: HashMap<&'a str, CredentialOffer>,
-    pub cred_reqs: Vec<(CredentialRequest, CredentialRequestMetadata)>,
+    // credential_id: secret behind the credential's leaf in a Midnight registry
+    #[serde(default)]
+    pub midnight_secrets: HashMap<String, Bytes32>,
+    // thread_id: offer and request of issuance sessions still waiting for a credential
+    #[serde(skip)]
+    pub cred_offers: HashMap<String, CredentialOffer>,
+    #[serde(skip)]
+    pub cred_reqs: HashMap<String, (CredentialRequest, CredentialRequestMetadata)>,
 }
 
-impl<'a> Default for ProverWallet<'a> {
+impl Default for ProverWallet {
     fn default() -> Self {
         let link_secret = LinkSecret::new().expect("Error creating prover link secret");
         Self {
-            entropy: "entropy",
-            link_secret_id: "default",
+            entropy: generate_entropy(),
+            default_link_secret: DEFAULT_LINK_SECRET.to_string(),
+            link_secrets: HashMap::from([(DEFAULT_LINK_SECRET.to_string(), link_secret)]),
             credentials: HashMap::new(),
+            credential_link_secrets: HashMap::new(),
             rev_states: HashMap::new(),
-            link_secret,
+            midnight_secrets: HashMap::new(),
             cred_offers: HashMap::new(),
-            cred_reqs: vec![],
+            cred_reqs: HashMap::new(),
             w3c_credentials: HashMap::new(),
         }
     }
 }
 
+pub const DEFAULT_LINK_SECRET: &str = "default";
+
+// Entropy mixed into credential requests, unique per wallet
+fn generate_entropy() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+impl fmt::Debug for StoredCredDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredCredDef")
+            .field("public", &self.public)
+            .field("private", &REDACTED)
+            .field("key_proof", &self.key_proof)
+            .finish()
+    }
+}
+
+impl fmt::Debug for StoredRevDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredRevDef")
+            .field("public", &self.public)
+            .field("private", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for IssuerWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IssuerWallet")
+            .field("cred_defs", &self.cred_defs)
+            .field("rev_defs", &self.rev_defs)
+            .field(
+                "midnight_keys",
+                &self.midnight_keys.keys().map(|id| (id, REDACTED)).collect::<Vec<_>>(),
+            )
+            .field("cred_offers", &self.cred_offers)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ProverWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProverWallet")
+            .field("entropy", &REDACTED)
+            .field("default_link_secret", &self.default_link_secret)
+            .field(
+                "link_secrets",
+                &self.link_secrets.keys().map(|id| (id, REDACTED)).collect::<Vec<_>>(),
+            )
+            .field("credentials", &self.credentials)
+            .field("w3c_credentials", &self.w3c_credentials)
+            .field("credential_link_secrets", &self.credential_link_secrets)
+            .field("rev_states", &self.rev_states)
+            .field(
+                "midnight_secrets",
+                &self
+                    .midnight_secrets
+                    .keys()
+                    .map(|id| (id, REDACTED))
+                    .collect::<Vec<_>>(),
+            )
+            .field("cred_offers", &self.cred_offers)
+            .field("cred_reqs", &self.cred_reqs.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
 // A struct for keeping all verifier-related objects together
-#[derive(Debug, Default)]
-pub struct VerifierWallet {}
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VerifierWallet {
+    pub policy: TrustPolicy,
+    pub nonces: NonceStore,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_secrets() {
+        let mut wallet = ProverWallet::default();
+        wallet.midnight_secrets.insert("GVT".to_string(), [7u8; 32]);
+        let debug = format!("{wallet:?}");
+        assert!(debug.contains(REDACTED));
+        assert!(!debug.contains(&wallet.entropy));
+        assert!(!debug.contains("[7, 7"));
+    }
+}
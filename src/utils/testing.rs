@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use anoncreds::types::PresentationRequest;
+use serde_json::Value;
+
+use super::fixtures::{CredentialFixture, FIXTURES_DIR, load_fixtures};
+use super::mock::{CredentialFormat, PresentationFormat, Presentations};
+use super::request::RequestSpec;
+use super::selection::HolderChoices;
+use super::session::{SessionStore, generate_thread_id};
+use super::storage::{IssuerWallet, Ledger, ProverWallet, VerifierWallet};
+
+pub(crate) const NOW: u64 = 100;
+
+// Wallets after a GVT credential went through the whole exchange, for tests that need objects only anoncreds
+// can create
+pub(crate) struct Issued {
+    pub ledger: Ledger,
+    pub issuer: IssuerWallet,
+    pub holder: ProverWallet,
+    pub verifier: VerifierWallet,
+    pub fixture: CredentialFixture,
+}
+
+pub(crate) fn fixture(name: &str) -> CredentialFixture {
+    load_fixtures(Path::new(FIXTURES_DIR)).unwrap().remove(name).unwrap()
+}
+
+pub(crate) fn gvt() -> CredentialFixture {
+    fixture("GVT")
+}
+
+pub(crate) fn sessions() -> SessionStore {
+    SessionStore::new(
+        std::env::temp_dir()
+            .join("anoncreds-unit-tests")
+            .join(generate_thread_id()),
+    )
+    .unwrap()
+}
+
+// The GVT sample credential, held as `GVT` in the given format
+pub(crate) fn issued(format: CredentialFormat) -> Issued {
+    issued_with(format, false)
+}
+
+pub(crate) fn issued_with(format: CredentialFormat, revocable: bool) -> Issued {
+    let mut issued = Issued {
+        ledger: Ledger::default(),
+        issuer: IssuerWallet::default(),
+        holder: ProverWallet::default(),
+        verifier: VerifierWallet::default(),
+        fixture: gvt(),
+    };
+    issued.issue(&issued.fixture.clone(), format, revocable);
+    issued
+}
+
+impl Issued {
+    // Publishes `fixture` and issues its sample credential, held under the fixture's name. When revocable, the
+    // credential has index 1 of the fixture's registry, whose status list is published at `NOW`
+    pub fn issue(&mut self, fixture: &CredentialFixture, format: CredentialFormat, revocable: bool) {
+        let sessions = sessions();
+        let (schema, _) = self.issuer.create_schema(&mut self.ledger, fixture).unwrap();
+        let (cred_def, _) = self
+            .issuer
+            .create_cred_def(&mut self.ledger, fixture, &schema, revocable)
+            .unwrap();
+        let revocation = revocable.then(|| {
+            let (rev_reg_id, _, status_list) = self
+                .issuer
+                .create_revocation_registry(&mut self.ledger, fixture, &cred_def, Some(NOW), true)
+                .unwrap();
+            (rev_reg_id, status_list)
+        });
+        let (thread_id, offer) = self
+            .issuer
+            .offer_credential(&sessions, &fixture.schema.id, &fixture.cred_def.id)
+            .unwrap();
+        self.holder
+            .receive_offer(&sessions, &self.ledger, &thread_id, offer)
+            .unwrap();
+        let request = self
+            .holder
+            .request_credential(&sessions, &self.ledger, &thread_id, None)
+            .unwrap();
+        let values = fixture.credential_values_for(&fixture.values).unwrap();
+        self.issuer.receive_request(&sessions, &thread_id, &request).unwrap();
+        let mut credential = self
+            .issuer
+            .issue_requested_credential(
+                &sessions,
+                &thread_id,
+                &format,
+                values.into(),
+                revocation.as_ref().map(|(rev_reg_id, _)| rev_reg_id.as_str()),
+                revocation.as_ref().map(|(_, status_list)| status_list),
+                revocation.as_ref().map(|_| 1),
+                None,
+            )
+            .unwrap();
+        self.holder
+            .receive_credential(&sessions, &self.ledger, &thread_id, &fixture.name, &mut credential)
+            .unwrap();
+    }
+
+    // The verifier's request built from a spec in its JSON form, and the holder's presentation answering it
+    pub fn present(&mut self, spec: Value, format: &PresentationFormat) -> (PresentationRequest, Presentations) {
+        let spec: RequestSpec = serde_json::from_value(spec).unwrap();
+        let pres_request = spec.build(&mut self.verifier, NOW).unwrap();
+        let presentation = self
+            .holder
+            .present(&self.ledger, format, &pres_request, &HolderChoices::default())
+            .unwrap();
+        (pres_request, presentation)
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use anoncreds::data_types::cred_def::{CredentialDefinition, CredentialDefinitionId};
+use anoncreds::data_types::schema::SchemaId;
+use anoncreds::prover;
+use anoncreds::types::{Credential, CredentialOffer, LinkSecret};
+
+use super::encoding::{AttributeDefinition, AttributeType, encode_attributes, encoded_value};
+use super::mock::{Credentials, TestError};
+use super::query::{HeldCredential, attr_common_view};
+use super::storage::{Ledger, ProverWallet};
+
+// Why a holder refused an offer or a received credential
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HolderValidationError {
+    UnknownCredDef(String),
+    UnknownSchema(String),
+    SchemaMismatch {
+        cred_def_id: String,
+        expected: String,
+        found: String,
+    },
+    InvalidKeyCorrectnessProof {
+        cred_def_id: String,
+        reason: String,
+    },
+    OfferMismatch {
+        field: &'static str,
+        offered: String,
+        received: String,
+    },
+    AttributeMismatch {
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    InvalidValues(String),
+    ValueMismatch {
+        attribute: String,
+        expected: String,
+        found: String,
+    },
+    UnknownRevocationRegistry(String),
+    RevocationRegistryMismatch {
+        rev_reg_id: String,
+        expected: String,
+        found: String,
+    },
+    RevocationNotSupported(String),
+}
+
+impl fmt::Display for HolderValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HolderValidationError::UnknownCredDef(id) => write!(f, "cred def {id} is not published on the ledger"),
+            HolderValidationError::UnknownSchema(id) => write!(f, "schema {id} is not published on the ledger"),
+            HolderValidationError::SchemaMismatch {
+                cred_def_id,
+                expected,
+                found,
+            } => write!(f, "cred def {cred_def_id} belongs to schema {expected}, not {found}"),
+            HolderValidationError::InvalidKeyCorrectnessProof { cred_def_id, reason } => {
+                write!(
+                    f,
+                    "key correctness proof does not match cred def {cred_def_id}: {reason}"
+                )
+            }
+            HolderValidationError::OfferMismatch {
+                field,
+                offered,
+                received,
+            } => write!(f, "{field} {received} differs from the offered {offered}"),
+            HolderValidationError::AttributeMismatch { missing, unexpected } => write!(
+                f,
+                "attributes do not match the schema (missing: [{}], unexpected: [{}])",
+                missing.join(", "),
+                unexpected.join(", ")
+            ),
+            HolderValidationError::InvalidValues(reason) => write!(f, "invalid attribute values: {reason}"),
+            HolderValidationError::ValueMismatch {
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "attribute {attribute} is encoded as {found}, but its raw value encodes to {expected}"
+            ),
+            HolderValidationError::UnknownRevocationRegistry(id) => {
+                write!(f, "revocation registry {id} is not published on the ledger")
+            }
+            HolderValidationError::RevocationRegistryMismatch {
+                rev_reg_id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "revocation registry {rev_reg_id} belongs to cred def {found}, not {expected}"
+            ),
+            HolderValidationError::RevocationNotSupported(id) => {
+                write!(f, "cred def {id} does not support revocation")
+            }
+        }
+    }
+}
+
+impl From<HolderValidationError> for TestError {
+    fn from(error: HolderValidationError) -> Self {
+        TestError(error.to_string())
+    }
+}
+
+// The ledger cred def an offer or credential refers to, checked against its schema
+fn published_cred_def<'a>(
+    ledger: &'a Ledger,
+    cred_def_id: &CredentialDefinitionId,
+    schema_id: &SchemaId,
+) -> Result<&'a CredentialDefinition, HolderValidationError> {
+    let cred_def = ledger
+        .cred_defs
+        .get(cred_def_id)
+        .ok_or_else(|| HolderValidationError::UnknownCredDef(cred_def_id.0.clone()))?;
+    if &cred_def.schema_id != schema_id {
+        return Err(HolderValidationError::SchemaMismatch {
+            cred_def_id: cred_def_id.0.clone(),
+            expected: cred_def.schema_id.0.clone(),
+            found: schema_id.0.clone(),
+        });
+    }
+    if !ledger.schemas.contains_key(schema_id) {
+        return Err(HolderValidationError::UnknownSchema(schema_id.0.clone()));
+    }
+    Ok(cred_def)
+}
+
+fn supports_revocation(cred_def: &CredentialDefinition) -> bool {
+    serde_json::to_value(&cred_def.value)
+        .map(|value| !value["revocation"].is_null())
+        .unwrap_or(false)
+}
+
+impl ProverWallet {
+    pub fn validate_offer(&self, ledger: &Ledger, offer: &CredentialOffer) -> Result<(), HolderValidationError> {
+        let cred_def = published_cred_def(ledger, &offer.cred_def_id, &offer.schema_id)?;
+
+        // anoncreds verifies the key correctness proof while blinding the link secret,
+        // so a request built with a throwaway link secret checks the proof without binding anything
+        let link_secret = LinkSecret::new().map_err(|e| HolderValidationError::InvalidKeyCorrectnessProof {
+            cred_def_id: offer.cred_def_id.0.clone(),
+            reason: e.to_string(),
+        })?;
+        prover::create_credential_request(Some(&self.entropy), None, cred_def, &link_secret, "validation", offer)
+            .map_err(|e| HolderValidationError::InvalidKeyCorrectnessProof {
+                cred_def_id: offer.cred_def_id.0.clone(),
+                reason: e.to_string(),
+            })?;
+        Ok(())
+    }
+
+    pub fn validate_credential(
+        &self,
+        ledger: &Ledger,
+        offer: &CredentialOffer,
+        credential: &Credentials,
+    ) -> Result<(), HolderValidationError> {
+        match credential {
+            Credentials::Legacy(credential) => {
+                validate_held_credential(ledger, offer, credential)?;
+                validate_encoded_values(ledger, credential)
+            }
+            Credentials::W3C(credential) => validate_held_credential(ledger, offer, credential),
+        }
+    }
+}
+
+fn validate_held_credential<T: HeldCredential>(
+    ledger: &Ledger,
+    offer: &CredentialOffer,
+    credential: &T,
+) -> Result<(), HolderValidationError> {
+    let cred_def_id = credential.cred_def_id();
+    if cred_def_id != offer.cred_def_id {
+        return Err(HolderValidationError::OfferMismatch {
+            field: "cred def",
+            offered: offer.cred_def_id.0.clone(),
+            received: cred_def_id.0,
+        });
+    }
+    let schema_id = credential.schema_id();
+    if schema_id != offer.schema_id {
+        return Err(HolderValidationError::OfferMismatch {
+            field: "schema",
+            offered: offer.schema_id.0.clone(),
+            received: schema_id.0,
+        });
+    }
+    let cred_def = published_cred_def(ledger, &cred_def_id, &schema_id)?;
+
+    // Every schema attribute has to be present, and nothing else
+    let schema = &ledger.schemas[&schema_id];
+    let expected: BTreeSet<String> = schema.attr_names.0.iter().map(|name| attr_common_view(name)).collect();
+    let found: BTreeSet<String> = credential.raw_values().into_keys().collect();
+    if expected != found {
+        return Err(HolderValidationError::AttributeMismatch {
+            missing: expected.difference(&found).cloned().collect(),
+            unexpected: found.difference(&expected).cloned().collect(),
+        });
+    }
+
+    if let Some(rev_reg_id) = credential.rev_reg_id() {
+        if !supports_revocation(cred_def) {
+            return Err(HolderValidationError::RevocationNotSupported(cred_def_id.0));
+        }
+        let rev_reg_def = ledger
+            .rev_reg_defs
+            .get(&rev_reg_id)
+            .ok_or_else(|| HolderValidationError::UnknownRevocationRegistry(rev_reg_id.0.clone()))?;
+        if rev_reg_def.cred_def_id != cred_def_id {
+            return Err(HolderValidationError::RevocationRegistryMismatch {
+                rev_reg_id: rev_reg_id.0,
+                expected: cred_def_id.0,
+                found: rev_reg_def.cred_def_id.0.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Legacy credentials carry each value twice, and proofs are made over the encoded one. Attribute types are not on
+// the ledger, so raw values are taken as issued and only have to encode the way anoncreds encodes them
+fn validate_encoded_values(ledger: &Ledger, credential: &Credential) -> Result<(), HolderValidationError> {
+    let definitions: Vec<AttributeDefinition> = ledger.schemas[&credential.schema_id]
+        .attr_names
+        .0
+        .iter()
+        .map(|name| AttributeDefinition {
+            name: name.clone(),
+            attr_type: AttributeType::String,
+        })
+        .collect();
+    let raw: BTreeMap<String, String> = credential
+        .values
+        .0
+        .iter()
+        .map(|(name, value)| (name.clone(), value.raw.clone()))
+        .collect();
+    let mismatch = |attribute: &str, expected: String| HolderValidationError::ValueMismatch {
+        attribute: attribute.to_string(),
+        expected,
+        found: credential.values.0[attribute].encoded.clone(),
+    };
+
+    // Names were checked against the schema already, so this only fails on one given twice in different cases
+    let canonical = encode_attributes(&definitions, &raw).map_err(|e| HolderValidationError::InvalidValues(e.0))?;
+    for (name, value) in canonical {
+        let attribute = credential
+            .values
+            .0
+            .keys()
+            .find(|attribute| attr_common_view(attribute) == attr_common_view(&name))
+            .expect("attribute names were checked against the schema");
+        let expected = encoded_value(&value).map_err(|e| HolderValidationError::InvalidValues(e.0))?;
+        if credential.values.0[attribute].encoded != expected {
+            return Err(mismatch(attribute, expected));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anoncreds::types::AttributeValues;
+
+    use super::*;
+    use crate::utils::CredentialFormat;
+    use crate::utils::testing::{Issued, issued, sessions};
+
+    // The GVT credential taken out of the wallet, and a fresh offer for its cred def
+    fn held(issued: &mut Issued) -> (Credential, CredentialOffer) {
+        let credential = issued.holder.credentials.remove("GVT").unwrap();
+        let (_, offer) = issued
+            .issuer
+            .offer_credential(&sessions(), &issued.fixture.schema.id, &issued.fixture.cred_def.id)
+            .unwrap();
+        (credential, offer)
+    }
+
+    fn validate(issued: &Issued, offer: &CredentialOffer, credential: Credential) -> Result<(), HolderValidationError> {
+        issued
+            .holder
+            .validate_credential(&issued.ledger, offer, &Credentials::Legacy(credential))
+    }
+
+    #[test]
+    fn issued_credentials_are_valid() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let (credential, offer) = held(&mut issued);
+        validate(&issued, &offer, credential).unwrap();
+    }
+
+    #[test]
+    fn missing_attributes_are_rejected() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let (mut credential, offer) = held(&mut issued);
+        credential.values.0.remove("age");
+        assert_eq!(
+            validate(&issued, &offer, credential),
+            Err(HolderValidationError::AttributeMismatch {
+                missing: vec!["age".to_string()],
+                unexpected: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn extra_attributes_are_rejected() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let (mut credential, offer) = held(&mut issued);
+        credential.values.0.insert(
+            "nickname".to_string(),
+            AttributeValues {
+                raw: "Al".to_string(),
+                encoded: "1".to_string(),
+            },
+        );
+        assert_eq!(
+            validate(&issued, &offer, credential),
+            Err(HolderValidationError::AttributeMismatch {
+                missing: vec![],
+                unexpected: vec!["nickname".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn raw_values_have_to_match_their_encoding() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let (mut credential, offer) = held(&mut issued);
+        let age = credential.values.0.get_mut("age").unwrap();
+        let encoded = age.encoded.clone();
+        age.raw = "99".to_string();
+        assert_eq!(
+            validate(&issued, &offer, credential),
+            Err(HolderValidationError::ValueMismatch {
+                attribute: "age".to_string(),
+                expected: "99".to_string(),
+                found: encoded,
+            })
+        );
+    }
+
+    #[test]
+    fn encoded_values_have_to_match_their_raw_value() {
+        let mut issued = issued(CredentialFormat::Legacy);
+        let (mut credential, offer) = held(&mut issued);
+        credential.values.0.get_mut("name").unwrap().encoded = "12345".to_string();
+        assert!(matches!(
+            validate(&issued, &offer, credential),
+            Err(HolderValidationError::ValueMismatch { attribute, .. }) if attribute == "name"
+        ));
+    }
+}
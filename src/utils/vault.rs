@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anoncreds::types::LinkSecret;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+
+use super::mock::TestError;
+use super::storage::{IssuerWallet, ProverWallet};
+
+const VAULT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+// Argon2id parameters, memory cost in KiB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+// On-disk form of an encrypted wallet. Everything except the ciphertext is authenticated as associated data
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedVault {
+    pub version: u32,
+    pub kdf: KdfParams,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+impl SealedVault {
+    fn associated_data(&self) -> Vec<u8> {
+        format!(
+            "{}:{}:{}:{}:{}",
+            self.version, self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost, self.salt
+        )
+        .into_bytes()
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], kdf: &KdfParams) -> Result<Zeroizing<[u8; KEY_LEN]>, TestError> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+        .map_err(|e| TestError(format!("Invalid KDF parameters: {e}")))?;
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| TestError(format!("Error deriving wallet key: {e}")))?;
+    Ok(key)
+}
+
+pub fn seal<T: Serialize>(passphrase: &str, value: &T, kdf: KdfParams) -> Result<SealedVault, TestError> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut vault = SealedVault {
+        version: VAULT_VERSION,
+        kdf,
+        salt: to_hex(&salt),
+        nonce: to_hex(&nonce),
+        ciphertext: String::new(),
+    };
+
+    let key = derive_key(passphrase, &salt, &kdf)?;
+    let plaintext = Zeroizing::new(serde_json::to_vec(value).map_err(|e| TestError(e.to_string()))?);
+    let aad = vault.associated_data();
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| TestError("Error encrypting wallet".to_string()))?;
+    vault.ciphertext = to_hex(&ciphertext);
+    Ok(vault)
+}
+
+pub fn unseal<T: DeserializeOwned>(passphrase: &str, vault: &SealedVault) -> Result<T, TestError> {
+    if vault.version != VAULT_VERSION {
+        return Err(TestError(format!("Unsupported wallet version {}", vault.version)));
+    }
+    let salt = from_hex(&vault.salt)?;
+    let nonce = from_hex(&vault.nonce)?;
+    if nonce.len() != NONCE_LEN {
+        return Err(TestError("Corrupted wallet nonce".to_string()));
+    }
+    let ciphertext = from_hex(&vault.ciphertext)?;
+
+    let key = derive_key(passphrase, &salt, &vault.kdf)?;
+    let aad = vault.associated_data();
+    let plaintext = Zeroizing::new(
+        XChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| TestError("Wrong passphrase or corrupted wallet".to_string()))?,
+    );
+    serde_json::from_slice(&plaintext).map_err(|e| TestError(format!("Corrupted wallet contents: {e}")))
+}
+
+pub fn write_sealed<T: Serialize>(path: &Path, passphrase: &str, value: &T) -> Result<(), TestError> {
+    let vault = seal(passphrase, value, KdfParams::default())?;
+    let json = serde_json::to_string_pretty(&vault).map_err(|e| TestError(e.to_string()))?;
+    fs::write(path, json).map_err(|e| TestError(format!("Error writing wallet {}: {e}", path.display())))
+}
+
+pub fn read_sealed<T: DeserializeOwned>(path: &Path, passphrase: &str) -> Result<T, TestError> {
+    let json =
+        fs::read_to_string(path).map_err(|e| TestError(format!("Error reading wallet {}: {e}", path.display())))?;
+    let vault: SealedVault =
+        serde_json::from_str(&json).map_err(|e| TestError(format!("Corrupted wallet {}: {e}", path.display())))?;
+    unseal(passphrase, &vault)
+}
+
+impl IssuerWallet {
+    pub fn save_encrypted(&self, path: &Path, passphrase: &str) -> Result<(), TestError> {
+        write_sealed(path, passphrase, self)
+    }
+
+    pub fn load_encrypted(path: &Path, passphrase: &str) -> Result<Self, TestError> {
+        read_sealed(path, passphrase)
+    }
+}
+
+impl ProverWallet {
+    pub fn save_encrypted(&self, path: &Path, passphrase: &str) -> Result<(), TestError> {
+        write_sealed(path, passphrase, self)
+    }
+
+    pub fn load_encrypted(path: &Path, passphrase: &str) -> Result<Self, TestError> {
+        read_sealed(path, passphrase)
+    }
+}
+
+// `LinkSecret` has no serde support, it round-trips through its decimal string form
+pub mod link_secrets_serde {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        link_secrets: &HashMap<String, LinkSecret>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut values = BTreeMap::new();
+        for (id, link_secret) in link_secrets.iter() {
+            let value: String = link_secret
+                .try_clone()
+                .and_then(|link_secret| link_secret.try_into())
+                .map_err(serde::ser::Error::custom)?;
+            values.insert(id, Zeroizing::new(value));
+        }
+        values.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, LinkSecret>, D::Error> {
+        let values = BTreeMap::<String, Zeroizing<String>>::deserialize(deserializer)?;
+        values
+            .into_iter()
+            .map(|(id, value)| {
+                let link_secret = LinkSecret::try_from(value.as_str()).map_err(serde::de::Error::custom)?;
+                Ok((id, link_secret))
+            })
+            .collect()
+    }
+}
+
+// Link secrets exported from one holder wallet, to be imported on another device
+#[derive(Serialize, Deserialize)]
+struct LinkSecretBundle {
+    #[serde(with = "link_secrets_serde")]
+    link_secrets: HashMap<String, LinkSecret>,
+}
+
+impl ProverWallet {
+    // Every link secret is exported when `ids` is empty
+    pub fn export_link_secrets(&self, passphrase: &str, ids: &[&str]) -> Result<SealedVault, TestError> {
+        let mut link_secrets = HashMap::new();
+        for (id, link_secret) in self.link_secrets.iter() {
+            if ids.is_empty() || ids.contains(&id.as_str()) {
+                let link_secret = link_secret.try_clone().map_err(|e| TestError(e.to_string()))?;
+                link_secrets.insert(id.clone(), link_secret);
+            }
+        }
+        if let Some(missing) = ids.iter().find(|id| !self.link_secrets.contains_key(**id)) {
+            return Err(TestError(format!("Link secret `{missing}` not found")));
+        }
+        seal(passphrase, &LinkSecretBundle { link_secrets }, KdfParams::default())
+    }
+
+    // Returns the ids of the imported link secrets. Ids already held with the same value are skipped. A different
+    // link secret under the same id is only replaced while nothing is bound to it, like the random default link
+    // secret of a freshly created wallet that a backup is restored into
+    pub fn import_link_secrets(&mut self, passphrase: &str, bundle: &SealedVault) -> Result<Vec<String>, TestError> {
+        let bundle: LinkSecretBundle = unseal(passphrase, bundle)?;
+        let mut imported = vec![];
+        for (id, link_secret) in bundle.link_secrets.iter() {
+            match self.link_secrets.get(id) {
+                Some(held) if same_link_secret(held, link_secret)? => {}
+                Some(_) if self.link_secret_in_use(id) => {
+                    return Err(TestError(format!(
+                        "A different link secret `{id}` is already held and bound to credentials"
+                    )));
+                }
+                _ => imported.push(id.clone()),
+            }
+        }
+
+        for (id, link_secret) in bundle.link_secrets {
+            if imported.contains(&id) {
+                self.link_secrets.insert(id, link_secret);
+            }
+        }
+        imported.sort();
+        Ok(imported)
+    }
+
+    // Held credentials and pending credential requests are bound to their link secret
+    fn link_secret_in_use(&self, id: &str) -> bool {
+        self.credential_link_secrets.values().any(|bound| bound == id)
+            || self
+                .cred_reqs
+                .values()
+                .any(|(_, metadata)| metadata.link_secret_name == id)
+    }
+}
+
+fn same_link_secret(a: &LinkSecret, b: &LinkSecret) -> Result<bool, TestError> {
+    let to_string = |link_secret: &LinkSecret| -> Result<Zeroizing<String>, TestError> {
+        let value: String = link_secret
+            .try_clone()
+            .and_then(|link_secret| link_secret.try_into())
+            .map_err(|e| TestError(e.to_string()))?;
+        Ok(Zeroizing::new(value))
+    };
+    Ok(to_string(a)? == to_string(b)?)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(value: &str) -> Result<Vec<u8>, TestError> {
+    if !value.is_ascii() || value.len() & 1 == 1 {
+        return Err(TestError(format!("Invalid hex string `{value}`")));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| TestError(format!("Invalid hex string `{value}`")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DEFAULT_LINK_SECRET;
+
+    fn same_default(a: &ProverWallet, b: &ProverWallet) -> bool {
+        same_link_secret(
+            a.link_secret(DEFAULT_LINK_SECRET).unwrap(),
+            b.link_secret(DEFAULT_LINK_SECRET).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sealed_values_need_the_passphrase_and_an_untouched_header() {
+        let vault = seal("correct", &vec![1, 2, 3], KdfParams::default()).unwrap();
+        assert_eq!(unseal::<Vec<u8>>("correct", &vault).unwrap(), vec![1, 2, 3]);
+        assert!(unseal::<Vec<u8>>("wrong", &vault).is_err());
+
+        let mut tampered = vault;
+        tampered.kdf.t_cost += 1;
+        assert!(unseal::<Vec<u8>>("correct", &tampered).is_err());
+    }
+
+    #[test]
+    fn backup_restores_into_a_fresh_wallet() {
+        let wallet = ProverWallet::default();
+        let bundle = wallet.export_link_secrets("backup", &[]).unwrap();
+
+        let mut restored = ProverWallet::default();
+        assert!(!same_default(&wallet, &restored));
+        assert_eq!(
+            restored.import_link_secrets("backup", &bundle).unwrap(),
+            vec![DEFAULT_LINK_SECRET.to_string()]
+        );
+        assert!(same_default(&wallet, &restored));
+        // Importing the same bundle again changes nothing
+        assert!(restored.import_link_secrets("backup", &bundle).unwrap().is_empty());
+    }
+
+    #[test]
+    fn import_keeps_link_secrets_credentials_are_bound_to() {
+        let wallet = ProverWallet::default();
+        let bundle = wallet.export_link_secrets("backup", &[DEFAULT_LINK_SECRET]).unwrap();
+
+        let mut other = ProverWallet::default();
+        other
+            .credential_link_secrets
+            .insert("GVT".to_string(), DEFAULT_LINK_SECRET.to_string());
+        assert!(other.import_link_secrets("backup", &bundle).is_err());
+        assert!(!same_default(&wallet, &other));
+    }
+
+    #[test]
+    fn export_names_missing_link_secrets() {
+        let wallet = ProverWallet::default();
+        let error = wallet.export_link_secrets("backup", &["unknown"]).unwrap_err();
+        assert!(error.0.contains("unknown"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anoncreds::types::{CredentialOffer, CredentialRequest};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::midnight::Bytes32;
+use super::mock::{Credentials, TestError};
+use super::session::SessionStore;
+use super::storage::{IssuerWallet, Ledger, ProverWallet, VerifierWallet};
+
+const LEDGER_FILE: &str = "ledger.json";
+const ISSUER_FILE: &str = "issuer.json";
+const HOLDER_FILE: &str = "holder.json";
+const VERIFIER_FILE: &str = "verifier.json";
+
+// Sent by the issuer, answered with a `RequestMessage`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfferMessage {
+    pub thread_id: String,
+    pub offer: CredentialOffer,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestMessage {
+    pub thread_id: String,
+    pub request: CredentialRequest,
+    // Registered in the Midnight registry when the credential is revocable there
+    pub commitment: Bytes32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialMessage {
+    pub thread_id: String,
+    pub credential: Credentials,
+}
+
+// The state one party keeps between command invocations: its wallets and sessions in `wallet_dir`,
+// and the ledger in `ledger_dir`, which is shared by everyone
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    pub wallet_dir: PathBuf,
+    pub ledger_dir: PathBuf,
+    // Only the issuer and holder wallets need it
+    passphrase: Option<String>,
+}
+
+impl Workspace {
+    pub fn new(
+        wallet_dir: impl Into<PathBuf>,
+        ledger_dir: impl Into<PathBuf>,
+        passphrase: Option<&str>,
+    ) -> Result<Self, TestError> {
+        let workspace = Self {
+            wallet_dir: wallet_dir.into(),
+            ledger_dir: ledger_dir.into(),
+            passphrase: passphrase.map(str::to_string),
+        };
+        for dir in [&workspace.wallet_dir, &workspace.ledger_dir] {
+            fs::create_dir_all(dir).map_err(|e| TestError(format!("Error creating {}: {e}", dir.display())))?;
+        }
+        Ok(workspace)
+    }
+
+    // Wallets are never sealed with a passphrase nobody chose
+    pub fn passphrase(&self) -> Result<&str, TestError> {
+        self.passphrase
+            .as_deref()
+            .filter(|passphrase| !passphrase.is_empty())
+            .ok_or_else(|| TestError("Issuer and holder wallets need a passphrase, set WALLET_PASSPHRASE".to_string()))
+    }
+
+    pub fn sessions(&self) -> Result<SessionStore, TestError> {
+        SessionStore::new(self.wallet_dir.join("sessions"))
+    }
+
+    // An empty ledger until something is published
+    pub fn load_ledger(&self) -> Result<Ledger, TestError> {
+        read_json_or_default(&self.ledger_dir.join(LEDGER_FILE))
+    }
+
+    pub fn save_ledger(&self, ledger: &Ledger) -> Result<(), TestError> {
+        write_json(&self.ledger_dir.join(LEDGER_FILE), ledger)
+    }
+
+    // A new wallet on first use, with the offers of unfinished sessions restored. The passphrase is checked even
+    // then, so a command fails before it changes anything rather than when saving the wallet
+    pub fn load_issuer(&self) -> Result<IssuerWallet, TestError> {
+        let passphrase = self.passphrase()?;
+        let path = self.wallet_dir.join(ISSUER_FILE);
+        let mut wallet = if path.exists() {
+            IssuerWallet::load_encrypted(&path, passphrase)?
+        } else {
+            IssuerWallet::default()
+        };
+        wallet.resume_sessions(&self.sessions()?)?;
+        Ok(wallet)
+    }
+
+    pub fn save_issuer(&self, wallet: &IssuerWallet) -> Result<(), TestError> {
+        wallet.save_encrypted(&self.wallet_dir.join(ISSUER_FILE), self.passphrase()?)
+    }
+
+    // A new wallet with a fresh link secret on first use, with unfinished sessions restored
+    pub fn load_holder(&self) -> Result<ProverWallet, TestError> {
+        let passphrase = self.passphrase()?;
+        let path = self.wallet_dir.join(HOLDER_FILE);
+        let mut wallet = if path.exists() {
+            ProverWallet::load_encrypted(&path, passphrase)?
+        } else {
+            ProverWallet::default()
+        };
+        wallet.resume_sessions(&self.sessions()?)?;
+        Ok(wallet)
+    }
+
+    pub fn save_holder(&self, wallet: &ProverWallet) -> Result<(), TestError> {
+        wallet.save_encrypted(&self.wallet_dir.join(HOLDER_FILE), self.passphrase()?)
+    }
+
+    // Holds no secrets, only the policy and the nonces handed out
+    pub fn load_verifier(&self) -> Result<VerifierWallet, TestError> {
+        read_json_or_default(&self.wallet_dir.join(VERIFIER_FILE))
+    }
+
+    pub fn save_verifier(&self, wallet: &VerifierWallet) -> Result<(), TestError> {
+        write_json(&self.wallet_dir.join(VERIFIER_FILE), wallet)
+    }
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, TestError> {
+    let json = fs::read_to_string(path).map_err(|e| TestError(format!("Error reading {}: {e}", path.display())))?;
+    serde_json::from_str(&json).map_err(|e| TestError(format!("Invalid {}: {e}", path.display())))
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), TestError> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| TestError(e.to_string()))?;
+    fs::write(path, json).map_err(|e| TestError(format!("Error writing {}: {e}", path.display())))
+}
+
+fn read_json_or_default<T: DeserializeOwned + Default>(path: &Path) -> Result<T, TestError> {
+    if path.exists() {
+        read_json(path)
+    } else {
+        Ok(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::generate_thread_id;
+
+    fn workspace(passphrase: Option<&str>) -> Workspace {
+        let dir = std::env::temp_dir()
+            .join("anoncreds-unit-tests")
+            .join(generate_thread_id());
+        Workspace::new(dir.join("wallet"), dir.join("ledger"), passphrase).unwrap()
+    }
+
+    #[test]
+    fn issuer_and_holder_wallets_need_a_passphrase() {
+        for passphrase in [None, Some("")] {
+            let workspace = workspace(passphrase);
+            assert!(workspace.load_issuer().is_err());
+            assert!(workspace.load_holder().is_err());
+            assert!(workspace.save_issuer(&IssuerWallet::default()).is_err());
+            // The verifier and the ledger hold no secrets
+            workspace.load_verifier().unwrap();
+            workspace.load_ledger().unwrap();
+        }
+    }
+
+    #[test]
+    fn wallets_open_with_the_passphrase_they_were_saved_with() {
+        let workspace = workspace(Some("first"));
+        workspace.save_issuer(&workspace.load_issuer().unwrap()).unwrap();
+        workspace.load_issuer().unwrap();
+
+        let other = Workspace::new(&workspace.wallet_dir, &workspace.ledger_dir, Some("second")).unwrap();
+        assert!(other.load_issuer().is_err());
+    }
+}